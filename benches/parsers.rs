@@ -3,7 +3,7 @@
 extern crate rust_kb;
 extern crate test;
 
-use rust_kb::kb::knowledge_base::KnowledgeBase;
+use rust_kb::KnowledgeBase;
 use test::Bencher;
 
 // ~6s
@@ -0,0 +1,318 @@
+use super::{Fact, KnowledgeBase, Rule};
+use production::Production;
+use unify::Substitution;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// A rule or production that matched facts, waiting on the agenda to be fired
+///
+/// Firing a rule activation either infers the rule's conclusion, when the matched premise was the
+/// last one, or infers a new rule made of the remaining premises. Firing a production activation
+/// carries out the production's effects for one complete match of its premises.
+#[derive(Debug, Clone)]
+pub struct Activation {
+    // The facts that were matched. A rule activation matches a single fact.
+    pub(crate) facts: Vec<Rc<Fact>>,
+    pub(crate) source: Source,
+    // When the activation was put on the agenda
+    pub(crate) time: u64,
+    // When each matched fact was added, most recent first, and when the fact matching the first
+    // premise was added. Both are worked out once, when the activation is created.
+    recency: Vec<u64>,
+    first: u64,
+}
+
+// What fires when an activation is selected
+#[derive(Debug, Clone)]
+pub(crate) enum Source {
+    Rule(Rc<Rule>),
+    Production(Rc<Production>, Substitution),
+}
+
+impl Activation {
+    pub(crate) fn new(
+        kb: &KnowledgeBase,
+        facts: Vec<Rc<Fact>>,
+        source: Source,
+        time: u64,
+    ) -> Activation {
+        let mut recency = matched_times(kb, &facts, &source);
+        let first = recency.last().cloned().unwrap_or(0);
+        recency.sort_by(|x, y| y.cmp(x));
+        Activation {
+            facts,
+            source,
+            time,
+            recency,
+            first,
+        }
+    }
+
+    // Orders the activation among those of the agenda under a strategy, with the activation that
+    // fires first having the greatest key. Vectors compare element by element and a vector that
+    // runs out first is the lesser, which is exactly the order of `Lex`.
+    fn key(&self, strategy: Strategy) -> (i32, u64, Vec<u64>, i64) {
+        let time = self.time as i64;
+        match strategy {
+            Strategy::Depth => (self.salience(), 0, vec![], time),
+            Strategy::Breadth => (self.salience(), 0, vec![], -time),
+            Strategy::Lex => (self.salience(), 0, self.recency.clone(), time),
+            Strategy::Mea => (self.salience(), self.first, self.recency.clone(), time),
+        }
+    }
+
+    /// Returns the facts that were matched, in the order of the premises they matched
+    pub fn facts(&self) -> Vec<&Fact> {
+        self.facts.iter().map(|f| &**f).collect()
+    }
+
+    /// Returns the rule that will fire, if this is a rule activation
+    pub fn rule(&self) -> Option<&Rule> {
+        match &self.source {
+            Source::Rule(rule) => Some(rule),
+            Source::Production(..) => None,
+        }
+    }
+
+    /// Returns the production that will fire, if this is a production activation
+    pub fn production(&self) -> Option<&Production> {
+        match &self.source {
+            Source::Rule(_) => None,
+            Source::Production(production, _) => Some(production),
+        }
+    }
+
+    /// Returns the salience of the rule or production that will fire
+    pub fn salience(&self) -> i32 {
+        match &self.source {
+            Source::Rule(rule) => rule.salience,
+            Source::Production(production, _) => production.salience,
+        }
+    }
+}
+
+/// The order in which activations of equal salience are fired
+///
+/// Activations of rules with a higher salience always fire first. These strategies decide
+/// between the rest, as in CLIPS.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Strategy {
+    /// The newest activation fires first
+    #[default]
+    Depth,
+    /// The oldest activation fires first
+    Breadth,
+    /// The activation matching the most recent facts fires first, comparing the facts that
+    /// matched each premise from most to least recent. When one list of facts runs out first,
+    /// the activation that matched more premises fires first.
+    Lex,
+    /// The activation whose first premise matched the most recent fact fires first, falling back
+    /// to `Lex` on ties
+    Mea,
+}
+
+/// Bounds on the work a single run of the agenda may do
+///
+/// A limit that is `None` is not enforced, which is the default for all three. The depth of an
+/// asserted fact is 0, and a fact derived by a rule is one deeper than the deepest fact that
+/// matched the rule's premises.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::InferenceLimits;
+/// use std::time::Duration;
+///
+/// let limits = InferenceLimits {
+///     max_depth: Some(20),
+///     time_budget: Some(Duration::from_millis(5)),
+///     ..Default::default()
+/// };
+/// assert_eq!(limits.max_derived, None);
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct InferenceLimits {
+    /// The deepest a derived fact may be
+    pub max_depth: Option<usize>,
+    /// How many facts may be added, by rules or by productions
+    pub max_derived: Option<usize>,
+    /// How long the run may take
+    pub time_budget: Option<Duration>,
+}
+
+/// How much of the agenda one call to `KnowledgeBase::run_inference` may process
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Budget {
+    /// Fire at most this many activations
+    Steps(usize),
+    /// Stop firing activations once this much time has passed
+    Time(Duration),
+}
+
+impl Budget {
+    // Checks whether another activation may fire after `fired` fired since the run started
+    pub(crate) fn allows(&self, fired: usize, started: Instant) -> bool {
+        match *self {
+            Budget::Steps(steps) => fired < steps,
+            Budget::Time(time) => started.elapsed() < time,
+        }
+    }
+}
+
+/// What one call to `KnowledgeBase::run_inference` did
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Progress {
+    /// How many activations fired
+    pub fired: usize,
+    /// Whether the agenda is empty, so nothing more can be inferred until statements change
+    pub fixpoint: bool,
+}
+
+// Prefix of the errors returned when a limit is exceeded
+pub(crate) const LIMIT_EXCEEDED: &str = "Inference limit exceeded";
+
+impl InferenceLimits {
+    // Checks the work done since a run started, which had already added `added_before` facts
+    pub(crate) fn check(
+        &self,
+        kb: &KnowledgeBase,
+        started: Instant,
+        added_before: usize,
+    ) -> Result<(), String> {
+        if let Some(max_depth) = self.max_depth {
+            if kb.deepest > max_depth {
+                return Err(format!(
+                    "{}: a fact was derived at depth {} but the limit is {}",
+                    LIMIT_EXCEEDED, kb.deepest, max_depth
+                ));
+            }
+        }
+        if let Some(max_derived) = self.max_derived {
+            if kb.inserted - added_before > max_derived {
+                return Err(format!(
+                    "{}: more than {} facts were added",
+                    LIMIT_EXCEEDED, max_derived
+                ));
+            }
+        }
+        if let Some(time_budget) = self.time_budget {
+            if started.elapsed() > time_budget {
+                return Err(format!(
+                    "{}: inference took longer than {:?}",
+                    LIMIT_EXCEEDED, time_budget
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// The activations waiting to fire, kept in a heap ordered by the conflict resolution strategy
+#[derive(Debug, Default)]
+pub(crate) struct Agenda {
+    heap: BinaryHeap<Queued>,
+    strategy: Strategy,
+}
+
+impl Agenda {
+    pub(crate) fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    // Changes the strategy, ordering the waiting activations again
+    pub(crate) fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+        let activations = std::mem::take(&mut self.heap).into_vec();
+        for queued in activations {
+            self.push(queued.activation);
+        }
+    }
+
+    pub(crate) fn push(&mut self, activation: Activation) {
+        let key = activation.key(self.strategy);
+        self.heap.push(Queued { key, activation });
+    }
+
+    // Removes the activation that fires next
+    pub(crate) fn pop(&mut self) -> Option<Activation> {
+        self.heap.pop().map(|queued| queued.activation)
+    }
+
+    // Takes the activations that match off the agenda and returns them
+    pub(crate) fn remove<F>(&mut self, mut matches: F) -> Vec<Activation>
+    where
+        F: FnMut(&Activation) -> bool,
+    {
+        let (removed, kept): (Vec<Queued>, Vec<Queued>) = std::mem::take(&mut self.heap)
+            .into_vec()
+            .into_iter()
+            .partition(|queued| matches(&queued.activation));
+        self.heap = BinaryHeap::from(kept);
+        removed.into_iter().map(|queued| queued.activation).collect()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Activation> {
+        self.heap.iter().map(|queued| &queued.activation)
+    }
+
+    // Returns the activations in the order they fire
+    pub(crate) fn sorted(&self) -> Vec<Activation> {
+        let mut queued = self.heap.clone().into_sorted_vec();
+        queued.reverse();
+        queued.into_iter().map(|queued| queued.activation).collect()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.heap.clear();
+    }
+}
+
+// An activation on the agenda with its key under the agenda's strategy
+#[derive(Debug, Clone)]
+struct Queued {
+    key: (i32, u64, Vec<u64>, i64),
+    activation: Activation,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Queued) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Queued) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Queued) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+// Returns when each fact an activation matched was added, from the fact matching its last
+// premise back to the fact that matched the first premise. For a rule activation, the facts that
+// matched the premises of the original rule are found through the rules derived from it. Facts
+// computed by builtins and aggregates are never stored, and count as the oldest facts.
+fn matched_times(kb: &KnowledgeBase, facts: &[Rc<Fact>], source: &Source) -> Vec<u64> {
+    let mut times: Vec<u64> = facts.iter().rev().map(|f| kb.fact_time(f)).collect();
+    if let Source::Rule(ref rule) = *source {
+        let mut rule = rule;
+        while let Some((fact, parent)) = rule.supported_by.first() {
+            times.push(kb.fact_time(fact));
+            rule = parent;
+        }
+    }
+    times
+}
@@ -0,0 +1,137 @@
+use super::{Fact, KnowledgeBase};
+use symbols::Symbol;
+use term::Term;
+use unify::Substitution;
+
+use std::collections::HashMap;
+
+// Names of the aggregate operations
+pub(crate) const AGGREGATES: &[&str] = &["count", "sum", "min", "max", "collect"];
+
+// Returns true if the predicate names an aggregate operation
+pub(crate) fn is_operation(pred: &Symbol) -> bool {
+    AGGREGATES.contains(&&**pred)
+}
+
+// Returns true if the fact is an aggregate goal, such as (count ?n ?e (adjacent ?p ?e))
+//
+// An aggregate goal has an operation as its predicate and three arguments: the result, the
+// template being aggregated, and a pattern written as a compound term.
+pub(crate) fn is_aggregate(goal: &Fact) -> bool {
+    is_operation(&goal.pred)
+        && goal.args.len() == 3
+        && matches!(goal.args[2], Term::Compound(..))
+}
+
+// Returns the pattern an aggregate goal ranges over as a fact
+pub(crate) fn pattern(goal: &Fact) -> Fact {
+    match goal.args[2] {
+        Term::Compound(ref functor, ref args) => Fact::new(functor.clone(), args.clone(), vec![]),
+        _ => unreachable!("aggregate goals always end in a compound pattern"),
+    }
+}
+
+// Returns the variables of the pattern that are not part of the template. Answers are grouped by
+// these variables, and each group produces its own aggregate. Use the anonymous variable ? for
+// pattern arguments that should be aggregated over instead of grouped by.
+fn group_variables(goal: &Fact) -> Vec<Symbol> {
+    let mut template = Vec::new();
+    goal.args[1].collect_variables(&mut template);
+
+    let mut variables = Vec::new();
+    goal.args[2].collect_variables(&mut variables);
+    variables.retain(|var| !template.contains(var));
+    variables
+}
+
+// Returns the variables an answer to an aggregate goal binds: those of the result followed by the
+// grouping variables. Variables only used inside the template are local to the aggregate.
+pub(crate) fn visible_variables(goal: &Fact) -> Vec<Symbol> {
+    let mut variables = Vec::new();
+    goal.args[0].collect_variables(&mut variables);
+    for var in group_variables(goal) {
+        if !variables.contains(&var) {
+            variables.push(var);
+        }
+    }
+    variables
+}
+
+// Evaluates an aggregate goal against the knowledge base and returns one instance of the goal per
+// group, with the result and the grouping variables filled in
+//
+// count counts the answers of the pattern, sum, min and max combine the integer values of the
+// template and ignore anything else, and collect gathers the template values into a sorted list.
+// When every grouping variable is already bound there is exactly one group, so count, sum and
+// collect still answer 0, 0 and [] when nothing matches.
+pub(crate) fn solve(kb: &KnowledgeBase, goal: &Fact) -> Vec<Fact> {
+    let pattern = pattern(goal);
+    let group_vars = group_variables(goal);
+
+    let mut order: Vec<Vec<Term>> = Vec::new();
+    let mut groups: HashMap<Vec<Term>, Vec<Term>> = HashMap::new();
+    if group_vars.is_empty() {
+        order.push(vec![]);
+        groups.insert(vec![], vec![]);
+    }
+
+    for binding in kb.query_iter(&pattern) {
+        let substitution = Substitution::from_map(&binding.into_iter().collect());
+        let key: Vec<Term> = group_vars
+            .iter()
+            .map(|var| substitution.apply_term(&Term::Symbol(var.clone())))
+            .collect();
+
+        if !groups.contains_key(&key) {
+            order.push(key.clone());
+        }
+        groups
+            .entry(key)
+            .or_default()
+            .push(substitution.apply_term(&goal.args[1]));
+    }
+
+    let mut instances = Vec::new();
+    for key in order {
+        let values = &groups[&key];
+        let result = match combine(kb, &goal.pred, values) {
+            Some(result) => result,
+            None => continue,
+        };
+
+        let mut substitution = Substitution::new();
+        for (var, value) in group_vars.iter().zip(key.iter()) {
+            substitution.unify(&Term::Symbol(var.clone()), value);
+        }
+
+        let args = vec![
+            result,
+            substitution.apply_term(&goal.args[1]),
+            substitution.apply_term(&goal.args[2]),
+        ];
+        instances.push(Fact::new(goal.pred.clone(), args, vec![]));
+    }
+    instances
+}
+
+// Applies an aggregate operation to the values of one group
+fn combine(kb: &KnowledgeBase, op: &Symbol, values: &[Term]) -> Option<Term> {
+    let numbers = values.iter().filter_map(|value| match *value {
+        Term::Symbol(ref s) => s.parse::<i64>().ok(),
+        Term::Compound(..) => None,
+    });
+
+    let number = |n: i64| Term::Symbol(kb.symbols.intern(&n.to_string()));
+    match &**op {
+        "count" => Some(number(values.len() as i64)),
+        "sum" => Some(number(numbers.sum())),
+        "min" => numbers.min().map(number),
+        "max" => numbers.max().map(number),
+        "collect" => {
+            let mut items = values.to_vec();
+            items.sort();
+            Some(Term::list(items, None, &kb.symbols))
+        }
+        _ => None,
+    }
+}
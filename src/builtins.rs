@@ -0,0 +1,77 @@
+use super::Fact;
+use symbols::{Symbol, SymbolTable};
+use term::Term;
+
+// Names of the relations the knowledge base computes instead of storing
+const BUILTINS: &[&str] = &["member", "length", "append", "different"];
+
+// Returns true if facts with this predicate are computed by a builtin
+pub(crate) fn is_builtin(pred: &Symbol) -> bool {
+    BUILTINS.contains(&&**pred)
+}
+
+// Evaluates a builtin goal and returns every ground instance of it that holds
+//
+// The goal's variables are not bound here; callers unify each instance with the goal. Builtins
+// need enough of their arguments bound to enumerate finitely many answers, and produce nothing
+// otherwise:
+//
+// * `(member ?x [a b c])` needs the list
+// * `(length [a b c] ?n)` needs the list
+// * `(append ?x ?y [a b])` needs either the joined list or both of the lists being joined
+// * `(different ?x ?y)` needs both terms, and holds when they are not the same
+pub(crate) fn solve(goal: &Fact, symbols: &SymbolTable) -> Vec<Fact> {
+    let args: Vec<&Term> = goal.args.iter().collect();
+    let instance = |args: Vec<Term>| Fact::new(goal.pred.clone(), args, vec![]);
+
+    let instances = match (&*goal.pred, &args[..]) {
+        ("member", &[_, list]) => match list.as_list() {
+            Some(items) => items
+                .into_iter()
+                .map(|item| instance(vec![item.clone(), list.clone()]))
+                .collect(),
+            None => vec![],
+        },
+        ("length", &[list, _]) => match list.as_list() {
+            Some(items) => {
+                let length = Term::Symbol(symbols.intern(&items.len().to_string()));
+                vec![instance(vec![list.clone(), length])]
+            }
+            None => vec![],
+        },
+        ("append", &[front, back, joined]) => match joined.as_list() {
+            Some(items) => (0..items.len() + 1)
+                .map(|split| {
+                    let front = owned_list(&items[..split], symbols);
+                    let back = owned_list(&items[split..], symbols);
+                    instance(vec![front, back, joined.clone()])
+                })
+                .collect(),
+            None => match (front.as_list(), back.as_list()) {
+                (Some(mut front_items), Some(back_items)) => {
+                    front_items.extend(back_items);
+                    let joined = owned_list(&front_items, symbols);
+                    vec![instance(vec![front.clone(), back.clone(), joined])]
+                }
+                _ => vec![],
+            },
+        },
+        ("different", &[a, b]) => {
+            if a != b {
+                vec![instance(vec![a.clone(), b.clone()])]
+            } else {
+                vec![]
+            }
+        }
+        _ => vec![],
+    };
+
+    instances
+        .into_iter()
+        .filter(|fact| !fact.contains_variable())
+        .collect()
+}
+
+fn owned_list(items: &[&Term], symbols: &SymbolTable) -> Term {
+    Term::list(items.iter().map(|&item| item.clone()).collect(), None, symbols)
+}
@@ -0,0 +1,75 @@
+/// How the confidence in a fact follows from the confidences of what supports it
+///
+/// Facts are asserted with a confidence between 0 and 1, and rules with a strength between 0 and
+/// 1; both default to 1. A fact derived by a rule has the rule's strength times the conjunction
+/// of the confidences of the facts that matched its premises. A fact that is asserted or derived
+/// more than once has the disjunction of the confidences of each of those supports.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::{CertaintyModel, Conjunction, Disjunction};
+///
+/// let model = CertaintyModel {
+///     conjunction: Conjunction::Product,
+///     disjunction: Disjunction::NoisyOr,
+/// };
+/// assert_eq!(model.conjunction.combine(0.5, 0.5), 0.25);
+/// assert_eq!(model.disjunction.combine(0.5, 0.5), 0.75);
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct CertaintyModel {
+    /// Combines the confidences of the facts matching the premises of a rule
+    pub conjunction: Conjunction,
+    /// Combines the confidences of the supports of a fact
+    pub disjunction: Disjunction,
+}
+
+/// How the confidences of facts that must all hold are combined
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Conjunction {
+    /// The least confidence
+    #[default]
+    Min,
+    /// The product of the confidences, as if they were independent
+    Product,
+}
+
+/// How the confidences of facts of which one must hold are combined
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Disjunction {
+    /// The greatest confidence
+    #[default]
+    Max,
+    /// One minus the product of the confidences that each fact does not hold
+    NoisyOr,
+}
+
+impl Conjunction {
+    /// Combines two confidences
+    pub fn combine(&self, a: f64, b: f64) -> f64 {
+        match *self {
+            Conjunction::Min => a.min(b),
+            Conjunction::Product => a * b,
+        }
+    }
+}
+
+impl Disjunction {
+    /// Combines two confidences
+    pub fn combine(&self, a: f64, b: f64) -> f64 {
+        match *self {
+            Disjunction::Max => a.max(b),
+            Disjunction::NoisyOr => 1.0 - (1.0 - a) * (1.0 - b),
+        }
+    }
+}
+
+// Checks that a confidence or strength is between 0 and 1
+pub(crate) fn check(confidence: f64) -> Result<(), String> {
+    if (0.0..=1.0).contains(&confidence) {
+        Ok(())
+    } else {
+        Err(format!("Confidence {} should be between 0 and 1", confidence))
+    }
+}
@@ -0,0 +1,91 @@
+use super::{Fact, Rule};
+use agenda::Activation;
+use production::Production;
+use symbols::Symbol;
+use term::Term;
+
+use std::rc::Rc;
+
+// A match of a production that fired, by the production and the facts it matched
+pub(crate) type FiredMatch = (Rc<Production>, Vec<Fact>);
+
+// A change made to the knowledge base by the operation under way
+#[derive(Debug)]
+pub(crate) enum Edit {
+    AddedFact(Rc<Fact>),
+    AddedRule(Rc<Rule>),
+    RemovedFact(Rc<Fact>, Stored),
+    RemovedRule(Rc<Rule>, Stored),
+    // An activation put on the agenda, by the time it was put there
+    Queued(u64),
+    // Activations taken off the agenda without firing
+    Dropped(Vec<Activation>),
+    // An activation taken off the agenda to fire
+    Fired(Activation),
+    // A match of a production recorded as fired
+    Refracted,
+    // A callback a production called, which is only made once the outermost operation succeeds
+    Called(Symbol, Vec<Term>),
+}
+
+// Where a removed statement was stored, and what was kept about it alongside
+#[derive(Debug, Default)]
+pub(crate) struct Stored {
+    // The position among the facts or rules. A fact is followed by its positions in the predicate
+    // index, among the facts of the predicate and then in the list of each argument, and a rule
+    // whose first premise is an aggregate by its position among the rules aggregating over the
+    // same predicate.
+    pub(crate) positions: Vec<usize>,
+    pub(crate) time: Option<u64>,
+    pub(crate) depth: Option<usize>,
+    pub(crate) expiry: Option<i64>,
+    // The confidence of a fact, or the strength of a rule
+    pub(crate) certainty: Option<f64>,
+    // The activations that matched the statement, and the fired matches of productions that used
+    // the fact, by their positions
+    pub(crate) activations: Vec<Activation>,
+    pub(crate) refracted: Vec<(usize, FiredMatch)>,
+}
+
+// The changes made by the operations under way. Operations nest when one calls another, and
+// changes are only recorded while at least one is under way. They are forgotten once the
+// outermost operation succeeds.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    edits: Vec<Edit>,
+    depth: usize,
+}
+
+impl Journal {
+    // Starts an operation, returning the mark to roll back to if it fails
+    pub(crate) fn begin(&mut self) -> usize {
+        self.depth += 1;
+        self.edits.len()
+    }
+
+    pub(crate) fn record(&mut self, edit: Edit) {
+        if self.depth > 0 {
+            self.edits.push(edit);
+        }
+    }
+
+    // Finishes an operation that succeeded, returning the changes to forget if it was the
+    // outermost one
+    pub(crate) fn commit(&mut self) -> Vec<Edit> {
+        self.depth -= 1;
+        if self.depth == 0 {
+            std::mem::take(&mut self.edits)
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Finishes an operation that failed, returning the changes it made from the latest to the
+    // earliest, in the order they are undone
+    pub(crate) fn rollback(&mut self, mark: usize) -> Vec<Edit> {
+        self.depth -= 1;
+        let mut edits = self.edits.split_off(mark);
+        edits.reverse();
+        edits
+    }
+}
@@ -17,15 +17,51 @@
 extern crate nom;
 extern crate weak_table;
 
+mod agenda;
+mod aggregate;
+mod builtins;
+mod certainty;
+mod journal;
 mod parser;
+mod pddl;
+mod plan;
+mod production;
+mod query;
+mod schema;
+mod subscription;
 mod symbols;
+mod temporal;
+mod term;
+mod unify;
 
-use parser::{parse_fact, parse_kb_from_file, parse_rule, ParsedFact, ParsedKnowledgeBase,
+use agenda::{Agenda, Source};
+use journal::{Edit, Journal, Stored};
+use parser::{parse_action, parse_constraint, parse_fact, parse_kb_from_file, parse_production,
+             parse_rule, parse_term, ParsedConstraint, ParsedFact, ParsedKnowledgeBase,
              ParsedRule};
-use symbols::{Symbol, SymbolTable};
+use plan::DEFAULT_PLAN_LIMIT;
+use production::{Callbacks, Effect};
+use query::WatchState;
+use schema::{Schema, Sort};
+use subscription::Subscriptions;
+use symbols::SymbolTable;
+use unify::{Substitution, VariableRenamer};
 
-use std::collections::{HashMap, HashSet};
+pub use agenda::{Activation, Budget, InferenceLimits, Progress, Strategy};
+pub use certainty::{CertaintyModel, Conjunction, Disjunction};
+pub use plan::{unsatisfied_goals, Action, ActionSchema, Heuristic, PlanState, Search};
+pub use production::{Callback, Production};
+pub use query::{QueryIter, QueryResult, QueryRow, QueryWatch, RowChange};
+pub use subscription::{Change, Listener, Subscription};
+pub use symbols::Symbol;
+pub use temporal::Interval;
+pub use term::Term;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
+use std::time::Instant;
 
 /// Defines a fact relationship between two or more arguments
 ///
@@ -46,43 +82,71 @@ use std::rc::Rc;
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Fact {
     pred: Symbol,
-    args: Vec<Symbol>,
+    args: Vec<Term>,
+    valid: Interval,
+    context: Option<Symbol>,
     asserted: bool,
     supported_by: Vec<(Rc<Fact>, Rc<Rule>)>,
 }
 
 impl Fact {
-    fn new(pred: Symbol, args: Vec<Symbol>, supported_by: Vec<(Rc<Fact>, Rc<Rule>)>) -> Fact {
+    fn new(pred: Symbol, args: Vec<Term>, supported_by: Vec<(Rc<Fact>, Rc<Rule>)>) -> Fact {
         let asserted = supported_by.is_empty();
         Fact {
             pred,
             args,
+            valid: Interval::always(),
+            context: None,
             asserted,
             supported_by,
         }
     }
 
+    /// Returns the interval during which the fact is valid
+    pub fn interval(&self) -> Interval {
+        self.valid
+    }
+
+    /// Returns the fact with the interval during which it is valid set to the given one
+    ///
+    /// Facts that only differ in their intervals are different facts.
+    pub fn valid_during(mut self, interval: Interval) -> Fact {
+        self.valid = interval;
+        self
+    }
+
+    /// Returns the name of the context the fact belongs to, or None for the shared context
+    pub fn context(&self) -> Option<&Symbol> {
+        self.context.as_ref()
+    }
+
+    // Returns the fact moved to the given context
+    fn in_context(mut self, context: Option<Symbol>) -> Fact {
+        self.context = context;
+        self
+    }
+
     /// Creates a new fact from the parser output and a given symbol table
-    fn from(pf: &ParsedFact, symbols: &mut SymbolTable) -> Fact {
+    fn from(pf: &ParsedFact, symbols: &SymbolTable) -> Fact {
         let pred = symbols.intern(&pf.pred);
         let mut args = Vec::new();
         for parg in &pf.args {
-            args.push(symbols.intern(&parg));
+            args.push(Term::from(parg, symbols));
         }
 
-        Fact::new(pred, args, vec![])
+        Fact::new(pred, args, vec![]).valid_during(Interval::new(pf.start, pf.end))
     }
 
     /// Creates a fact from a vector of Strings, each representing a token in the fact. A symbol
     /// table must also be provided
-    fn from_raw(raw_fact: &[String], symbols: &mut SymbolTable) -> Fact {
+    fn from_raw(raw_fact: &[String], symbols: &SymbolTable) -> Fact {
         let mut args = Vec::new();
         let mut pred = symbols.intern("");
         for (i, item) in raw_fact.iter().enumerate() {
             if i == 0 {
-                pred = symbols.intern(&item);
+                pred = symbols.intern(item);
             } else {
-                args.push(symbols.intern(&item));
+                args.push(Term::Symbol(symbols.intern(item)));
             }
         }
 
@@ -90,12 +154,21 @@ impl Fact {
     }
 
     fn contains_variable(&self) -> bool {
-        for s in &self.args {
-            if s.is_var() {
-                return true;
-            }
+        self.args.iter().any(|arg| arg.contains_variable())
+    }
+}
+
+impl fmt::Display for Fact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rendered = format!("({}", &*self.pred);
+        for arg in &self.args {
+            rendered.push_str(&format!(" {}", arg));
         }
-        false
+        rendered.push(')');
+        if !self.valid.is_always() {
+            rendered.push_str(&format!(" {}", self.valid));
+        }
+        f.pad(&rendered)
     }
 }
 
@@ -107,6 +180,10 @@ impl Fact {
 /// Use this if you want to impose specialized relationships that are not the default assumption
 /// for rules.
 ///
+/// A rule may conclude several facts, as in `((parent ?x ?y)) -> (ancestor ?x ?y) (related ?x ?y)`.
+/// Each conclusion is supported by the facts that matched the premises, so retracting one of
+/// them retracts all of the conclusions.
+///
 /// A rule can only be created by an instance of a knowledge base. This means that rules cannot be
 /// created on its own, or passed from one knowledge base to another.
 ///
@@ -124,52 +201,131 @@ impl Fact {
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Rule {
     lhs: Vec<Fact>,
-    rhs: Fact,
+    rhs: Vec<Fact>,
+    // For a rule derived from another, the interval during which the facts it matched so far
+    // were all valid
+    valid: Interval,
+    context: Option<Symbol>,
     asserted: bool,
     supported_by: Vec<(Rc<Fact>, Rc<Rule>)>,
+    salience: i32,
 }
 
 impl Rule {
     /// Create a new rule from Facts
-    fn new(lhs: Vec<Fact>, rhs: Fact, supported_by: Vec<(Rc<Fact>, Rc<Rule>)>) -> Rule {
+    fn new(lhs: Vec<Fact>, rhs: Vec<Fact>, supported_by: Vec<(Rc<Fact>, Rc<Rule>)>) -> Rule {
         let asserted = supported_by.is_empty();
         Rule {
             lhs,
             rhs,
+            valid: Interval::always(),
+            context: None,
             asserted,
             supported_by,
+            salience: 0,
         }
     }
 
     // Create a new rule from a parsed object
-    fn from(pr: &ParsedRule, symbols: &mut SymbolTable) -> Rule {
-        let mut lhs = Vec::new();
-
-        for parsed_raw_fact in &pr.lhs {
-            let mut args = Vec::new();
-            let mut pred = symbols.intern("");
-            for (i, item) in parsed_raw_fact.iter().enumerate() {
-                if i == 0 {
-                    pred = symbols.intern(&item);
-                } else {
-                    args.push(symbols.intern(&item));
-                }
-            }
-            lhs.push(Fact::new(pred, args, vec![]));
-        }
+    fn from(pr: &ParsedRule, symbols: &SymbolTable) -> Rule {
+        let lhs = pr.lhs.iter().map(|pf| Fact::from(pf, symbols)).collect();
+        let rhs = pr.rhs.iter().map(|pf| Fact::from(pf, symbols)).collect();
 
-        let mut args = Vec::new();
-        let mut pred = symbols.intern("");
-        for (i, item) in pr.rhs.iter().enumerate() {
-            if i == 0 {
-                pred = symbols.intern(&item);
-            } else {
-                args.push(symbols.intern(&item));
+        Rule::new(lhs, rhs, vec![]).with_salience(pr.salience)
+    }
+
+    /// Returns the salience of the rule
+    ///
+    /// Activations of rules with a higher salience fire before those of rules with a lower
+    /// salience. Rules have a salience of 0 unless they are written with one, as in
+    /// `rule: ((alarm ?x)) -> (flee ?x) salience: 10`.
+    pub fn salience(&self) -> i32 {
+        self.salience
+    }
+
+    /// Returns the rule with its salience set to the given value
+    pub fn with_salience(mut self, salience: i32) -> Rule {
+        self.salience = salience;
+        self
+    }
+
+    /// Returns the name of the context the rule belongs to, or None for the shared context
+    pub fn context(&self) -> Option<&Symbol> {
+        self.context.as_ref()
+    }
+
+    // Returns the rule moved to the given context
+    fn in_context(mut self, context: Option<Symbol>) -> Rule {
+        self.context = context;
+        self
+    }
+
+    // Checks whether two rules only differ in the names of their variables
+    fn is_variant(&self, other: &Rule) -> bool {
+        let own: Vec<&Fact> = self.lhs.iter().chain(self.rhs.iter()).collect();
+        let others: Vec<&Fact> = other.lhs.iter().chain(other.rhs.iter()).collect();
+        self.valid == other.valid
+            && self.context == other.context
+            && unify::is_variant(&own, &others)
+    }
+}
+
+/// Defines an invariant of a knowledge base: a list of facts that must never all hold at once
+///
+/// Once a constraint is added to a knowledge base, any assertion that would make every fact of the
+/// constraint hold for some binding of its variables is undone and reported as an error. Use the
+/// builtin (different ?x ?y) to require that two variables are bound to different values.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::KnowledgeBase;
+///
+/// let mut kb = KnowledgeBase::new();
+/// match kb.create_constraint("constraint: ((location ?x ?a) (location ?x ?b) (different ?a ?b));") {
+///     Ok(constraint) => { /* Here you can use the constraint object */ },
+///     Err(_) => {},
+/// }
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Constraint {
+    body: Vec<Fact>,
+}
+
+impl Constraint {
+    fn new(body: Vec<Fact>) -> Constraint {
+        Constraint { body }
+    }
+
+    // Create a new constraint from a parsed object
+    fn from(pc: &ParsedConstraint, symbols: &SymbolTable) -> Constraint {
+        Constraint::new(pc.body.iter().map(|pf| Fact::from(pf, symbols)).collect())
+    }
+
+    // Describes the values a solution gives the variables of the constraint, e.g. "?x = a"
+    fn describe_bindings(&self, substitution: &Substitution) -> String {
+        let mut variables = Vec::new();
+        for fact in &self.body {
+            for arg in &fact.args {
+                arg.collect_variables(&mut variables);
             }
         }
-        let rhs = Fact::new(pred, args, vec![]);
 
-        Rule::new(lhs, rhs, vec![])
+        variables
+            .into_iter()
+            .map(|var| {
+                let value = substitution.apply_term(&Term::Symbol(var.clone()));
+                format!("{} = {}", &*var, value)
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let body: Vec<String> = self.body.iter().map(|fact| fact.to_string()).collect();
+        f.pad(&format!("({})", body.join(" ")))
     }
 }
 
@@ -212,8 +368,28 @@ impl Statement for Rule {
 // Type alias for an index of one argument column
 type ArgumentHash = HashMap<Symbol, Vec<Rc<Fact>>>;
 
+// Index of every fact sharing one predicate, along with one argument column per argument position
+#[derive(Debug, Default)]
+struct PredicateIndex {
+    facts: Vec<Rc<Fact>>,
+    args: Vec<ArgumentHash>,
+}
+
+// Checks whether two facts agree on every key argument but differ in some other argument
+fn shares_key(positions: &[usize], f1: &Fact, f2: &Fact) -> bool {
+    f1.args.len() == f2.args.len()
+        && positions.iter().all(|&i| f1.args.get(i) == f2.args.get(i))
+        && f1.args != f2.args
+}
+
+// A statement waiting to be removed, together with everything derived from it
+enum Removal {
+    Fact(Rc<Fact>),
+    Rule(Rc<Rule>),
+}
+
 // Type alias for a specific binding from one variable to one argument
-type QueryBinding = Vec<(Symbol, Symbol)>;
+type QueryBinding = Vec<(Symbol, Term)>;
 
 /// A data structure which can take in facts and rules, and respond to logical questions and queries
 ///
@@ -223,9 +399,44 @@ type QueryBinding = Vec<(Symbol, Symbol)>;
 #[derive(Debug)]
 pub struct KnowledgeBase {
     facts: Vec<Rc<Fact>>,
-    facts_map: HashMap<Symbol, Vec<ArgumentHash>>,
+    facts_map: HashMap<Symbol, PredicateIndex>,
     rules: Vec<Rc<Rule>>,
-    symbols: SymbolTable,
+    schemas: HashMap<Symbol, Schema>,
+    constraints: Vec<Constraint>,
+    keys: HashMap<Symbol, Vec<usize>>,
+    actions: Vec<ActionSchema>,
+    productions: Vec<Rc<Production>>,
+    // Matches of productions that already fired, by the facts they matched
+    refracted: Vec<(Rc<Production>, Vec<Fact>)>,
+    callbacks: Callbacks,
+    subscriptions: Subscriptions,
+    agenda: Agenda,
+    // What the operations under way changed, so a failed one can be undone
+    journal: Journal,
+    auto_run: bool,
+    clock: u64,
+    fact_times: HashMap<*const Fact, u64>,
+    fact_depths: HashMap<*const Fact, usize>,
+    rule_depths: HashMap<*const Rule, usize>,
+    // Rules whose first premise is an aggregate, by the predicate the aggregate ranges over
+    aggregate_rules: HashMap<Symbol, Vec<Rc<Rule>>>,
+    // The time set by advance_time, and when facts asserted with a time to live expire
+    now: i64,
+    expiries: HashMap<*const Fact, i64>,
+    // Confidences of asserted facts and strengths of asserted rules, where they are not 1
+    confidences: HashMap<*const Fact, f64>,
+    strengths: HashMap<*const Rule, f64>,
+    certainty: CertaintyModel,
+    // The parent of each named context, where None is the shared context
+    contexts: HashMap<Symbol, Option<Symbol>>,
+    limits: InferenceLimits,
+    // How many states the planner may look at before giving up
+    plan_limit: Option<usize>,
+    // How many facts were ever added, and the deepest fact added during the current run
+    inserted: usize,
+    deepest: usize,
+    symbols: Rc<SymbolTable>,
+    renamer: VariableRenamer,
 }
 
 impl PartialEq for KnowledgeBase {
@@ -234,6 +445,12 @@ impl PartialEq for KnowledgeBase {
     }
 }
 
+impl Default for KnowledgeBase {
+    fn default() -> KnowledgeBase {
+        KnowledgeBase::new()
+    }
+}
+
 impl KnowledgeBase {
     /// Creates a new, empty knowledge base
     ///
@@ -255,48 +472,145 @@ impl KnowledgeBase {
             facts: Vec::new(),
             facts_map: HashMap::new(),
             rules: Vec::new(),
-            symbols: SymbolTable::new(),
+            schemas: HashMap::new(),
+            constraints: Vec::new(),
+            keys: HashMap::new(),
+            actions: Vec::new(),
+            productions: Vec::new(),
+            refracted: Vec::new(),
+            callbacks: Callbacks::default(),
+            subscriptions: Subscriptions::default(),
+            agenda: Agenda::default(),
+            journal: Journal::default(),
+            auto_run: true,
+            clock: 0,
+            fact_times: HashMap::new(),
+            fact_depths: HashMap::new(),
+            rule_depths: HashMap::new(),
+            aggregate_rules: HashMap::new(),
+            now: 0,
+            expiries: HashMap::new(),
+            confidences: HashMap::new(),
+            strengths: HashMap::new(),
+            certainty: CertaintyModel::default(),
+            contexts: HashMap::new(),
+            limits: InferenceLimits::default(),
+            plan_limit: Some(DEFAULT_PLAN_LIMIT),
+            inserted: 0,
+            deepest: 0,
+            symbols: Rc::new(SymbolTable::new()),
+            renamer: VariableRenamer::default(),
         }
     }
 
-    // Creates a new knowledge base with given rules and facts and a symbol table
-    fn new_filled(facts: Vec<Fact>, rules: Vec<Rule>, symbols: SymbolTable) -> KnowledgeBase {
+    // Creates a new knowledge base with given rules and facts and a shared symbol table
+    fn new_filled(
+        facts: Vec<Fact>,
+        rules: Vec<Rule>,
+        symbols: Rc<SymbolTable>,
+    ) -> Result<KnowledgeBase, String> {
         let mut kb = KnowledgeBase {
-            facts: Vec::new(),
-            facts_map: HashMap::new(),
-            rules: Vec::new(),
             symbols,
+            ..KnowledgeBase::new()
+        };
+        kb.fill(facts, rules)?;
+        Ok(kb)
+    }
+
+    // Creates a knowledge base with the same declarations, and asserted facts and rules of the
+    // shared context, as this one.
+    // The symbol table is shared, so facts of the copy can be compared with facts of the original.
+    // Productions and callbacks are left out, so nothing done with the copy has side effects.
+    // Facts asserted with a time to live never expire in the copy, and every statement in it is
+    // certain.
+    fn scratch_copy(&self) -> KnowledgeBase {
+        let mut kb = KnowledgeBase {
+            schemas: self.schemas.clone(),
+            constraints: self.constraints.clone(),
+            keys: self.keys.clone(),
+            actions: self.actions.clone(),
+            limits: self.limits,
+            symbols: self.symbols.clone(),
+            ..KnowledgeBase::new()
         };
 
+        for fact in self.facts.iter().filter(|f| f.asserted && f.context.is_none()) {
+            let copy = Fact::new(fact.pred.clone(), fact.args.clone(), vec![]);
+            let _ = kb.add_statement(copy.valid_during(fact.valid));
+        }
+        for rule in self.rules.iter().filter(|r| r.asserted && r.context.is_none()) {
+            let copy = Rule::new(rule.lhs.clone(), rule.rhs.clone(), vec![]);
+            let _ = kb.add_statement(copy.with_salience(rule.salience));
+        }
+        let _ = kb.run();
+
+        kb
+    }
+
+    // Asserts the given facts and then the given rules
+    fn fill(&mut self, facts: Vec<Fact>, rules: Vec<Rule>) -> Result<(), String> {
         for fact in facts {
-            assert!(kb.assert(fact).is_ok());
+            self.assert(fact)?;
         }
 
         for rule in rules {
-            assert!(kb.assert(rule).is_ok());
+            self.assert(rule)?;
         }
 
-        kb
+        Ok(())
     }
 
     /// Creates a knowledge base from a parsed object from the crate's parser
-    fn from(pkb: ParsedKnowledgeBase) -> KnowledgeBase {
+    fn from(pkb: ParsedKnowledgeBase) -> Result<KnowledgeBase, String> {
         let mut facts = Vec::new();
         let mut rules = Vec::new();
-        let mut symbols = SymbolTable::new();
+        let mut kb = KnowledgeBase::new();
+
+        for parsed_pred in &pkb.preds {
+            let sorts: Vec<&str> = parsed_pred.sorts.iter().map(|s| &s[..]).collect();
+            kb.register_predicate(&parsed_pred.name, &sorts)?;
+        }
+
+        for parsed_key in &pkb.keys {
+            kb.register_key(&parsed_key.pred, &parsed_key.args)?;
+        }
 
         for parsed_fact in &pkb.facts {
-            let f = Fact::from(&parsed_fact, &mut symbols);
+            let f = Fact::from(parsed_fact, &kb.symbols);
             if !f.contains_variable() {
                 facts.push(f);
             }
         }
 
         for parsed_rule in &pkb.rules {
-            rules.push(Rule::from(&parsed_rule, &mut symbols));
+            rules.push(Rule::from(parsed_rule, &kb.symbols));
+        }
+
+        // Members of named sorts have to be known before the facts that use them are checked
+        let sort_names = kb.named_sorts();
+        let (members, others): (Vec<Fact>, Vec<Fact>) = facts
+            .into_iter()
+            .partition(|f| sort_names.contains(&f.pred));
+        kb.fill(members, vec![])?;
+        kb.fill(others, rules)?;
+
+        for parsed_constraint in &pkb.constraints {
+            let constraint = Constraint::from(parsed_constraint, &kb.symbols);
+            kb.add_constraint(constraint)?;
+        }
+
+        for parsed_action in &pkb.actions {
+            let action = ActionSchema::from(parsed_action, &kb.symbols)?;
+            kb.add_action(action)?;
         }
 
-        KnowledgeBase::new_filled(facts, rules, symbols)
+        for parsed_production in &pkb.productions {
+            let production = Production::from(parsed_production, &kb.symbols)?;
+            kb.check_production(&production)?;
+            kb.add_production(production)?;
+        }
+
+        Ok(kb)
     }
 
     /// Attempts to create a knowledge base from a given input file
@@ -312,8 +626,9 @@ impl KnowledgeBase {
     /// ```
     ///
     /// All facts should follow. Each fact should be on its own line and be prefixed by "fact:".
-    /// Then, the fact should be left left parenthesis, the predicate, one or more arguments, and finally
-    /// a right parenthesis
+    /// Then, the fact should be left left parenthesis, the predicate, zero or more arguments, and finally
+    /// a right parenthesis. An argument can itself be a parenthesized compound term, such as
+    /// (location player).
     ///
     /// # Proper fact syntax
     ///
@@ -321,24 +636,46 @@ impl KnowledgeBase {
     /// kb {
     ///     fact: (isa cube box)
     ///     fact: (isa box container)
+    ///     fact: (holds (location player) kitchen)
     ///
     ///     ...
     /// }
     /// ```
     ///
     /// Finally, all rules should follow. Each rule should be on its own line and be prefixed by "rule:".
-    /// Then the rule should have a list of one or more facts, a right arrow (->), and finally a fact
-    /// that can be inferred. These facts should use variables to connect arguments from different
-    /// facts.
+    /// Then the rule should have a list of one or more facts, a right arrow (->), and finally one or
+    /// more facts that can be inferred. These facts should use variables to connect arguments from
+    /// different facts.
+    ///
+    /// Facts may use list arguments and end with a validity interval, as described for
+    /// `create_fact`, and rule premises may use builtins and aggregates, as described for
+    /// `create_rule`.
+    ///
+    /// The other statements are written as for the methods that add them at run time: "pred:"
+    /// for `register_predicate`, "key:" for `register_key`, "constraint:" for `create_constraint`,
+    /// "action:" for `create_action` and "production:" for `create_production`.
+    ///
+    /// A file may include others with `include "other.kb"`, where the path is relative to the
+    /// including file. Included statements are loaded before those of the including file, each
+    /// file is loaded once, and files that include each other fail to load. With
+    /// `include "north.kb" as north`, the predicates that north.kb declares, asserts or concludes
+    /// become north::ally and so on, both in north.kb and in files that refer to them by that name.
+    /// The same goes for the patterns of aggregates and the facts of retract effects, but other
+    /// compound terms are data and keep their names. Other predicates, and those of files
+    /// north.kb includes in turn, are left alone.
     ///
     /// # Proper knowledge base file format
     ///
     /// ``` txt
     /// kb {
+    ///     pred: (inventory symbol list)
+    ///
     ///     fact: (isa cube box)
     ///     fact: (isa box container)
+    ///     fact: (inventory player [sword key])
     ///
     ///     rule: ((inst ?x ?y) (isa ?y ?z)) -> (inst ?x ?z)
+    ///     rule: ((inventory ?p ?items) (member ?i ?items)) -> (carries ?p ?i)
     /// }
     /// ```
     ///
@@ -353,7 +690,42 @@ impl KnowledgeBase {
     /// ```
     pub fn from_file(filename: &str) -> Result<KnowledgeBase, String> {
         let pkb = parse_kb_from_file(filename)?;
-        Ok(KnowledgeBase::from(pkb))
+        KnowledgeBase::from(pkb)
+    }
+
+    /// Attempts to create a knowledge base from a PDDL domain file and a PDDL problem file
+    ///
+    /// The knowledge base holds the domain's predicates and actions and the problem's objects and
+    /// initial facts. The facts of the problem's goal are returned alongside it, ready to be
+    /// passed to `plan`.
+    ///
+    /// Types become unary facts, so an object a of type block gives the facts (block a) and
+    /// (object a), and every predicate is declared with the types of its arguments. The :strips,
+    /// :typing and :equality requirements are supported, with (not (= ?x ?y)) read as
+    /// (different ?x ?y). Any other requirement, and constructs such as negative preconditions,
+    /// disjunctions, quantifiers or conditional effects, make loading fail with an error naming
+    /// what is unsupported.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let (kb, goal) =
+    ///     KnowledgeBase::from_pddl("test/blocks-domain.pddl", "test/blocks-problem.pddl").unwrap();
+    /// let plan = kb.plan(&goal).unwrap();
+    /// ```
+    pub fn from_pddl(
+        domain_file: &str,
+        problem_file: &str,
+    ) -> Result<(KnowledgeBase, Vec<Fact>), String> {
+        let (pkb, parsed_goal) = pddl::parse_pddl_from_files(domain_file, problem_file)?;
+        let kb = KnowledgeBase::from(pkb)?;
+        let goal = parsed_goal
+            .iter()
+            .map(|pf| Fact::from(pf, &kb.symbols))
+            .collect();
+        Ok((kb, goal))
     }
 
     /// Attempts to create a fact from a given string slice.
@@ -361,6 +733,10 @@ impl KnowledgeBase {
     /// If the fact is ill-formatted, the function will return an error. In this context, the
     /// fact must be terminated by a semicolon.
     ///
+    /// Arguments may be compound terms such as (location player), or lists, written [a b c], or
+    /// matched as a head and tail with [?h | ?t]. A fact may end with "from:" and "to:" and a
+    /// number, limiting when it is valid. See `Interval` and `query_at`.
+    ///
     ///  # Example
     ///
     /// ```
@@ -374,13 +750,25 @@ impl KnowledgeBase {
     /// ```
     pub fn create_fact(&mut self, fact: &str) -> Result<Fact, String> {
         let pf = parse_fact(fact.as_bytes())?;
-        Ok(Fact::from(&pf, &mut self.symbols))
+        Ok(Fact::from(&pf, &self.symbols))
     }
 
     /// Attempts to create a rule from a given string slice.
     ///
     /// If the rule is ill-formatted, the function will return an error. In this context, the
-    /// fact must be terminated by a semicolon.
+    /// fact must be terminated by a semicolon. A rule may end with "salience:" and a number; see
+    /// `Rule::salience`.
+    ///
+    /// The builtin relations (member ?x ?list), (length ?list ?n) and (append ?x ?y ?joined) are
+    /// computed rather than stored. They can be queried directly or used as rule premises, as long
+    /// as an earlier premise binds the lists they work on.
+    ///
+    /// Aggregates summarize the answers to a pattern: (count ?n ?e (adjacent ?p ?e)) counts the
+    /// ?e adjacent to each ?p, and sum, min, max and collect combine the values of their second
+    /// argument the same way. Answers are grouped by the pattern's other variables; use the
+    /// anonymous variable ? to aggregate over an argument instead. Facts derived from an aggregate
+    /// are updated whenever facts matching its pattern are asserted or retracted. Asserting a rule
+    /// whose conclusions would feed back into the pattern of one of its aggregates is an error.
     ///
     ///  # Example
     ///
@@ -395,19 +783,17 @@ impl KnowledgeBase {
     /// ```
     pub fn create_rule(&mut self, rule: &str) -> Result<Rule, String> {
         let pr = parse_rule(rule.as_bytes())?;
-        Ok(Rule::from(&pr, &mut self.symbols))
-    }
-
-    #[inline]
-    fn intern_string(&mut self, name: &str) -> Symbol {
-        self.symbols.intern(name)
+        let rule = Rule::from(&pr, &self.symbols);
+        self.check_rule(&rule)?;
+        Ok(rule)
     }
 
-    /// Add a fact or rule to the knowledge base
+    /// Attempts to create a constraint from a given string slice.
     ///
-    /// This function will use inference by forward chaining to add implied facts from given rules.
-    /// An error will be returned if the statement is already present in the knowledge base. Use this
-    /// error to detect logical errors, or duplicate assertions in code.
+    /// A constraint lists facts that must never all hold at once, such as ((on ?x ?x)). The
+    /// builtin (different ?x ?y) holds when its arguments differ. If the constraint is
+    /// ill-formatted, or does not fit the declared predicates, the function will return an error.
+    /// In this context, the constraint must be terminated by a semicolon.
     ///
     ///  # Example
     ///
@@ -415,82 +801,98 @@ impl KnowledgeBase {
     /// use rust_kb::KnowledgeBase;
     ///
     /// let mut kb = KnowledgeBase::new();
-    /// match kb.create_fact("fact: (isa square rectangle);") {
-    ///     Ok(fact) => { kb.assert(fact); },
-    ///     Err(_) => {},
+    /// match kb.create_constraint("constraint: ((on ?x ?x));") {
+    ///     Ok(constraint) => { /* Will execute this branch */ },
+    ///     Err(_) => { /* Will not execute this branch, because of proper format */ },
     /// }
     /// ```
-    pub fn assert<T: Statement>(&mut self, statement: T) -> Result<Rc<Statement>, String> {
-        match statement.to_fact() {
-            Some(fact) => match self.add_fact(fact) {
-                Ok(rc_fact) => {
-                    for rule in &self.rules.clone() {
-                        self.infer(rc_fact.clone(), rule.clone());
-                    }
-                    Ok(rc_fact)
-                }
-                Err(e) => Err(e),
-            },
-            None => {
-                let rule = statement.to_rule().unwrap();
-                match self.add_rule(rule) {
-                    Ok(rc_rule) => {
-                        for fact in &self.facts.clone() {
-                            self.infer(fact.clone(), rc_rule.clone());
-                        }
-                        Ok(rc_rule)
-                    }
-                    Err(e) => Err(e),
-                }
-            }
+    pub fn create_constraint(&mut self, constraint: &str) -> Result<Constraint, String> {
+        let pc = parse_constraint(constraint.as_bytes())?;
+        let constraint = Constraint::from(&pc, &self.symbols);
+        for fact in &constraint.body {
+            self.check_premise(fact)?;
         }
+        Ok(constraint)
     }
 
-    /// Remove a fact or rule from the knowledge base.
-    ///
-    /// This function will remove a specific statement from the knowledge base. In addition, it will
-    /// recursively chain logic to remove other statements that were dependent on the given statement
+    /// Adds a constraint that every later assertion has to respect
     ///
-    /// Statements that still have support from other Fact/Rule pairs will error on retract
+    /// An error is returned if the constraint is already in the knowledge base, or if the
+    /// knowledge base already violates it.
     ///
     ///  # Example
     ///
     /// ```
-    /// use rust_kb::{KnowledgeBase,Fact};
+    /// use rust_kb::KnowledgeBase;
     ///
     /// let mut kb = KnowledgeBase::new();
+    /// if let Ok(constraint) = kb.create_constraint("constraint: ((on ?x ?x));") {
+    ///     kb.add_constraint(constraint).unwrap();
+    /// }
     ///
-    /// // Add to the knowledge base
-    ///
-    /// if let Ok(fact) = kb.create_fact("fact: (isa square rectangle);") {
-    ///     kb.retract(fact);
+    /// if let Ok(fact) = kb.create_fact("fact: (on a a);") {
+    ///     assert!(kb.assert(fact).is_err());
     /// }
     /// ```
-    pub fn retract<T: Statement>(&mut self, statement: T) -> Result<(), String> {
-        match statement.to_fact() {
-            Some(fact) => {
-                if fact.supported_by.is_empty() {
-                    return self.remove_fact(&fact);
-                } else {
-                    return Err(String::from(
-                        "Fact cannot be removed because it's supported",
-                    ));
-                }
+    pub fn add_constraint(&mut self, constraint: Constraint) -> Result<(), String> {
+        let body: Vec<&Fact> = constraint.body.iter().collect();
+        if self.constraints.iter().any(|c| {
+            unify::is_variant(&c.body.iter().collect::<Vec<&Fact>>(), &body)
+        }) {
+            return Err(String::from("constraint already in kb"));
+        }
+
+        self.check_constraint(&constraint)?;
+        self.constraints.push(constraint);
+        Ok(())
+    }
+
+    /// Removes a constraint from the knowledge base
+    ///
+    /// An error is returned if the constraint cannot be found.
+    pub fn remove_constraint(&mut self, constraint: &Constraint) -> Result<(), String> {
+        match self.constraints.iter().position(|c| c == constraint) {
+            Some(position) => {
+                self.constraints.remove(position);
+                Ok(())
             }
-            None => {
-                let rule = statement.to_rule().unwrap();
-                if rule.supported_by.is_empty() {
-                    return self.remove_rule(&rule);
+            None => Err(String::from("constraint does not exist in kb")),
+        }
+    }
+
+    // Checks every constraint of the knowledge base, returning the first violation found
+    fn check_constraints(&self) -> Result<(), String> {
+        for constraint in &self.constraints {
+            self.check_constraint(constraint)?;
+        }
+        Ok(())
+    }
+
+    // Returns an error naming the constraint and the bindings that satisfy it, if any do
+    fn check_constraint(&self, constraint: &Constraint) -> Result<(), String> {
+        match query::first_solution(self, &constraint.body) {
+            Some(solution) => {
+                let bindings = constraint.describe_bindings(&solution);
+                if bindings.is_empty() {
+                    Err(format!("Constraint {} is violated", constraint))
                 } else {
-                    return Err(String::from(
-                        "Rule cannot be removed because it's supported",
-                    ));
+                    Err(format!("Constraint {} is violated by {}", constraint, bindings))
                 }
             }
+            None => Ok(()),
         }
     }
 
-    /// Ask if a specific fact can be proven by the knowledge base
+    /// Declares that some arguments of a predicate determine the rest
+    ///
+    /// Arguments are numbered from 1. With the first argument of location declared as its key,
+    /// asserting (location player hall) retracts (location player kitchen), along with everything
+    /// that was inferred from it. Only asserted facts replace others this way; a fact concluded by
+    /// a rule never retracts an asserted one. Keys can also be declared in a knowledge base file
+    /// with `key: (location 1)`.
+    ///
+    /// Declaring a key again with different arguments, or declaring one that existing facts
+    /// already break, is an error.
     ///
     ///  # Example
     ///
@@ -498,611 +900,4566 @@ impl KnowledgeBase {
     /// use rust_kb::KnowledgeBase;
     ///
     /// let mut kb = KnowledgeBase::new();
-    /// if let Ok(fact) = kb.create_fact("fact: (isa square rectangle);") {
-    ///     kb.ask(&fact);
+    /// kb.register_key("location", &[1]).unwrap();
+    ///
+    /// for statement in &["fact: (location player kitchen);", "fact: (location player hall);"] {
+    ///     if let Ok(fact) = kb.create_fact(statement) {
+    ///         kb.assert(fact).unwrap();
+    ///     }
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (location player kitchen);") {
+    ///     assert_eq!(kb.ask(&fact), Ok(false));
     /// }
     /// ```
-    pub fn ask(&self, fact: &Fact) -> Result<bool, String> {
-        if self.contains_fact(fact) {
-            return Ok(true);
+    pub fn register_key(&mut self, name: &str, args: &[usize]) -> Result<(), String> {
+        let pred = self.intern_string(name);
+        if builtins::is_builtin(&pred) || aggregate::is_operation(&pred) {
+            return Err(format!("Cannot declare a key for builtin predicate {}", name));
         }
-        Ok(false)
-    }
-
-    // internal method to add a fact to the knowledge base
-    // used within the forward chaining algorithm
-    fn insert_fact(&mut self, fact: Fact) -> Rc<Fact> {
-        let fact_ref = Rc::new(fact);
-        self.facts.push(fact_ref.clone());
 
-        let args_vec = self.facts_map
-            .entry(fact_ref.pred.clone())
-            .or_insert(Vec::new());
+        if args.is_empty() || args.contains(&0) {
+            return Err(format!(
+                "Key of {} needs at least one argument, numbered from 1",
+                name
+            ));
+        }
 
-        if args_vec.is_empty() {
-            for _ in 0..fact_ref.args.len() {
-                args_vec.push(HashMap::new());
+        if let Some(schema) = self.schemas.get(&pred) {
+            if let Some(&arg) = args.iter().find(|&&arg| arg > schema.sorts.len()) {
+                return Err(format!(
+                    "Predicate {} has no argument {} to use as a key",
+                    name, arg
+                ));
             }
         }
 
-        for j in 0..args_vec.len() {
-            let mut arg_list = args_vec[j]
-                .entry(fact_ref.args[j].clone())
-                .or_insert(Vec::new());
-            arg_list.push(fact_ref.clone());
+        let positions: Vec<usize> = args.iter().map(|arg| arg - 1).collect();
+        if let Some(existing) = self.keys.get(&pred) {
+            if *existing == positions {
+                return Ok(());
+            }
+            return Err(format!("Predicate {} already has a different key", name));
         }
 
-        fact_ref
-    }
-
-    // checks whether fact already exists in knowledge base, and calls internal insert function
-    fn add_fact(&mut self, fact: Fact) -> Result<Rc<Fact>, String> {
-        if fact.contains_variable() {
-            return Err(String::from("Cannot assert fact with bound variables"));
+        if let Some(index) = self.facts_map.get(&pred) {
+            for (i, f1) in index.facts.iter().enumerate() {
+                if let Some(f2) = index.facts[i + 1..]
+                    .iter()
+                    .find(|f2| shares_key(&positions, f1, f2))
+                {
+                    return Err(format!("{} and {} share a key of {}", f1, f2, name));
+                }
+            }
         }
 
-        if self.facts.contains(&Rc::new(fact.clone())) {
-            return Err(String::from("fact already in kb"));
-        }
+        self.keys.insert(pred, positions);
+        Ok(())
+    }
 
-        Ok(self.insert_fact(fact))
+    // retracts the facts that have the same key as a new asserted fact but differ from it
+    // elsewhere
+    fn displace_by_key(&mut self, fact: &Fact) -> Result<(), String> {
+        let index = (self.keys.get(&fact.pred), self.facts_map.get(&fact.pred));
+        let conflicts: Vec<Removal> = match index {
+            (Some(positions), Some(index)) => index
+                .facts
+                .iter()
+                .filter(|f| f.context == fact.context && shares_key(positions, f, fact))
+                .cloned()
+                .map(Removal::Fact)
+                .collect(),
+            _ => return Ok(()),
+        };
+        self.remove_all(conflicts)
     }
 
-    // attempts to find and remove a fact
-    // returns an error if the fact cannot be found
-    fn remove_fact(&mut self, fact: &Fact) -> Result<(), String> {
-        if fact.contains_variable() {
-            return Err(String::from("Cannot retract fact with bound variables"));
+    /// Declares the signature of a predicate: how many arguments it takes and what sort of value
+    /// each argument holds
+    ///
+    /// Once a predicate is declared, asserting a fact or creating a rule that uses it with the
+    /// wrong number of arguments, or with an argument of the wrong sort, returns an error. The
+    /// sorts are any, symbol, number, list and compound. Any other name is a sort of its own,
+    /// whose members are given by unary facts: with (room kitchen) asserted, kitchen is a room.
+    /// Predicates can also be declared in a knowledge base file with `pred: (location object room)`.
+    ///
+    /// Declaring a predicate again with a different signature, or declaring one that existing facts
+    /// do not fit, is an error.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.register_predicate("isa", &["symbol", "symbol"]).unwrap();
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (isa cube);") {
+    ///     assert!(kb.assert(fact).is_err());
+    /// }
+    /// ```
+    pub fn register_predicate(&mut self, name: &str, sorts: &[&str]) -> Result<(), String> {
+        let pred = self.intern_string(name);
+        if builtins::is_builtin(&pred) || aggregate::is_operation(&pred) {
+            return Err(format!("Cannot declare builtin predicate {}", name));
         }
 
-        let mut fact_to_remove = None;
+        let sorts = sorts.iter().map(|s| Sort::from(s, &self.symbols)).collect();
+        let schema = Schema::new(pred.clone(), sorts);
 
-        for i in 0..self.facts.len() {
-            if fact == &*self.facts[i] {
-                fact_to_remove = Some(self.facts[i].clone());
-                self.facts.remove(i);
-                break;
+        if let Some(existing) = self.schemas.get(&pred) {
+            if *existing == schema {
+                return Ok(());
             }
+            return Err(format!(
+                "Predicate {} is already declared with a different signature",
+                name
+            ));
         }
 
-        match fact_to_remove {
-            None => Err(String::from("fact does not exist in kb")),
-
-            Some(fact_reference) => {
-                {
-                    // A found fact must be in args vec
-                    let mut args_vec = self.facts_map.get_mut(&fact_reference.pred).unwrap();
+        if let Some(index) = self.facts_map.get(&pred) {
+            for fact in &index.facts {
+                schema.check_shape(fact)?;
+            }
+        }
 
-                    for j in 0..args_vec.len() {
-                        // A found fact must have an entry for each argument
-                        let mut arg_list = args_vec[j].get_mut(&fact_reference.args[j]).unwrap();
+        self.schemas.insert(pred, schema);
+        Ok(())
+    }
 
-                        let index = arg_list.iter().position(|x| *x == fact_reference).unwrap();
-                        arg_list.remove(index);
+    // Returns the predicates used as named sorts by any declaration
+    fn named_sorts(&self) -> Vec<Symbol> {
+        let mut names = Vec::new();
+        for schema in self.schemas.values() {
+            for sort in &schema.sorts {
+                if let Sort::Named(ref name) = *sort {
+                    if !names.contains(name) {
+                        names.push(name.clone());
                     }
                 }
+            }
+        }
+        names
+    }
 
-                // retract facts supported by this fact
-                for f in &self.facts.clone() {
-                    for i in 0..f.supported_by.len() {
-                        if fact_reference == f.supported_by[i].0 {
-                            self.remove_fact(&f).is_ok();
-                        }
+    // Checks a fact against the declaration of its predicate, if there is one
+    //
+    // Membership in named sorts is only checked for asserted facts. Derived facts follow from
+    // rules whose shape was already checked, and their premises decide what they range over.
+    fn check_fact(&self, fact: &Fact) -> Result<(), String> {
+        let schema = match self.schemas.get(&fact.pred) {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+        schema.check_shape(fact)?;
+
+        if fact.asserted {
+            for (sort, arg) in schema.sorts.iter().zip(fact.args.iter()) {
+                if let Sort::Named(ref name) = *sort {
+                    let member = Fact::new(name.clone(), vec![arg.clone()], vec![]);
+                    if !self.contains_fact(&member) {
+                        return Err(format!(
+                            "{} in {} is not a {}; assert {} first",
+                            arg, fact, sort, member
+                        ));
                     }
                 }
-
-                // retract rules supported by this fact
-                for r in self.rules.clone().iter() {
-                    for i in 0..r.supported_by.len() {
-                        if fact_reference == r.supported_by[i].0 {
-                            self.remove_rule(&r).is_ok();
-                        }
-                    }
-                }
-
-                Ok(())
             }
         }
-    }
-
-    fn insert_rule(&mut self, rule: Rule) -> Rc<Rule> {
-        let rule_ref = Rc::new(rule);
-        self.rules.push(rule_ref.clone());
 
-        rule_ref
+        Ok(())
     }
 
-    // checks whether rule already exists in knowledge base, and calls internal insert function
-    fn add_rule(&mut self, rule: Rule) -> Result<Rc<Rule>, String> {
-        if self.contains_rule(&rule) {
-            return Err(String::from("rule already in kb"));
+    // Checks every premise and the conclusion of a rule against their declarations
+    fn check_rule(&self, rule: &Rule) -> Result<(), String> {
+        for fact in rule.lhs.iter().chain(rule.rhs.iter()) {
+            self.check_premise(fact)?;
         }
-        Ok(self.insert_rule(rule))
+        Ok(())
     }
 
-    // attempts to find and remove a rule
-    // returns an error if the rule cannot be found
-    fn remove_rule(&mut self, rule: &Rule) -> Result<(), String> {
-        let mut rule_to_remove = None;
-
-        for i in 0..self.rules.len() {
-            if rule == &*self.rules[i] {
-                rule_to_remove = Some(self.rules[i].clone());
-                self.rules.remove(i);
-                break;
+    // Checks that no aggregate ranges over facts that can be concluded from its own result, given
+    // the premises and conclusions of a rule or production about to be added. Such an aggregate
+    // would change every time facts are concluded from it, so inference would never end.
+    fn check_stratified(&self, premises: &[Fact], conclusions: &[&Fact]) -> Result<(), String> {
+        // Edges from the predicates of premises to those of conclusions, marking the ones that
+        // go through an aggregate
+        let mut edges: Vec<(Symbol, Symbol, bool)> = Vec::new();
+        let mut add_edges = |premises: &[Fact], conclusions: &[&Fact]| {
+            for premise in premises {
+                let (from, through_aggregate) = if aggregate::is_aggregate(premise) {
+                    (aggregate::pattern(premise).pred, true)
+                } else {
+                    (premise.pred.clone(), false)
+                };
+                for conclusion in conclusions {
+                    edges.push((from.clone(), conclusion.pred.clone(), through_aggregate));
+                }
             }
+        };
+        for rule in self.rules.iter().filter(|r| r.asserted) {
+            add_edges(&rule.lhs, &rule.rhs.iter().collect::<Vec<&Fact>>());
         }
+        for production in &self.productions {
+            add_edges(&production.lhs, &production.asserted_facts().collect::<Vec<&Fact>>());
+        }
+        add_edges(premises, conclusions);
 
-        match rule_to_remove {
-            None => Err(String::from("rule does not exist in kb")),
-
-            Some(rule_reference) => {
-                // retract facts supported by this rule
-                for f in &self.facts.clone() {
-                    for i in 0..f.supported_by.len() {
-                        if rule_reference == f.supported_by[i].1 {
-                            Rc::make_mut(&mut f.clone()).supported_by.remove(i);
-                            self.remove_fact(&f).is_ok();
-                        }
-                    }
+        for (over, result, _) in edges.iter().filter(|edge| edge.2) {
+            let mut pending = vec![result];
+            let mut seen = Vec::new();
+            while let Some(pred) = pending.pop() {
+                if pred == over {
+                    return Err(format!(
+                        "Aggregate over {} depends on its own result, so inference would never end",
+                        &**over
+                    ));
                 }
-
-                // retract rules supported by this rule
-                for r in &self.rules.clone() {
-                    for i in 0..r.supported_by.len() {
-                        if rule_reference == r.supported_by[i].1 {
-                            Rc::make_mut(&mut r.clone()).supported_by.remove(i);
-                            self.remove_rule(&r).is_ok();
-                        }
-                    }
+                if !seen.contains(&pred) {
+                    seen.push(pred);
+                    pending.extend(edges.iter().filter(|edge| edge.0 == *pred).map(|edge| &edge.1));
                 }
-
-                Ok(())
             }
         }
+        Ok(())
     }
 
-    // checks if given fact is in knowledge base
-    fn contains_fact(&self, fact: &Fact) -> bool {
-        self.facts.iter().fold(false, |acc, f| {
-            let temp = &**f;
-            acc || (temp.pred == fact.pred && temp.args == fact.args)
-        })
+    // Checks the premises of a production and the facts it asserts against their declarations
+    fn check_production(&self, production: &Production) -> Result<(), String> {
+        for fact in production.lhs.iter().chain(production.asserted_facts()) {
+            self.check_premise(fact)?;
+        }
+        Ok(())
     }
 
-    // checks if given rule is in knowledge base
-    fn contains_rule(&self, rule: &Rule) -> bool {
-        self.rules.iter().fold(false, |acc, r| {
-            let temp = &**r;
-            acc || (temp.lhs == rule.lhs && temp.rhs == rule.rhs)
-        })
+    // Checks the shape of a fact that may contain variables against its declaration
+    fn check_premise(&self, fact: &Fact) -> Result<(), String> {
+        let fact = schema::checked_fact(fact);
+        match self.schemas.get(&fact.pred) {
+            Some(schema) => schema.check_shape(&fact),
+            None => Ok(()),
+        }
     }
 
-    // function that implements inference by forward chaining
-    fn infer(&mut self, fact: Rc<Fact>, rule: Rc<Rule>) {
-        // Inference by Forward Chaining
-        if rule.lhs.len() == 1 {
-            let lhs = &rule.lhs[0];
-            if let Ok(bindings) = self.try_bind(&fact, lhs) {
-                let new_fact =
-                    self.apply_bindings(&rule.rhs, Some((fact.clone(), rule.clone())), &bindings);
-                if !new_fact.contains_variable() {
-                    self.assert(new_fact).is_ok();
-                }
-            }
-        } else if rule.lhs.len() > 1 {
-            let lhs = &rule.lhs[0];
-            if let Ok(bindings) = self.try_bind(&fact, lhs) {
-                let new_lhs = rule.lhs
-                    .clone()
-                    .iter()
-                    .enumerate()
-                    .filter(|&(n, _)| n != 0)
-                    .map(|(_, f)| self.apply_bindings(f, None, &bindings))
-                    .collect::<Vec<Fact>>();
-                let new_rhs = self.apply_bindings(&rule.rhs, None, &bindings);
-                let new_rule = Rule::new(new_lhs, new_rhs, vec![(fact.clone(), rule.clone())]);
+    #[inline]
+    fn intern_string(&mut self, name: &str) -> Symbol {
+        self.symbols.intern(name)
+    }
 
-                self.assert(new_rule).is_ok();
-            }
-        }
+    /// Add a fact or rule to the knowledge base
+    ///
+    /// This function will use inference by forward chaining to add implied facts from given rules.
+    /// An error will be returned if the statement is already present in the knowledge base. Use this
+    /// error to detect logical errors, or duplicate assertions in code.
+    ///
+    /// If the statement, together with what is inferred from it, violates a constraint of the
+    /// knowledge base, it is retracted again and the error names the constraint and the bindings
+    /// that violate it.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// match kb.create_fact("fact: (isa square rectangle);") {
+    ///     Ok(fact) => { kb.assert(fact); },
+    ///     Err(_) => {},
+    /// }
+    /// ```
+    pub fn assert<T: Statement>(&mut self, statement: T) -> Result<Rc<dyn Statement>, String> {
+        // Failing undoes the statement, everything inferred from it and the facts it replaced
+        self.transaction(|kb| {
+            let added = kb.add_statement(statement)?;
+            kb.run_or_check()?;
+            Ok(added)
+        })
     }
 
-    fn try_bind(&self, f1: &Fact, f2: &Fact) -> Result<HashMap<Symbol, Symbol>, String> {
-        if f1.pred != f2.pred || f1.args.len() != f2.args.len() {
-            return Err("bind failed".to_string());
-        }
-        let mut bindings: HashMap<Symbol, Symbol> = HashMap::new();
-        for pairs in f1.args.iter().zip(f2.args.iter()) {
-            let (a1, a2) = pairs;
-            if a1 != a2 {
-                if a1.is_var() && !a2.is_var() {
-                    match bindings.insert(a1.clone(), a2.clone()) {
-                        Some(old_val) => {
-                            if a2.clone() != old_val {
-                                return Err("bind failed".to_string());
-                            }
-                        }
-                        None => continue,
+    // adds a statement and everything that can be inferred from it, without checking constraints
+    fn add_statement<T: Statement>(&mut self, statement: T) -> Result<Rc<dyn Statement>, String> {
+        match statement.to_fact() {
+            Some(fact) => match self.add_fact(fact) {
+                Ok(rc_fact) => {
+                    for rule in &self.rules.clone() {
+                        self.activate(rc_fact.clone(), rule.clone());
+                    }
+                    for production in &self.productions.clone() {
+                        self.match_production(production, Some(&rc_fact));
                     }
-                } else if a2.is_var() && !a1.is_var() {
-                    match bindings.insert(a2.clone(), a1.clone()) {
-                        Some(old_val) => {
-                            if a1.clone() != old_val {
-                                return Err("bind failed".to_string());
+                    self.refresh_aggregates(&rc_fact.pred)?;
+                    Ok(rc_fact)
+                }
+                Err(e) => Err(e),
+            },
+            None => {
+                let rule = statement.to_rule().unwrap();
+                match self.add_rule(rule) {
+                    Ok(rc_rule) => {
+                        let solutions = rc_rule
+                            .lhs
+                            .first()
+                            .and_then(|premise| self.solve_computed(premise));
+
+                        match solutions {
+                            // Computed premises are never stored, so they are matched against
+                            // their instances instead of the facts of the knowledge base
+                            Some(solutions) => {
+                                for solution in solutions {
+                                    self.activate(Rc::new(solution), rc_rule.clone());
+                                }
+                            }
+                            None => {
+                                for fact in &self.facts.clone() {
+                                    self.activate(fact.clone(), rc_rule.clone());
+                                }
                             }
                         }
-                        None => continue,
+                        Ok(rc_rule)
                     }
-                } else {
-                    return Err("bind failed".to_string());
+                    Err(e) => Err(e),
                 }
             }
         }
-        Ok(bindings)
     }
 
-    fn apply_bindings(
-        &self,
-        fact: &Fact,
-        support: Option<(Rc<Fact>, Rc<Rule>)>,
-        bindings: &HashMap<Symbol, Symbol>,
-    ) -> Fact {
-        let mut args: Vec<Symbol> = Vec::new();
-        for symbol in &fact.args {
-            if symbol.is_var() {
-                if !bindings.contains_key(symbol) {
-                    args.push(symbol.clone());
+    /// Remove a fact or rule from the knowledge base.
+    ///
+    /// This function will remove a specific statement from the knowledge base. In addition, it will
+    /// remove every statement that was inferred from the given statement, directly or through
+    /// other inferred statements. If the inference that follows goes past a limit, the statement
+    /// and everything inferred from it are restored and the error is returned.
+    ///
+    /// Statements that still have support from other Fact/Rule pairs will error on retract
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::{KnowledgeBase,Fact};
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// // Add to the knowledge base
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (isa square rectangle);") {
+    ///     kb.retract(fact);
+    /// }
+    /// ```
+    pub fn retract<T: Statement>(&mut self, statement: T) -> Result<(), String> {
+        match statement.to_fact() {
+            Some(fact) => {
+                if fact.supported_by.is_empty() {
+                    self.transaction(|kb| {
+                        kb.remove_fact(&fact)?;
+                        kb.run_if_auto()
+                    })
                 } else {
-                    args.push(bindings.get(symbol).unwrap().clone());
+                    Err(String::from(
+                        "Fact cannot be removed because it's supported",
+                    ))
+                }
+            }
+            None => {
+                let rule = statement.to_rule().unwrap();
+                if rule.supported_by.is_empty() {
+                    self.transaction(|kb| {
+                        kb.remove_rule(&rule)?;
+                        kb.run_if_auto()
+                    })
+                } else {
+                    Err(String::from(
+                        "Rule cannot be removed because it's supported",
+                    ))
                 }
-            } else {
-                args.push(symbol.clone());
             }
         }
-        match support {
-            Some(sup) => Fact::new(fact.pred.clone(), args, vec![sup]),
-            None => Fact::new(fact.pred.clone(), args, vec![]),
+    }
+
+    /// Add a fact or rule to a named context
+    ///
+    /// The statement is only seen from the context and its descendants. See `create_context`.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.create_context("guard", None).unwrap();
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (hides thief barn);") {
+    ///     kb.assert_in("guard", fact.clone()).unwrap();
+    ///     assert_eq!(kb.ask_in("guard", &fact), Ok(true));
+    ///     assert_eq!(kb.ask(&fact), Ok(false));
+    /// }
+    /// ```
+    pub fn assert_in<T: Statement>(
+        &mut self,
+        context: &str,
+        statement: T,
+    ) -> Result<Rc<dyn Statement>, String> {
+        let context = Some(self.context_named(context)?);
+        match statement.to_fact() {
+            Some(fact) => self.assert(fact.in_context(context)),
+            None => self.assert(statement.to_rule().unwrap().in_context(context)),
+        }
+    }
+
+    /// Remove a fact or rule from a named context
+    ///
+    /// Statements of the context's ancestors cannot be removed through it.
+    pub fn retract_in<T: Statement>(&mut self, context: &str, statement: T) -> Result<(), String> {
+        let context = Some(self.context_named(context)?);
+        match statement.to_fact() {
+            Some(fact) => self.retract(fact.in_context(context)),
+            None => self.retract(statement.to_rule().unwrap().in_context(context)),
         }
     }
 
-    /// Query a knowledge base to find all possible bindings to variables in the fact
+    /// Add a fact that is forgotten once `ttl` units of time have passed
     ///
-    /// The given fact should contain at least one variable.
+    /// The fact is retracted, along with everything inferred from it, by the first call to
+    /// `advance_time` that reaches its expiry. Asserting the same fact with a time to live again
+    /// before then refreshes it, so it expires `ttl` units after the current time instead. A fact
+    /// that was asserted without a time to live cannot be given one.
     ///
-    /// # Example
+    ///  # Example
     ///
     /// ```
     /// use rust_kb::KnowledgeBase;
     ///
     /// let mut kb = KnowledgeBase::new();
     ///
-    /// // Fill the knowledge base
+    /// if let Ok(fact) = kb.create_fact("fact: (sees guard player);") {
+    ///     kb.assert_for(fact.clone(), 5).unwrap();
     ///
-    /// if let Ok(fact) = kb.create_fact("fact: (isa ?object rectangle);") {
-    ///     let result = kb.query(&fact);
-    ///     // result is now a vector of all bindings found in the knowledge base.
+    ///     kb.advance_time(3).unwrap();
+    ///     kb.assert_for(fact.clone(), 5).unwrap();
+    ///
+    ///     kb.advance_time(6).unwrap();
+    ///     assert_eq!(kb.ask(&fact), Ok(true));
+    ///
+    ///     kb.advance_time(8).unwrap();
+    ///     assert_eq!(kb.ask(&fact), Ok(false));
     /// }
     /// ```
-    pub fn query(&self, f: &Fact) -> Vec<QueryBinding> {
-        self.get_query_facts(f)
-            .into_iter()
-            .map(|x| self.try_bind(&x, f))
-            .filter(|x| x.is_ok())
-            .map(|x| {
-                x.unwrap()
-                    .iter()
-                    .map(|t| (t.0.clone(), t.1.clone()))
-                    .collect()
+    pub fn assert_for(&mut self, fact: Fact, ttl: i64) -> Result<Rc<dyn Statement>, String> {
+        if ttl <= 0 {
+            return Err(format!(
+                "Time to live of {} should be positive, but is {}",
+                fact, ttl
+            ));
+        }
+        let expiry = self.now.saturating_add(ttl);
+
+        let stored = self.facts.iter().find(|f| ***f == fact).cloned();
+        if let Some(stored) = stored {
+            if let Some(old) = self.expiries.get_mut(&Rc::as_ptr(&stored)) {
+                *old = expiry;
+                return Ok(stored);
+            }
+        }
+
+        let added = self.assert(fact.clone())?;
+        // A production may already have retracted the fact again
+        if let Some(stored) = self.facts.iter().find(|f| ***f == fact) {
+            self.expiries.insert(Rc::as_ptr(stored), expiry);
+        }
+        Ok(added)
+    }
+
+    /// Moves the time of the knowledge base forward, retracting facts whose time to live ran out
+    ///
+    /// Facts asserted with `assert_for` expire once the time reaches their expiry. Everything
+    /// inferred from an expired fact is retracted with it. The expired facts are returned in the
+    /// order they were added. Time starts at 0 and cannot move backwards.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// if let Ok(rule) = kb.create_rule("rule: ((sees ?x player)) -> (alerted ?x);") {
+    ///     kb.assert(rule).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (sees guard player);") {
+    ///     kb.assert_for(fact, 5).unwrap();
+    /// }
+    ///
+    /// assert_eq!(kb.advance_time(5).unwrap().len(), 1);
+    /// if let Ok(fact) = kb.create_fact("fact: (alerted guard);") {
+    ///     assert_eq!(kb.ask(&fact), Ok(false));
+    /// }
+    /// ```
+    pub fn advance_time(&mut self, now: i64) -> Result<Vec<Fact>, String> {
+        if now < self.now {
+            return Err(format!("Cannot move time back from {} to {}", self.now, now));
+        }
+
+        let expired: Vec<Rc<Fact>> = self
+            .facts
+            .iter()
+            .filter(|f| {
+                self.expiries
+                    .get(&Rc::as_ptr(f))
+                    .is_some_and(|&expiry| expiry <= now)
             })
-            .collect()
+            .cloned()
+            .collect();
+        let before = std::mem::replace(&mut self.now, now);
+        let removed = self.transaction(|kb| {
+            kb.remove_all(expired.iter().cloned().map(Removal::Fact).collect())?;
+            kb.run_if_auto()
+        });
+        if let Err(e) = removed {
+            self.now = before;
+            return Err(e);
+        }
+
+        Ok(expired.iter().map(|f| (**f).clone()).collect())
+    }
+
+    /// Returns the time last set by `advance_time`
+    pub fn now(&self) -> i64 {
+        self.now
+    }
+
+    /// Returns when a fact asserted with a time to live expires, or None if it never does
+    pub fn expiry(&self, fact: &Fact) -> Option<i64> {
+        self.facts
+            .iter()
+            .find(|f| ***f == *fact)
+            .and_then(|f| self.expiries.get(&Rc::as_ptr(f)).cloned())
+    }
+
+    /// Add a fact with a confidence, or a rule with a strength, between 0 and 1
+    ///
+    /// Statements added with `assert` have a confidence or strength of 1. See `CertaintyModel`
+    /// for how the confidence in derived facts is computed, and `ask_with_confidence` to read it.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// if let Ok(rule) = kb.create_rule("rule: ((heard ?x noise)) -> (suspects ?x intruder);") {
+    ///     kb.assert_with_confidence(rule, 0.5).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (heard guard noise);") {
+    ///     kb.assert_with_confidence(fact, 0.8).unwrap();
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (suspects guard intruder);") {
+    ///     assert_eq!(kb.ask_with_confidence(&fact), Some(0.4));
+    /// }
+    /// ```
+    pub fn assert_with_confidence<T: Statement>(
+        &mut self,
+        statement: T,
+        confidence: f64,
+    ) -> Result<Rc<dyn Statement>, String> {
+        certainty::check(confidence)?;
+        let (fact, rule) = (statement.to_fact(), statement.to_rule());
+        let added = self.assert(statement)?;
+
+        // A production may already have retracted the statement again
+        match fact {
+            Some(fact) => {
+                if let Some(stored) = self.facts.iter().find(|f| ***f == fact) {
+                    self.confidences.insert(Rc::as_ptr(stored), confidence);
+                }
+            }
+            None => {
+                let rule = rule.unwrap();
+                if let Some(stored) = self.rules.iter().find(|r| ***r == rule) {
+                    self.strengths.insert(Rc::as_ptr(stored), confidence);
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    /// Sets how the confidence in derived facts is computed
+    pub fn set_certainty_model(&mut self, model: CertaintyModel) {
+        self.certainty = model;
+    }
+
+    /// Returns how the confidence in derived facts is computed
+    pub fn certainty_model(&self) -> CertaintyModel {
+        self.certainty
+    }
+
+    /// Ask if a specific fact can be proven by the knowledge base
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// if let Ok(fact) = kb.create_fact("fact: (isa square rectangle);") {
+    ///     kb.ask(&fact);
+    /// }
+    /// ```
+    pub fn ask(&self, fact: &Fact) -> Result<bool, String> {
+        if self.contains_fact(fact) {
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Ask if a specific fact can be proven in a named context, from its own statements and those
+    /// of its ancestors
+    pub fn ask_in(&self, context: &str, fact: &Fact) -> Result<bool, String> {
+        let context = Some(self.context_named(context)?);
+        Ok(self.facts.iter().any(|f| {
+            f.pred == fact.pred && f.args == fact.args && self.sees(&context, &f.context)
+        }))
+    }
+
+    /// Returns the confidence in a fact, or None if the knowledge base does not know it
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::{CertaintyModel, Conjunction, Disjunction, KnowledgeBase};
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.set_certainty_model(CertaintyModel {
+    ///     conjunction: Conjunction::Product,
+    ///     disjunction: Disjunction::NoisyOr,
+    /// });
+    ///
+    /// for fact in &["fact: (saw guard thief);", "fact: (saw cook thief);"] {
+    ///     if let Ok(fact) = kb.create_fact(fact) {
+    ///         kb.assert_with_confidence(fact, 0.5).unwrap();
+    ///     }
+    /// }
+    /// if let Ok(rule) = kb.create_rule("rule: ((saw ?x thief)) -> (thief seen);") {
+    ///     kb.assert(rule).unwrap();
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (thief seen);") {
+    ///     assert_eq!(kb.ask_with_confidence(&fact), Some(0.75));
+    /// }
+    /// ```
+    pub fn ask_with_confidence(&self, fact: &Fact) -> Option<f64> {
+        self.fact_confidence(fact)
+    }
+
+    // internal method to add a fact to the knowledge base
+    // used within the forward chaining algorithm
+    fn insert_fact(&mut self, fact: Fact) -> Rc<Fact> {
+        let fact_ref = Rc::new(fact);
+
+        self.clock += 1;
+        self.fact_times.insert(Rc::as_ptr(&fact_ref), self.clock);
+
+        let depth = fact_ref
+            .supported_by
+            .iter()
+            .map(|(f, r)| 1 + self.fact_depth(f).max(self.rule_depth(r)))
+            .min()
+            .unwrap_or(0);
+        self.fact_depths.insert(Rc::as_ptr(&fact_ref), depth);
+        self.deepest = self.deepest.max(depth);
+        self.inserted += 1;
+
+        self.link_fact(&fact_ref, &[]);
+        self.journal.record(Edit::AddedFact(fact_ref.clone()));
+        fact_ref
+    }
+
+    // stores a fact among the facts and in the predicate index, and reports it to subscriptions.
+    // A fact that was removed goes back to the positions it was removed from; any other fact goes
+    // at the end.
+    fn link_fact(&mut self, fact: &Rc<Fact>, positions: &[usize]) {
+        let at = |i: usize, len: usize| positions.get(i).map_or(len, |&p| p.min(len));
+        let position = at(0, self.facts.len());
+        self.facts.insert(position, fact.clone());
+
+        let index = self.facts_map.entry(fact.pred.clone()).or_default();
+        let position = at(1, index.facts.len());
+        index.facts.insert(position, fact.clone());
+
+        while index.args.len() < fact.args.len() {
+            index.args.push(HashMap::new());
+        }
+
+        for (i, (column, arg)) in index.args.iter_mut().zip(fact.args.iter()).enumerate() {
+            // Facts in the knowledge base are ground, so every argument has an index key
+            if let Some(key) = arg.index_key() {
+                let facts = column.entry(key.clone()).or_default();
+                let position = at(i + 2, facts.len());
+                facts.insert(position, fact.clone());
+            }
+        }
+
+        let change = if fact.asserted {
+            Change::Asserted
+        } else {
+            Change::Derived
+        };
+        if fact.context.is_none() {
+            self.subscriptions.notify(change, fact);
+        }
+    }
+
+    // takes a stored fact out of the facts and the predicate index, along with what was kept about
+    // it and the activations and fired matches that used it, and reports it to subscriptions.
+    // Returns None if the fact is not stored.
+    fn unlink_fact(&mut self, fact: &Rc<Fact>) -> Option<Stored> {
+        let position = self.facts.iter().position(|f| Rc::ptr_eq(f, fact))?;
+        self.facts.remove(position);
+
+        let key = Rc::as_ptr(fact);
+        let mut stored = Stored {
+            positions: vec![position],
+            time: self.fact_times.remove(&key),
+            depth: self.fact_depths.remove(&key),
+            expiry: self.expiries.remove(&key),
+            certainty: self.confidences.remove(&key),
+            activations: self
+                .agenda
+                .remove(|a| a.facts.iter().any(|f| Rc::ptr_eq(f, fact))),
+            refracted: Vec::new(),
+        };
+        for (position, entry) in std::mem::take(&mut self.refracted).into_iter().enumerate() {
+            if entry.1.iter().any(|f| f.pred == fact.pred && f.args == fact.args) {
+                stored.refracted.push((position, entry));
+            } else {
+                self.refracted.push(entry);
+            }
+        }
+
+        {
+            // A stored fact must be in the predicate index
+            let index = self.facts_map.get_mut(&fact.pred).unwrap();
+
+            let position = index.facts.iter().position(|x| Rc::ptr_eq(x, fact)).unwrap();
+            index.facts.remove(position);
+            stored.positions.push(position);
+
+            for (column, arg) in index.args.iter_mut().zip(fact.args.iter()) {
+                // A stored fact must have an entry for each argument
+                let facts = column.get_mut(arg.index_key().unwrap()).unwrap();
+
+                let position = facts.iter().position(|x| Rc::ptr_eq(x, fact)).unwrap();
+                facts.remove(position);
+                stored.positions.push(position);
+            }
+        }
+        if fact.context.is_none() {
+            self.subscriptions.notify(Change::Retracted, fact);
+        }
+
+        Some(stored)
+    }
+
+    // puts a removed fact back where it was, with what was kept about it
+    fn relink_fact(&mut self, fact: Rc<Fact>, stored: Stored) {
+        let key = Rc::as_ptr(&fact);
+        if let Some(time) = stored.time {
+            self.fact_times.insert(key, time);
+        }
+        if let Some(depth) = stored.depth {
+            self.fact_depths.insert(key, depth);
+        }
+        if let Some(expiry) = stored.expiry {
+            self.expiries.insert(key, expiry);
+        }
+        if let Some(confidence) = stored.certainty {
+            self.confidences.insert(key, confidence);
+        }
+        self.link_fact(&fact, &stored.positions);
+
+        for activation in stored.activations {
+            self.agenda.push(activation);
+        }
+        for (position, entry) in stored.refracted {
+            self.refracted.insert(position, entry);
+        }
+    }
+
+    // checks whether fact already exists in knowledge base, and calls internal insert function
+    fn add_fact(&mut self, fact: Fact) -> Result<Rc<Fact>, String> {
+        if fact.contains_variable() {
+            return Err(String::from("Cannot assert fact with bound variables"));
+        }
+
+        if builtins::is_builtin(&fact.pred) {
+            return Err(format!("Cannot assert fact for builtin predicate {}", &*fact.pred));
+        }
+
+        if aggregate::is_aggregate(&fact) {
+            return Err(format!("Cannot assert aggregate {} as a fact", &*fact.pred));
+        }
+
+        if fact.valid.is_empty() {
+            return Err(format!("Fact {} is never valid", fact));
+        }
+
+        self.check_fact(&fact)?;
+
+        if self.facts.contains(&Rc::new(fact.clone())) {
+            return Err(String::from("fact already in kb"));
+        }
+
+        if fact.asserted {
+            self.displace_by_key(&fact)?;
+        }
+
+        Ok(self.insert_fact(fact))
+    }
+
+    // attempts to find and remove a fact, along with everything derived from it
+    // returns an error if the fact cannot be found
+    fn remove_fact(&mut self, fact: &Fact) -> Result<(), String> {
+        if fact.contains_variable() {
+            return Err(String::from("Cannot retract fact with bound variables"));
+        }
+
+        match self.facts.iter().find(|f| ***f == *fact).cloned() {
+            Some(stored) => self.remove_all(vec![Removal::Fact(stored)]),
+            None => Err(String::from("fact does not exist in kb")),
+        }
+    }
+
+    // removes stored statements along with everything derived from them. What depends on a
+    // removed statement is removed in turn from a worklist rather than recursively, so retracting
+    // the start of a long chain of derivations cannot overflow the stack. Aggregates over the
+    // predicates of removed facts are refreshed once everything is gone.
+    fn remove_all(&mut self, removals: Vec<Removal>) -> Result<(), String> {
+        let mut pending: Vec<Removal> = removals.into_iter().rev().collect();
+        let mut refresh: Vec<Symbol> = Vec::new();
+
+        while let Some(removal) = pending.pop() {
+            // A statement derived from several removed ones may already be gone
+            let removed = match removal {
+                Removal::Fact(ref fact) => self
+                    .unlink_fact(fact)
+                    .map(|stored| Edit::RemovedFact(fact.clone(), stored)),
+                Removal::Rule(ref rule) => self
+                    .unlink_rule(rule)
+                    .map(|stored| Edit::RemovedRule(rule.clone(), stored)),
+            };
+            match removed {
+                Some(edit) => self.journal.record(edit),
+                None => continue,
+            }
+            if let Removal::Fact(ref fact) = removal {
+                if !refresh.contains(&fact.pred) {
+                    refresh.push(fact.pred.clone());
+                }
+            }
+
+            // What was derived from the statement goes next, in the order it is stored
+            let supports = |support: &(Rc<Fact>, Rc<Rule>)| match removal {
+                Removal::Fact(ref fact) => Rc::ptr_eq(&support.0, fact),
+                Removal::Rule(ref rule) => Rc::ptr_eq(&support.1, rule),
+            };
+            let rules: Vec<Rc<Rule>> = self
+                .rules
+                .iter()
+                .filter(|r| r.supported_by.iter().any(supports))
+                .cloned()
+                .collect();
+            let facts: Vec<Rc<Fact>> = self
+                .facts
+                .iter()
+                .filter(|f| f.supported_by.iter().any(supports))
+                .cloned()
+                .collect();
+            pending.extend(rules.into_iter().rev().map(Removal::Rule));
+            pending.extend(facts.into_iter().rev().map(Removal::Fact));
+        }
+
+        for pred in refresh {
+            self.refresh_aggregates(&pred)?;
+        }
+        Ok(())
+    }
+
+    fn insert_rule(&mut self, rule: Rule) -> Rc<Rule> {
+        let rule_ref = Rc::new(rule);
+
+        let depth = rule_ref
+            .supported_by
+            .iter()
+            .map(|(f, r)| self.fact_depth(f).max(self.rule_depth(r)))
+            .min()
+            .unwrap_or(0);
+        self.rule_depths.insert(Rc::as_ptr(&rule_ref), depth);
+
+        self.link_rule(&rule_ref, &[]);
+        self.journal.record(Edit::AddedRule(rule_ref.clone()));
+        rule_ref
+    }
+
+    // stores a rule among the rules, and among the rules aggregating over a predicate if its first
+    // premise is an aggregate. A rule that was removed goes back to the positions it was removed
+    // from; any other rule goes at the end.
+    fn link_rule(&mut self, rule: &Rc<Rule>, positions: &[usize]) {
+        let at = |i: usize, len: usize| positions.get(i).map_or(len, |&p| p.min(len));
+        let position = at(0, self.rules.len());
+        self.rules.insert(position, rule.clone());
+
+        if let Some(premise) = rule.lhs.first() {
+            if aggregate::is_aggregate(premise) {
+                let pred = aggregate::pattern(premise).pred;
+                let rules = self.aggregate_rules.entry(pred).or_default();
+                let position = at(1, rules.len());
+                rules.insert(position, rule.clone());
+            }
+        }
+    }
+
+    // takes a stored rule out of the rules, along with what was kept about it and its activations.
+    // Returns None if the rule is not stored.
+    fn unlink_rule(&mut self, rule: &Rc<Rule>) -> Option<Stored> {
+        let position = self.rules.iter().position(|r| Rc::ptr_eq(r, rule))?;
+        self.rules.remove(position);
+
+        let key = Rc::as_ptr(rule);
+        let mut stored = Stored {
+            positions: vec![position],
+            depth: self.rule_depths.remove(&key),
+            certainty: self.strengths.remove(&key),
+            activations: self.agenda.remove(|a| match a.source {
+                Source::Rule(ref r) => Rc::ptr_eq(r, rule),
+                Source::Production(..) => false,
+            }),
+            ..Default::default()
+        };
+        for rules in self.aggregate_rules.values_mut() {
+            if let Some(position) = rules.iter().position(|r| Rc::ptr_eq(r, rule)) {
+                rules.remove(position);
+                stored.positions.push(position);
+            }
+        }
+
+        Some(stored)
+    }
+
+    // puts a removed rule back where it was, with what was kept about it
+    fn relink_rule(&mut self, rule: Rc<Rule>, stored: Stored) {
+        let key = Rc::as_ptr(&rule);
+        if let Some(depth) = stored.depth {
+            self.rule_depths.insert(key, depth);
+        }
+        if let Some(strength) = stored.certainty {
+            self.strengths.insert(key, strength);
+        }
+        self.link_rule(&rule, &stored.positions);
+
+        for activation in stored.activations {
+            self.agenda.push(activation);
+        }
+    }
+
+    // checks whether rule already exists in knowledge base, and calls internal insert function
+    fn add_rule(&mut self, rule: Rule) -> Result<Rc<Rule>, String> {
+        self.check_rule(&rule)?;
+        if rule.asserted {
+            self.check_stratified(&rule.lhs, &rule.rhs.iter().collect::<Vec<&Fact>>())?;
+        }
+
+        if self.contains_rule(&rule) {
+            return Err(String::from("rule already in kb"));
+        }
+        Ok(self.insert_rule(rule))
+    }
+
+    // attempts to find and remove a rule, along with everything derived from it
+    // returns an error if the rule cannot be found
+    fn remove_rule(&mut self, rule: &Rule) -> Result<(), String> {
+        match self.rules.iter().find(|r| ***r == *rule).cloned() {
+            Some(stored) => self.remove_all(vec![Removal::Rule(stored)]),
+            None => Err(String::from("rule does not exist in kb")),
+        }
+    }
+
+    // Carries out an operation that changes the knowledge base. When the operation fails,
+    // everything it changed is undone, so it either happens completely or not at all. Callbacks
+    // called by productions are only made once the outermost operation has succeeded.
+    fn transaction<T, F>(&mut self, operation: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut KnowledgeBase) -> Result<T, String>,
+    {
+        let mark = self.journal.begin();
+        let result = operation(self);
+        match result {
+            Ok(_) => {
+                let mut edits = self.journal.commit();
+                for edit in &edits {
+                    if let Edit::Called(ref name, ref args) = *edit {
+                        self.callbacks.call(name, args);
+                    }
+                }
+                // Forgetting the latest changes first frees a long chain of removed derivations
+                // one statement at a time, rather than recursively
+                while edits.pop().is_some() {}
+            }
+            Err(_) => self.undo(mark),
+        }
+        result
+    }
+
+    // undoes the changes recorded since the mark, from the latest to the earliest
+    fn undo(&mut self, mark: usize) {
+        for edit in self.journal.rollback(mark) {
+            match edit {
+                Edit::AddedFact(fact) => {
+                    self.unlink_fact(&fact);
+                }
+                Edit::AddedRule(rule) => {
+                    self.unlink_rule(&rule);
+                }
+                Edit::RemovedFact(fact, stored) => self.relink_fact(fact, stored),
+                Edit::RemovedRule(rule, stored) => self.relink_rule(rule, stored),
+                Edit::Queued(time) => {
+                    self.agenda.remove(|a| a.time == time);
+                }
+                Edit::Dropped(activations) => {
+                    for activation in activations {
+                        self.agenda.push(activation);
+                    }
+                }
+                Edit::Fired(activation) => self.agenda.push(activation),
+                Edit::Refracted => {
+                    self.refracted.pop();
+                }
+                Edit::Called(..) => {}
+            }
+        }
+    }
+
+    // checks if given fact is in knowledge base
+    fn contains_fact(&self, fact: &Fact) -> bool {
+        self.facts.iter().fold(false, |acc, f| {
+            let temp = &**f;
+            acc || (temp.pred == fact.pred && temp.args == fact.args && temp.context.is_none())
+        })
+    }
+
+    // checks if given rule is in knowledge base, up to a renaming of its variables
+    fn contains_rule(&self, rule: &Rule) -> bool {
+        self.rules.iter().any(|r| r.is_variant(rule))
+    }
+
+    // Unifies a fact with the first premise of a rule, returning the bindings of the rule's
+    // variables. Stored facts are ground, so the rule only has to be renamed apart for the rare
+    // fact with variables, such as the instance of an aggregate over an ungrouped variable.
+    fn match_premise(&mut self, fact: &Fact, rule: &Rule) -> Option<Substitution> {
+        let premise = rule.lhs.first()?;
+        if premise.pred != fact.pred || premise.args.len() != fact.args.len() {
+            return None;
+        }
+
+        let mut unifier = Substitution::new();
+        if !fact.contains_variable() {
+            return if unifier.unify_facts(fact, premise) { Some(unifier) } else { None };
+        }
+        let renaming = self.renamer.rename_apart(rule, &self.symbols);
+        if unifier.unify_facts(fact, &renaming.apply(premise)) {
+            Some(renaming.compose(&unifier))
+        } else {
+            None
+        }
+    }
+
+    // Puts a rule on the agenda if its first premise matches the fact
+    fn activate(&mut self, fact: Rc<Fact>, rule: Rc<Rule>) {
+        if self.match_premise(&fact, &rule).is_none() {
+            return;
+        }
+
+        self.clock += 1;
+        let activation = Activation::new(self, vec![fact], Source::Rule(rule), self.clock);
+        self.queue(activation);
+    }
+
+    // Puts an activation on the agenda
+    fn queue(&mut self, activation: Activation) {
+        self.journal.record(Edit::Queued(activation.time));
+        self.agenda.push(activation);
+    }
+
+    // Puts every match of a production that has not fired yet on the agenda. With a trigger,
+    // only the matches that use the trigger fact are looked for.
+    fn match_production(&mut self, production: &Rc<Production>, trigger: Option<&Rc<Fact>>) {
+        let substitutions = match trigger {
+            None => query::solve_goals(self, &production.lhs),
+            Some(fact) => {
+                let mut substitutions = Vec::new();
+                for premise in &production.lhs {
+                    let mut unifier = Substitution::new();
+                    if !unifier.unify_facts(premise, fact) {
+                        continue;
+                    }
+                    let goals: Vec<Fact> =
+                        production.lhs.iter().map(|f| unifier.apply(f)).collect();
+                    for solution in query::solve_goals(self, &goals) {
+                        substitutions.push(unifier.compose(&solution));
+                    }
+                }
+                substitutions
+            }
+        };
+
+        for substitution in substitutions {
+            let matched: Vec<Fact> = production.lhs.iter().map(|f| substitution.apply(f)).collect();
+            let refracted = self.refracted.iter().any(|(p, facts)| {
+                Rc::ptr_eq(p, production) && *facts == matched
+            });
+            let pending = self.agenda.iter().any(|a| match a.source {
+                Source::Production(ref p, _) => {
+                    Rc::ptr_eq(p, production)
+                        && a.facts.iter().zip(&matched).all(|(f, m)| {
+                            f.pred == m.pred && f.args == m.args
+                        })
+                }
+                Source::Rule(_) => false,
+            });
+            if refracted || pending {
+                continue;
+            }
+
+            let facts = matched
+                .into_iter()
+                .map(|f| self.stored_fact(&f).unwrap_or_else(|| Rc::new(f)))
+                .collect();
+            self.clock += 1;
+            let source = Source::Production(production.clone(), substitution);
+            let activation = Activation::new(self, facts, source, self.clock);
+            self.queue(activation);
+        }
+    }
+
+    // Carries out the effects of a production for one match. The match is refracted first, so
+    // the facts the production asserts can never make the same match fire again.
+    fn fire(
+        &mut self,
+        production: Rc<Production>,
+        facts: &[Rc<Fact>],
+        substitution: &Substitution,
+    ) {
+        let matched = facts
+            .iter()
+            .map(|f| Fact::new(f.pred.clone(), f.args.clone(), vec![]))
+            .collect();
+        self.refracted.push((production.clone(), matched));
+        self.journal.record(Edit::Refracted);
+
+        for effect in &production.rhs {
+            match effect.bind(substitution) {
+                Effect::Assert(fact) => {
+                    let _ = self.add_statement(fact);
+                }
+                Effect::Retract(fact) => {
+                    // Only asserted facts can be retracted; derived ones go with their support
+                    if let Some(stored) = self.stored_fact(&fact) {
+                        if stored.asserted {
+                            let _ = self.remove_fact(&stored);
+                        }
+                    }
+                }
+                Effect::Call(name, args) => {
+                    self.journal.record(Edit::Called(name, args));
+                }
+            }
+        }
+    }
+
+    // Finds the stored fact with the same predicate and arguments as the given one
+    fn stored_fact(&self, fact: &Fact) -> Option<Rc<Fact>> {
+        self.facts_map.get(&fact.pred).and_then(|index| {
+            index
+                .facts
+                .iter()
+                .find(|f| f.args == fact.args)
+                .cloned()
+        })
+    }
+
+    // Returns when a stored fact was added, or 0 for facts that were never stored
+    fn fact_time(&self, fact: &Rc<Fact>) -> u64 {
+        self.fact_times.get(&Rc::as_ptr(fact)).cloned().unwrap_or(0)
+    }
+
+    // Returns how many derivations a stored fact is away from asserted facts. Facts that were
+    // never stored are computed from their premises, and count as asserted.
+    fn fact_depth(&self, fact: &Rc<Fact>) -> usize {
+        self.fact_depths.get(&Rc::as_ptr(fact)).cloned().unwrap_or(0)
+    }
+
+    // Returns the depth of the deepest fact a derived rule has matched so far. Asserted rules have
+    // matched nothing yet.
+    fn rule_depth(&self, rule: &Rc<Rule>) -> usize {
+        self.rule_depths.get(&Rc::as_ptr(rule)).cloned().unwrap_or(0)
+    }
+
+    // Returns the confidence in one stored fact through the single fact and rule supporting it,
+    // which are found again through the rules derived from the original rule. Facts that were
+    // never stored are computed from their premises, and are certain.
+    fn instance_confidence(&self, fact: &Rc<Fact>, memo: &mut HashMap<*const Fact, f64>) -> f64 {
+        if let Some(&confidence) = memo.get(&Rc::as_ptr(fact)) {
+            return confidence;
+        }
+
+        let confidence = match fact.supported_by.first() {
+            None => self.confidences.get(&Rc::as_ptr(fact)).cloned().unwrap_or(1.0),
+            Some((premise, rule)) => {
+                let mut conjunction = self.instance_confidence(premise, memo);
+                let mut rule = rule;
+                while let Some((premise, parent)) = rule.supported_by.first() {
+                    let confidence = self.instance_confidence(premise, memo);
+                    conjunction = self.certainty.conjunction.combine(conjunction, confidence);
+                    rule = parent;
+                }
+                self.strengths.get(&Rc::as_ptr(rule)).cloned().unwrap_or(1.0) * conjunction
+            }
+        };
+        memo.insert(Rc::as_ptr(fact), confidence);
+        confidence
+    }
+
+    // Returns the confidence in a fact from every stored fact with its predicate and arguments,
+    // or None if there are none
+    pub(crate) fn fact_confidence(&self, fact: &Fact) -> Option<f64> {
+        let disjunction = self.certainty.disjunction;
+        let mut memo = HashMap::new();
+        self.facts_map
+            .get(&fact.pred)?
+            .facts
+            .iter()
+            .filter(|f| f.args == fact.args && f.context == fact.context)
+            .map(|f| self.instance_confidence(f, &mut memo))
+            .fold(None, |acc, confidence| match acc {
+                Some(acc) => Some(disjunction.combine(acc, confidence)),
+                None => Some(confidence),
+            })
+    }
+
+    // function that implements inference by forward chaining
+    fn infer(&mut self, fact: Rc<Fact>, rule: Rc<Rule>) {
+        let substitution = match self.match_premise(&fact, &rule) {
+            Some(substitution) => substitution,
+            None => return,
+        };
+
+        // What is inferred only holds while every matched fact does, and belongs to the context
+        // of the rule or the fact that sees the other
+        let valid = match rule.valid.intersect(&fact.valid) {
+            Some(valid) => valid,
+            None => return,
+        };
+        let context = match self.inner_context(&rule.context, &fact.context) {
+            Some(context) => context,
+            None => return,
+        };
+
+        // Inference by Forward Chaining
+        // Every conclusion is supported by the same fact and rule
+        if rule.lhs.len() == 1 {
+            for conclusion in &rule.rhs {
+                let new_fact = Fact::new(
+                    conclusion.pred.clone(),
+                    substitution.apply_args(conclusion),
+                    vec![(fact.clone(), rule.clone())],
+                )
+                .valid_during(valid)
+                .in_context(context.clone());
+                if !new_fact.contains_variable() {
+                    let _ = self.add_statement(new_fact);
+                }
+            }
+        } else {
+            let new_lhs = rule.lhs[1..]
+                .iter()
+                .map(|f| substitution.apply(f))
+                .collect::<Vec<Fact>>();
+            let new_rhs = rule
+                .rhs
+                .iter()
+                .map(|f| substitution.apply(f))
+                .collect::<Vec<Fact>>();
+            let mut new_rule = Rule::new(new_lhs, new_rhs, vec![(fact.clone(), rule.clone())])
+                .with_salience(rule.salience);
+            new_rule.valid = valid;
+            new_rule.context = context;
+
+            let _ = self.add_statement(new_rule);
+        }
+    }
+
+    // Checks whether statements of a context can be seen from a scope, which is the case when the
+    // context is the scope or one of its ancestors
+    pub(crate) fn sees(&self, scope: &Option<Symbol>, context: &Option<Symbol>) -> bool {
+        let mut current = scope.clone();
+        loop {
+            if current == *context {
+                return true;
+            }
+            current = match current {
+                Some(name) => self.contexts.get(&name).cloned().flatten(),
+                None => return false,
+            };
+        }
+    }
+
+    // Returns whichever of two contexts sees the other, or None if neither does
+    fn inner_context(
+        &self,
+        a: &Option<Symbol>,
+        b: &Option<Symbol>,
+    ) -> Option<Option<Symbol>> {
+        if self.sees(a, b) {
+            Some(a.clone())
+        } else if self.sees(b, a) {
+            Some(b.clone())
+        } else {
+            None
+        }
+    }
+
+    // Looks up a named context
+    fn context_named(&self, name: &str) -> Result<Symbol, String> {
+        let symbol = self.symbols.intern(name);
+        if self.contexts.contains_key(&symbol) {
+            Ok(symbol)
+        } else {
+            Err(format!("context {} does not exist in kb", name))
+        }
+    }
+
+    // computes the instances of a premise that is evaluated rather than stored, or returns None if
+    // the premise is matched against facts
+    fn solve_computed(&self, premise: &Fact) -> Option<Vec<Fact>> {
+        if builtins::is_builtin(&premise.pred) {
+            Some(builtins::solve(premise, &self.symbols))
+        } else if aggregate::is_aggregate(premise) {
+            Some(aggregate::solve(self, premise))
+        } else {
+            None
+        }
+    }
+
+    // recomputes every rule whose first premise aggregates over the given predicate. Only the
+    // aggregate values that changed are replaced: the statements derived from an old value are
+    // retracted and the rule is matched against the new one, while unchanged values keep what
+    // was derived from them.
+    // An aggregate in a later premise is refreshed through the rules derived from the original,
+    // whose first premise it becomes once the premises before it have been matched.
+    fn refresh_aggregates(&mut self, pred: &Symbol) -> Result<(), String> {
+        let rules = match self.aggregate_rules.get(pred) {
+            Some(rules) => rules.clone(),
+            None => return Ok(()),
+        };
+
+        for rule in rules {
+            // an earlier refresh may already have retracted this rule
+            if !self.rules.iter().any(|r| Rc::ptr_eq(r, &rule)) {
+                continue;
+            }
+            let solutions = aggregate::solve(self, &rule.lhs[0]);
+            let same = |a: &Fact, b: &Fact| a.pred == b.pred && a.args == b.args;
+
+            // The values the rule was matched with, whether it already fired or not
+            let mut old: Vec<Rc<Fact>> = Vec::new();
+            let supports = self.facts.iter().flat_map(|f| f.supported_by.iter());
+            let supports = supports.chain(self.rules.iter().flat_map(|r| r.supported_by.iter()));
+            let pending = self.agenda.iter().filter_map(|a| match a.source {
+                Source::Rule(ref r) => Some((&a.facts[0], r)),
+                Source::Production(..) => None,
+            });
+            for (instance, r) in supports.map(|s| (&s.0, &s.1)).chain(pending) {
+                if Rc::ptr_eq(r, &rule) && !old.iter().any(|o| same(o, instance)) {
+                    old.push(instance.clone());
+                }
+            }
+
+            for instance in &old {
+                if solutions.iter().any(|s| same(s, instance)) {
+                    continue;
+                }
+                let derived_from = |support: &(Rc<Fact>, Rc<Rule>)| {
+                    Rc::ptr_eq(&support.1, &rule) && same(&support.0, instance)
+                };
+                let dropped = self.agenda.remove(|a| match a.source {
+                    Source::Rule(ref r) => Rc::ptr_eq(r, &rule) && same(&a.facts[0], instance),
+                    Source::Production(..) => false,
+                });
+                self.journal.record(Edit::Dropped(dropped));
+
+                let facts = self.facts.iter().filter(|f| f.supported_by.iter().any(derived_from));
+                let rules = self.rules.iter().filter(|r| r.supported_by.iter().any(derived_from));
+                let derived = facts
+                    .cloned()
+                    .map(Removal::Fact)
+                    .chain(rules.cloned().map(Removal::Rule))
+                    .collect();
+                self.remove_all(derived)?;
+            }
+
+            for solution in solutions {
+                if !old.iter().any(|o| same(o, &solution)) {
+                    self.activate(Rc::new(solution), rule.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // unifies two facts, returning what every bound variable resolves to
+    fn try_bind(&self, f1: &Fact, f2: &Fact) -> Result<HashMap<Symbol, Term>, String> {
+        let mut substitution = Substitution::new();
+        if substitution.unify_facts(f1, f2) {
+            Ok(substitution.into_map())
+        } else {
+            Err("bind failed".to_string())
+        }
+    }
+
+    /// Query a knowledge base to find all possible bindings to variables in the fact
+    ///
+    /// The given fact should contain at least one variable. Each binding lists the variables in
+    /// the order they first appear in the fact.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// // Fill the knowledge base
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (isa ?object rectangle);") {
+    ///     let result = kb.query(&fact);
+    ///     // result is now a vector of all bindings found in the knowledge base.
+    /// }
+    /// ```
+    pub fn query(&self, f: &Fact) -> Vec<QueryBinding> {
+        self.query_iter(f).collect()
+    }
+
+    /// Query a knowledge base for the bindings of facts that are valid at a point in time
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (at guard gate) from: 10 to: 40;") {
+    ///     kb.assert(fact).unwrap();
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (at guard ?place);") {
+    ///     assert_eq!(kb.query_at(&fact, 20).len(), 1);
+    ///     assert!(kb.query_at(&fact, 40).is_empty());
+    /// }
+    /// ```
+    pub fn query_at(&self, f: &Fact, time: i64) -> Vec<QueryBinding> {
+        self.query_iter(f).at(time).collect()
+    }
+
+    /// Query a knowledge base for the bindings of facts that are valid at some point during an
+    /// interval
+    pub fn query_during(&self, f: &Fact, interval: Interval) -> Vec<QueryBinding> {
+        self.query_iter(f).during(interval).collect()
+    }
+
+    /// Returns the intervals during which a fact is known to be valid, in order of their start
+    ///
+    /// The interval the given fact was created with is ignored. Overlapping intervals, such as
+    /// those of a fact that was both asserted and derived, are returned separately.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rust_kb::{Interval, KnowledgeBase};
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// if let Ok(rule) = kb.create_rule("rule: ((at ?x gate) (alarm)) -> (alert ?x);") {
+    ///     kb.assert(rule).unwrap();
+    /// }
+    /// for fact in &["fact: (at guard gate) from: 10 to: 40;", "fact: (alarm) from: 30;"] {
+    ///     if let Ok(fact) = kb.create_fact(fact) {
+    ///         kb.assert(fact).unwrap();
+    ///     }
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (alert guard);") {
+    ///     assert_eq!(kb.intervals(&fact), vec![Interval::new(Some(30), Some(40))]);
+    /// }
+    /// ```
+    pub fn intervals(&self, fact: &Fact) -> Vec<Interval> {
+        let mut intervals: Vec<Interval> = match self.facts_map.get(&fact.pred) {
+            Some(index) => index
+                .facts
+                .iter()
+                .filter(|f| f.args == fact.args && f.context == fact.context)
+                .map(|f| f.valid)
+                .collect(),
+            None => Vec::new(),
+        };
+        intervals.sort_by_key(|i| (i.start().is_some(), i.start(), i.end().is_none(), i.end()));
+        intervals
+    }
+
+    /// Query a knowledge base for the bindings of facts with at least the given confidence
+    ///
+    /// Facts computed by builtins and aggregates are certain.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// let facts = [("fact: (hides thief barn);", 0.3), ("fact: (hides thief mill);", 0.9)];
+    /// for (fact, confidence) in &facts {
+    ///     if let Ok(fact) = kb.create_fact(fact) {
+    ///         kb.assert_with_confidence(fact, *confidence).unwrap();
+    ///     }
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (hides thief ?place);") {
+    ///     assert_eq!(kb.query_with_confidence(&fact, 0.5).len(), 1);
+    /// }
+    /// ```
+    pub fn query_with_confidence(&self, f: &Fact, threshold: f64) -> Vec<QueryBinding> {
+        self.query_iter(f).min_confidence(threshold).collect()
+    }
+
+    /// Query a named context, matching its own facts and those of its ancestors
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.create_context("guard", None).unwrap();
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (hides thief barn);") {
+    ///     kb.assert(fact).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (hides thief mill);") {
+    ///     kb.assert_in("guard", fact).unwrap();
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (hides thief ?place);") {
+    ///     assert_eq!(kb.query_in("guard", &fact).unwrap().len(), 2);
+    ///     assert_eq!(kb.query(&fact).len(), 1);
+    /// }
+    /// ```
+    pub fn query_in(&self, context: &str, f: &Fact) -> Result<Vec<QueryBinding>, String> {
+        let context = self.context_named(context)?;
+        Ok(self.query_iter(f).in_context(context).collect())
+    }
+
+    /// Query a knowledge base lazily, producing bindings one at a time
+    ///
+    /// Unlike `query`, no bindings are computed until the returned iterator is advanced, and
+    /// candidate facts are read directly from the knowledge base's index. Combine with
+    /// `QueryIter::offset` and `QueryIter::limit`, or any iterator adapter, to stop early.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// // Fill the knowledge base
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (isa ?object rectangle);") {
+    ///     let first = kb.query_iter(&fact).next();
+    ///     // first is the first binding found, if any
+    /// }
+    /// ```
+    pub fn query_iter<'a>(&'a self, f: &'a Fact) -> QueryIter<'a> {
+        QueryIter::new(self, f)
+    }
+
+    /// Query a knowledge base and collect the answers into a table
+    ///
+    /// The table has one column per variable of the fact, in the order the variables first appear,
+    /// so every row lines up with the header. See `QueryResult` for lookup and sorting.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// // Fill the knowledge base
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (isa ?object ?shape);") {
+    ///     for row in &kb.query_result(&fact) {
+    ///         let object = row.get("?object");
+    ///     }
+    /// }
+    /// ```
+    pub fn query_result(&self, f: &Fact) -> QueryResult {
+        QueryResult::from(self.query_iter(f))
+    }
+
+    /// Attempts to create an action schema from a given string slice.
+    ///
+    /// The action is written as its name and parameters, followed by its preconditions after
+    /// "pre:", the facts it adds after "add:" and the facts it deletes after "del:". Every part
+    /// but the name is optional. Every parameter has to be bound by a precondition, and the
+    /// effects may only use parameters. In this context, the action must be terminated by a
+    /// semicolon.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// match kb.create_action("action: (go ?from ?to) pre: ((at ?from) (path ?from ?to)) \
+    ///                         add: ((at ?to)) del: ((at ?from));") {
+    ///     Ok(action) => { /* Will execute this branch */ },
+    ///     Err(_) => { /* Will not execute this branch, because of proper format */ },
+    /// }
+    /// ```
+    pub fn create_action(&mut self, action: &str) -> Result<ActionSchema, String> {
+        let pa = parse_action(action.as_bytes())?;
+        ActionSchema::from(&pa, &self.symbols)
+    }
+
+    /// Returns the action schemas plans can use
+    pub fn actions(&self) -> &[ActionSchema] {
+        &self.actions
+    }
+
+    /// Adds an action schema that plans can use
+    ///
+    /// An error is returned if an action with the same name is already in the knowledge base.
+    pub fn add_action(&mut self, action: ActionSchema) -> Result<(), String> {
+        if self.actions.iter().any(|a| a.name() == action.name()) {
+            return Err(format!("action {} already in kb", action.name()));
+        }
+        self.actions.push(action);
+        Ok(())
+    }
+
+    /// Takes an action, binding its parameters to the given values in order
+    ///
+    /// The preconditions are checked first, and if one of them does not hold the error names it.
+    /// Otherwise the facts of the delete list are retracted and the facts of the add list are
+    /// asserted, along with everything inferred from them. The effects are applied as a whole:
+    /// constraints are only checked once all of them are in place, and if a constraint is
+    /// violated, or an effect cannot be added, the knowledge base is left as it was before the
+    /// action. Deleting a fact that is not asserted, or adding one that already is, does nothing.
+    ///
+    /// Each value is written as it would be in a fact, so lists and compound terms can be passed.
+    /// Values may not contain variables.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// let go = kb
+    ///     .create_action("action: (go ?p ?from ?to) pre: ((at ?p ?from) (path ?from ?to)) \
+    ///                     add: ((at ?p ?to)) del: ((at ?p ?from));")
+    ///     .unwrap();
+    ///
+    /// for statement in &["fact: (at player kitchen);", "fact: (path kitchen hall);"] {
+    ///     if let Ok(fact) = kb.create_fact(statement) {
+    ///         kb.assert(fact).unwrap();
+    ///     }
+    /// }
+    ///
+    /// assert!(kb.apply(&go, &["player", "kitchen", "hall"]).is_ok());
+    /// assert!(kb.apply(&go, &["player", "kitchen", "hall"]).is_err());
+    /// ```
+    pub fn apply(&mut self, action: &ActionSchema, bindings: &[&str]) -> Result<(), String> {
+        let mut args = Vec::new();
+        for binding in bindings {
+            let term = parse_term(binding.as_bytes())?;
+            args.push(Term::from(&term, &self.symbols));
+        }
+        let call = Fact::new(self.intern_string(action.name()), args, vec![]);
+        self.apply_call(action, &call)
+    }
+
+    /// Takes an action found by the planner
+    ///
+    /// The action's schema has to be one of the knowledge base's actions. See `apply`.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+    ///
+    /// if let Ok(goal) = kb.create_fact("fact: (on d c);") {
+    ///     for action in kb.plan(&[goal.clone()]).unwrap() {
+    ///         kb.execute(&action).unwrap();
+    ///     }
+    ///     assert_eq!(kb.ask(&goal), Ok(true));
+    /// }
+    /// ```
+    pub fn execute(&mut self, action: &Action) -> Result<(), String> {
+        let schema = match self.actions.iter().find(|a| a.name() == action.name()) {
+            Some(schema) => schema.clone(),
+            None => return Err(format!("action {} does not exist in kb", action.name())),
+        };
+        let call = Fact::new(
+            self.intern_string(action.name()),
+            action.args().to_vec(),
+            vec![],
+        );
+        self.apply_call(&schema, &call)
+    }
+
+    // checks the preconditions of an action called with the given arguments and applies its
+    // effects, undoing all of them if one cannot be added or the result violates a constraint
+    fn apply_call(&mut self, action: &ActionSchema, call: &Fact) -> Result<(), String> {
+        if call.contains_variable() {
+            return Err(format!("Cannot take action {} with unbound variables", call));
+        }
+        let substitution = action.bind(call)?;
+        action.check_preconditions(self, &substitution)?;
+        let (del, add) = action.effects(&substitution);
+
+        self.transaction(|kb| {
+            for fact in del {
+                // Deleting a fact that does not hold leaves nothing to remove
+                if kb.facts.iter().any(|f| **f == fact) {
+                    kb.remove_fact(&fact)?;
+                }
+            }
+            for fact in add {
+                match kb.add_statement(fact) {
+                    Ok(_) => {}
+                    Err(ref e) if e == "fact already in kb" => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            kb.run_or_check()
+        })
+    }
+
+    /// Finds a shortest sequence of actions that makes every fact of the goal hold
+    ///
+    /// The search starts from the current facts of the knowledge base and explores states breadth
+    /// first. Goal facts may contain variables, and may use facts that rules infer. States that
+    /// violate a constraint are never entered. The knowledge base itself is left unchanged. An
+    /// error is returned if no sequence of actions reaches the goal, or if the search looks at
+    /// more states than the limit set with `set_plan_limit` without reaching it.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+    ///
+    /// if let Ok(goal) = kb.create_fact("fact: (on d c);") {
+    ///     let plan = kb.plan(&[goal]).unwrap();
+    ///     for action in &plan {
+    ///         println!("{}", action);
+    ///     }
+    /// }
+    /// ```
+    pub fn plan(&self, goal: &[Fact]) -> Result<Vec<Action>, String> {
+        self.plan_with(goal, &Search::BreadthFirst)
+    }
+
+    /// Finds a sequence of actions that makes every fact of the goal hold, using the given search
+    ///
+    /// `Search::AStar` takes a heuristic estimating the number of actions left from a state. The
+    /// plan is only guaranteed to be shortest when the heuristic never overestimates, which
+    /// `unsatisfied_goals` can.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::{Fact, KnowledgeBase, PlanState, Search};
+    ///
+    /// let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+    ///
+    /// // No rule derives on, so at least one action is left while the goal is not asserted
+    /// let one_left = |state: &PlanState, goal: &[Fact]| {
+    ///     if goal.iter().all(|fact| state.contains(fact)) { 0 } else { 1 }
+    /// };
+    ///
+    /// if let Ok(goal) = kb.create_fact("fact: (on d c);") {
+    ///     let plan = kb.plan_with(&[goal.clone()], &Search::AStar(&one_left)).unwrap();
+    ///     assert_eq!(plan.len(), kb.plan(&[goal]).unwrap().len());
+    /// }
+    /// ```
+    pub fn plan_with(&self, goal: &[Fact], search: &Search) -> Result<Vec<Action>, String> {
+        plan::search(self, goal, search)
+    }
+
+    /// Sets how many states `plan` and `plan_with` may look at before giving up
+    ///
+    /// Once the search has looked at this many states without reaching the goal, it returns an
+    /// error. The default is 100000 states. With None, the search goes on until it has looked at
+    /// every reachable state, which may never happen.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+    /// kb.set_plan_limit(Some(1));
+    ///
+    /// if let Ok(goal) = kb.create_fact("fact: (on d c);") {
+    ///     assert!(kb.plan(&[goal]).is_err());
+    /// }
+    /// ```
+    pub fn set_plan_limit(&mut self, max_states: Option<usize>) {
+        self.plan_limit = max_states;
+    }
+
+    /// Returns how many states the planner may look at before giving up
+    pub fn plan_limit(&self) -> Option<usize> {
+        self.plan_limit
+    }
+
+    /// Sets the strategy that orders activations of equal salience on the agenda
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::{KnowledgeBase, Strategy};
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.set_strategy(Strategy::Breadth);
+    /// assert_eq!(kb.strategy(), Strategy::Breadth);
+    /// ```
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.agenda.set_strategy(strategy);
+    }
+
+    /// Returns the strategy that orders activations of equal salience on the agenda
+    pub fn strategy(&self) -> Strategy {
+        self.agenda.strategy()
+    }
+
+    /// Sets whether asserting and retracting statements fires the agenda until it is empty
+    ///
+    /// Inference runs automatically by default. When it is turned off, activations wait on the
+    /// agenda until `step`, `run` or `run_inference` fires them, and asserting a statement only
+    /// checks constraints against what has been inferred so far. `run` and `run_inference` check
+    /// them again against what they infer.
+    pub fn set_auto_run(&mut self, auto_run: bool) {
+        self.auto_run = auto_run;
+    }
+
+    /// Returns the activations waiting on the agenda, in the order they will fire
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.set_auto_run(false);
+    ///
+    /// if let Ok(rule) = kb.create_rule("rule: ((isa ?x dog)) -> (isa ?x animal) salience: 5;") {
+    ///     kb.assert(rule).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (isa fido dog);") {
+    ///     kb.assert(fact).unwrap();
+    /// }
+    ///
+    /// let agenda = kb.agenda();
+    /// assert_eq!(agenda.len(), 1);
+    /// assert_eq!(agenda[0].salience(), 5);
+    /// ```
+    pub fn agenda(&self) -> Vec<Activation> {
+        self.agenda.sorted()
+    }
+
+    /// Fires the next activation on the agenda and returns it, or None if the agenda is empty
+    ///
+    /// Firing an activation can put new activations on the agenda. Constraints are not checked.
+    pub fn step(&mut self) -> Option<Activation> {
+        self.transaction(|kb| Ok(kb.fire_next())).unwrap_or(None)
+    }
+
+    // Fires the next activation on the agenda and returns it
+    fn fire_next(&mut self) -> Option<Activation> {
+        let activation = self.agenda.pop()?;
+        self.journal.record(Edit::Fired(activation.clone()));
+        match activation.source {
+            Source::Rule(ref rule) => self.infer(activation.facts[0].clone(), rule.clone()),
+            Source::Production(ref production, ref substitution) => {
+                self.fire(production.clone(), &activation.facts, substitution)
+            }
+        }
+        Some(activation)
+    }
+
+    /// Fires activations until the agenda is empty, and returns how many fired
+    ///
+    /// An error starting with "Inference limit exceeded" is returned as soon as the run goes past
+    /// one of the limits set with `set_inference_limits`. Once the agenda is empty, constraints
+    /// are checked against everything inferred, and an error names the first one violated. Either
+    /// way, everything the run did is undone, leaving the agenda as it was.
+    pub fn run(&mut self) -> Result<usize, String> {
+        self.transaction(|kb| kb.run_within(None))
+            .map(|progress| progress.fired)
+    }
+
+    /// Fires activations until the agenda is empty or the budget is spent
+    ///
+    /// Together with `set_auto_run(false)`, which makes asserting and retracting statements only
+    /// put work on the agenda, this spreads inference over several calls, such as one per frame of
+    /// a game. The limits set with `set_inference_limits` apply to each call, and constraints are
+    /// checked at the end of each call, which is undone if it goes past a limit or violates a
+    /// constraint.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::{Budget, KnowledgeBase};
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.set_auto_run(false);
+    ///
+    /// let rule = "rule: ((parent ?x ?y) (parent ?y ?z)) -> (grandparent ?x ?z);";
+    /// if let Ok(rule) = kb.create_rule(rule) {
+    ///     kb.assert(rule).unwrap();
+    /// }
+    /// for fact in &["fact: (parent ann bob);", "fact: (parent bob cid);"] {
+    ///     if let Ok(fact) = kb.create_fact(fact) {
+    ///         kb.assert(fact).unwrap();
+    ///     }
+    /// }
+    ///
+    /// let progress = kb.run_inference(Budget::Steps(1)).unwrap();
+    /// assert!(!progress.fixpoint);
+    ///
+    /// while !kb.run_inference(Budget::Steps(1)).unwrap().fixpoint {}
+    /// let grandparent = kb.create_fact("fact: (grandparent ann cid);").unwrap();
+    /// assert_eq!(kb.ask(&grandparent), Ok(true));
+    /// ```
+    pub fn run_inference(&mut self, budget: Budget) -> Result<Progress, String> {
+        self.transaction(|kb| kb.run_within(Some(budget)))
+    }
+
+    // Fires activations until the agenda is empty, the budget is spent, or a limit is exceeded
+    fn run_within(&mut self, budget: Option<Budget>) -> Result<Progress, String> {
+        let started = Instant::now();
+        let added_before = self.inserted;
+        self.deepest = 0;
+
+        let mut fired = 0;
+        while budget.is_none_or(|b| b.allows(fired, started)) && self.fire_next().is_some() {
+            fired += 1;
+            self.limits.check(self, started, added_before)?;
+        }
+        self.check_constraints()?;
+        Ok(Progress {
+            fired,
+            fixpoint: self.agenda.is_empty(),
+        })
+    }
+
+    // Runs the agenda if inference runs automatically
+    fn run_if_auto(&mut self) -> Result<(), String> {
+        if self.auto_run {
+            self.run()?;
+        }
+        Ok(())
+    }
+
+    // Runs the agenda if inference runs automatically, which checks constraints once it is done.
+    // Otherwise constraints are checked against what has been inferred so far.
+    fn run_or_check(&mut self) -> Result<(), String> {
+        if self.auto_run {
+            self.run().map(|_| ())
+        } else {
+            self.check_constraints()
+        }
+    }
+
+    /// Calls the listener whenever a fact matching the pattern is asserted, derived or retracted
+    ///
+    /// Facts retracted because the facts or rules supporting them were are reported too, as are
+    /// facts replaced through a key and facts a failed assert rolls back. The listener receives
+    /// the bindings of the pattern's variables, in the order they first appear in the pattern.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::{Change, KnowledgeBase};
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// let seen = Rc::new(RefCell::new(Vec::new()));
+    ///
+    /// if let Ok(pattern) = kb.create_fact("fact: (sees guard ?who);") {
+    ///     let log = seen.clone();
+    ///     let subscription = kb.subscribe(&pattern, move |change, _, bindings| {
+    ///         log.borrow_mut().push((change, bindings[0].1.to_string()));
+    ///     });
+    ///
+    ///     if let Ok(fact) = kb.create_fact("fact: (sees guard player);") {
+    ///         kb.assert(fact.clone()).unwrap();
+    ///         kb.retract(fact).unwrap();
+    ///     }
+    ///     kb.unsubscribe(subscription).unwrap();
+    /// }
+    ///
+    /// let player = String::from("player");
+    /// assert_eq!(
+    ///     *seen.borrow(),
+    ///     vec![(Change::Asserted, player.clone()), (Change::Retracted, player)]
+    /// );
+    /// ```
+    pub fn subscribe<F>(&mut self, pattern: &Fact, listener: F) -> Subscription
+    where
+        F: FnMut(Change, &Fact, &[(Symbol, Term)]) + 'static,
+    {
+        self.subscriptions.add(pattern.clone(), Box::new(listener))
+    }
+
+    /// Stops calling the listener of a subscription
+    ///
+    /// An error is returned if the subscription was already removed.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> Result<(), String> {
+        if self.subscriptions.remove(subscription) {
+            Ok(())
+        } else {
+            Err(String::from("subscription does not exist in kb"))
+        }
+    }
+
+    /// Watches the answers to a query as the knowledge base changes
+    ///
+    /// The watch is maintained incrementally from the facts that are asserted, derived and
+    /// retracted, so the query is never run again. See `QueryWatch`. Use `unwatch_query` to stop
+    /// maintaining it.
+    ///
+    /// The answers of builtins and aggregates are computed rather than stored, so no fact ever
+    /// changes them, and watching one is an error.
+    pub fn watch_query(&mut self, pattern: &Fact) -> Result<QueryWatch, String> {
+        if builtins::is_builtin(&pattern.pred) || aggregate::is_aggregate(pattern) {
+            return Err(format!("Cannot watch {}, whose answers are computed", pattern));
+        }
+
+        let state = Rc::new(RefCell::new(WatchState::new(query::pattern_variables(pattern))));
+        for binding in self.query_iter(pattern) {
+            let values = binding.into_iter().map(|(_, value)| value).collect();
+            state.borrow_mut().record(values, true);
+        }
+
+        let watched = Rc::downgrade(&state);
+        let subscription = self.subscribe(pattern, move |change, _, bindings| {
+            if let Some(state) = watched.upgrade() {
+                let values = bindings.iter().map(|(_, value)| value.clone()).collect();
+                state.borrow_mut().record(values, change != Change::Retracted);
+            }
+        });
+        Ok(QueryWatch::new(subscription, state))
+    }
+
+    /// Stops maintaining a watched query
+    pub fn unwatch_query(&mut self, watch: QueryWatch) -> Result<(), String> {
+        self.unsubscribe(watch.subscription())
+    }
+
+    /// Creates a named context, whose statements are layered on those of its parent
+    ///
+    /// Statements added with `assert` belong to the shared context, which is the ancestor of
+    /// every named context. A context sees its own statements and those of its ancestors, but not
+    /// those of its siblings. A rule fires on the facts its context sees, as well as on the facts
+    /// of descendants that see the rule, and what it infers belongs to whichever of the two
+    /// contexts is deeper. Creating a context copies nothing.
+    ///
+    /// Productions, constraints, aggregates and subscriptions only see facts of the shared
+    /// context. Keys hold within each context: a fact only replaces the facts with the same key
+    /// that belong to its own context, so a private belief never retracts a shared fact.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.create_context("guards", None).unwrap();
+    /// kb.create_context("gate guard", Some("guards")).unwrap();
+    ///
+    /// if let Ok(rule) = kb.create_rule("rule: ((hides thief ?x)) -> (search ?x);") {
+    ///     kb.assert_in("guards", rule).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (hides thief barn);") {
+    ///     kb.assert_in("gate guard", fact).unwrap();
+    /// }
+    ///
+    /// if let Ok(fact) = kb.create_fact("fact: (search barn);") {
+    ///     assert_eq!(kb.ask_in("gate guard", &fact), Ok(true));
+    ///     assert_eq!(kb.ask_in("guards", &fact), Ok(false));
+    /// }
+    /// ```
+    pub fn create_context(&mut self, name: &str, parent: Option<&str>) -> Result<(), String> {
+        let parent = match parent {
+            Some(parent) => Some(self.context_named(parent)?),
+            None => None,
+        };
+        let name = self.intern_string(name);
+        if self.contexts.contains_key(&name) {
+            return Err(format!("context {} already exists in kb", &*name));
+        }
+        self.contexts.insert(name, parent);
+        Ok(())
+    }
+
+    /// Returns the parent of a named context, or None if its parent is the shared context
+    pub fn context_parent(&self, name: &str) -> Result<Option<Symbol>, String> {
+        let name = self.context_named(name)?;
+        Ok(self.contexts[&name].clone())
+    }
+
+    /// Sets the limits on the work each run of the agenda may do
+    ///
+    /// When asserting or retracting a statement, adding a production, taking an action or moving
+    /// time forward makes inference go past a limit, everything that change did is undone and
+    /// the error says which limit was exceeded. Neither inference nor retraction is recursive, so
+    /// rules that derive facts without end run until a limit stops them, and retracting the start
+    /// of a long chain of derivations does not overflow the stack.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::{InferenceLimits, KnowledgeBase};
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// kb.set_inference_limits(InferenceLimits {
+    ///     max_depth: Some(10),
+    ///     ..Default::default()
+    /// });
+    ///
+    /// if let Ok(rule) = kb.create_rule("rule: ((number ?n)) -> (number (next ?n));") {
+    ///     kb.assert(rule).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (number zero);") {
+    ///     assert!(kb.assert(fact).is_err());
+    /// }
+    /// ```
+    pub fn set_inference_limits(&mut self, limits: InferenceLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the limits on the work each run of the agenda may do
+    pub fn inference_limits(&self) -> InferenceLimits {
+        self.limits
+    }
+
+    /// Attempts to create a production from a given string slice.
+    ///
+    /// A production is written like a rule but concludes one or more effects: facts to assert,
+    /// (retract (fact)) and (call name args...). See `add_production` and `register_callback`.
+    /// If the production is ill-formatted, uses a variable in an effect that no premise binds, or
+    /// does not fit the declared predicates, the function will return an error. In this context,
+    /// the production must be terminated by a semicolon.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// match kb.create_production("production: ((alarm ?x)) -> (fled ?x) (retract (alarm ?x));") {
+    ///     Ok(production) => { /* Will execute this branch */ },
+    ///     Err(_) => { /* Will not execute this branch, because of proper format */ },
+    /// }
+    /// ```
+    pub fn create_production(&mut self, production: &str) -> Result<Production, String> {
+        let pp = parse_production(production.as_bytes())?;
+        let production = Production::from(&pp, &self.symbols)?;
+        self.check_production(&production)?;
+        Ok(production)
+    }
+
+    /// Returns the productions of the knowledge base
+    pub fn productions(&self) -> Vec<&Production> {
+        self.productions.iter().map(|p| &**p).collect()
+    }
+
+    /// Adds a production, putting every current match of its premises on the agenda
+    ///
+    /// A production fires at most once for each match, until one of the matched facts is
+    /// retracted. Its effects are part of the assert, retract or run that made it fire: when that
+    /// fails a constraint check or goes past a limit, the facts the production asserted or
+    /// retracted are undone with everything else, and its calls are never made. Calls are only
+    /// made once the change has succeeded. An error is returned if the production is already in
+    /// the knowledge base.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    ///
+    /// let production = "production: ((alarm ?x)) -> (fled ?x) (retract (alarm ?x));";
+    /// if let Ok(production) = kb.create_production(production) {
+    ///     kb.add_production(production).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (alarm fire);") {
+    ///     kb.assert(fact).unwrap();
+    /// }
+    ///
+    /// let fled = kb.create_fact("fact: (fled fire);").unwrap();
+    /// let alarm = kb.create_fact("fact: (alarm fire);").unwrap();
+    /// assert_eq!(kb.ask(&fled), Ok(true));
+    /// assert_eq!(kb.ask(&alarm), Ok(false));
+    /// ```
+    pub fn add_production(&mut self, production: Production) -> Result<(), String> {
+        if self.productions.iter().any(|p| **p == production) {
+            return Err(String::from("production already in kb"));
+        }
+        let asserted: Vec<&Fact> = production.asserted_facts().collect();
+        self.check_stratified(&production.lhs, &asserted)?;
+
+        let production = Rc::new(production);
+        self.productions.push(production.clone());
+        let added = self.transaction(|kb| {
+            kb.match_production(&production, None);
+            kb.run_if_auto()
+        });
+        if added.is_err() {
+            self.productions.pop();
+        }
+        added
+    }
+
+    /// Registers a callback that productions can call with `(call name args...)`
+    ///
+    /// The callback receives the arguments of the call with the bindings of the match applied.
+    /// Callbacks are called in the order the productions fired, once the change that made them
+    /// fire has succeeded, so a change that is undone calls nothing. Registering a callback under
+    /// a name that is already taken replaces the old one. Calls to a name with no callback do
+    /// nothing.
+    ///
+    ///  # Example
+    ///
+    /// ```
+    /// use rust_kb::KnowledgeBase;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// let mut kb = KnowledgeBase::new();
+    /// let greeted = Rc::new(RefCell::new(Vec::new()));
+    ///
+    /// let log = greeted.clone();
+    /// kb.register_callback("greet", move |args| log.borrow_mut().push(args[0].to_string()));
+    ///
+    /// let production = "production: ((visitor ?x)) -> (call greet ?x);";
+    /// if let Ok(production) = kb.create_production(production) {
+    ///     kb.add_production(production).unwrap();
+    /// }
+    /// if let Ok(fact) = kb.create_fact("fact: (visitor ann);") {
+    ///     kb.assert(fact).unwrap();
+    /// }
+    ///
+    /// assert_eq!(*greeted.borrow(), vec!["ann"]);
+    /// ```
+    pub fn register_callback<F>(&mut self, name: &str, callback: F)
+    where
+        F: FnMut(&[Term]) + 'static,
+    {
+        let name = self.intern_string(name);
+        self.callbacks.register(name, Box::new(callback));
+    }
+}
+
+#[cfg(test)]
+mod knowledge_base_basic_tests {
+    use super::*;
+
+    // Asserts a fact or rule written as in a knowledge base file, failing if it is rejected
+    pub fn assert_statement(kb: &mut KnowledgeBase, statement: &str) {
+        if statement.starts_with("rule:") {
+            let rule = kb.create_rule(statement).unwrap();
+            assert!(kb.assert(rule).is_ok());
+        } else {
+            let fact = kb.create_fact(statement).unwrap();
+            assert!(kb.assert(fact).is_ok());
+        }
+    }
+
+    // Asserts a fact written without its "fact:" prefix and semicolon
+    pub fn assert_fact(kb: &mut KnowledgeBase, fact: &str) -> Result<(), String> {
+        let fact = kb.create_fact(&format!("fact: {};", fact))?;
+        kb.assert(fact).map(|_| ())
+    }
+
+    #[test]
+    fn test_add_fact() {
+        let mut kb = KnowledgeBase::new();
+
+        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
+            match kb.add_fact(new_fact.clone()) {
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+
+            assert!(kb.contains_fact(&new_fact));
+        }
+    }
+
+    #[test]
+    fn test_remove_fact() {
+        let mut kb = KnowledgeBase::new();
+
+        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
+            match kb.add_fact(new_fact.clone()) {
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+
+            match kb.remove_fact(&new_fact) {
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+
+            assert!(!kb.contains_fact(&new_fact));
+            assert!(kb.facts.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_ask_fact_already_in_kb() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
+            match kb.add_fact(new_fact.clone()) {
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+            assert_eq!(kb.ask(&new_fact), Ok(true));
+        }
+    }
+
+    #[test]
+    fn test_ask_fact_not_in_fb() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
+            assert_eq!(kb.ask(&new_fact), Ok(false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod inference_tests {
+    use super::query_tests::rendered_rows;
+    use super::*;
+
+    #[test]
+    fn test_assert_and_infer() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
+            match kb.assert(new_fact.clone()) {
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+
+            if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy)) -> (cool ?x);") {
+                match kb.assert(new_rule.clone()) {
+                    Ok(_) => {}
+                    Err(e) => println!("{}", e),
+                }
+
+                if let Ok(result_fact) = kb.create_fact("fact: (cool Bob);") {
+                    assert!(kb.contains_fact(&new_fact));
+                    assert!(kb.contains_rule(&new_rule));
+                    assert!(kb.contains_fact(&result_fact));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_infer_rule() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
+            match kb.assert(new_fact.clone()) {
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+
+            if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy) (was ?x ?y)) -> (cool ?y);") {
+                match kb.assert(new_rule.clone()) {
+                    Ok(_) => {}
+                    Err(e) => println!("{}", e),
+                }
+
+                if let Ok(result_rule) = kb.create_rule("rule: ((was Bob ?y)) -> (cool ?y);") {
+                    assert!(kb.contains_fact(&new_fact));
+                    assert!(kb.contains_rule(&new_rule));
+                    assert!(kb.contains_rule(&result_rule));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bind() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(fact1) = kb.create_fact("fact: (isa Bob boy);") {
+            if let Ok(fact2) = kb.create_fact("fact: (isa ?x boy);") {
+                let bindings = match kb.try_bind(&fact1, &fact2) {
+                    Ok(lst) => lst,
+                    Err(e) => {
+                        println!("{}", e);
+                        HashMap::new()
+                    }
+                };
+
+                assert!(bindings.contains_key(&kb.intern_string("?x")));
+
+                if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy)) -> (cool ?x);") {
+                    let result_fact = Substitution::from_map(&bindings).apply(&new_rule.rhs[0]);
+
+                    assert_eq!(
+                        result_fact,
+                        Fact::new(
+                            kb.intern_string("cool"),
+                            vec![Term::Symbol(kb.intern_string("Bob"))],
+                            vec![]
+                        )
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_retract_inferred() {
+        let mut kb = KnowledgeBase::new();
+
+        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
+            let rc_fact = match kb.assert(new_fact.clone()) {
+                Ok(_f) => Some(_f),
+                Err(e) => {
+                    println!("{}", e);
+                    None
+                }
+            };
+
+            if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy)) -> (cool ?x);") {
+                let rc_rule = match kb.assert(new_rule.clone()) {
+                    Ok(_r) => Some(_r),
+                    Err(e) => {
+                        println!("{}", e);
+                        None
+                    }
+                };
+
+                let result_fact = Fact::new(
+                    kb.intern_string("cool"),
+                    vec![Term::Symbol(kb.intern_string("Bob"))],
+                    vec![
+                        (
+                            Rc::new(rc_fact.unwrap().to_fact().unwrap()),
+                            Rc::new(rc_rule.unwrap().to_rule().unwrap()),
+                        ),
+                    ],
+                );
+                assert!(kb.contains_fact(&new_fact));
+                assert!(kb.contains_rule(&new_rule));
+                assert!(kb.contains_fact(&result_fact));
+
+                for f in kb.facts.iter() {
+                    println!("{:?}\n\n", f)
+                }
+
+                assert!(kb.retract(new_fact.clone()).is_ok());
+
+                assert!(!kb.contains_fact(&new_fact));
+                assert!(kb.contains_rule(&new_rule));
+                assert!(!kb.contains_fact(&result_fact));
+            }
+        }
+    }
+
+    #[test]
+    fn test_infer_shared_variables() {
+        let mut kb = KnowledgeBase::new();
+        let statements = [
+            "rule: ((parent ?x ?y) (parent ?y ?z)) -> (grandparent ?x ?z);",
+            "rule: ((parent ?y ?x)) -> (child ?x ?y);",
+        ];
+        for statement in statements.iter() {
+            if let Ok(rule) = kb.create_rule(statement) {
+                assert!(kb.assert(rule).is_ok());
+            }
+        }
+
+        for statement in &["ann bob", "bob cal", "cal ann"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: (parent {});", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (grandparent ?x ?z);") {
+            let mut result = kb.query_result(&f);
+            result.sort();
+            let rows = rendered_rows(&result);
+            assert_eq!(
+                rows,
+                vec![vec!["ann", "cal"], vec!["bob", "ann"], vec!["cal", "bob"]]
+            );
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (child bob ann);") {
+            assert_eq!(kb.ask(&f), Ok(true));
+        }
+    }
+
+    #[test]
+    fn test_infer_multiple_conclusions() {
+        let mut kb = KnowledgeBase::new();
+        let statements = [
+            "rule: ((parent ?x ?y)) -> (ancestor ?x ?y) (related ?x ?y);",
+            "rule: ((parent ?x ?y) (parent ?y ?z)) -> (ancestor ?x ?z) (related ?x ?z);",
+        ];
+        for statement in statements.iter() {
+            if let Ok(rule) = kb.create_rule(statement) {
+                assert!(kb.assert(rule).is_ok());
+            }
+        }
+        for statement in &["ann bob", "bob cal"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: (parent {});", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        for pred in &["ancestor", "related"] {
+            if let Ok(f) = kb.create_fact(&format!("fact: ({} ?x ?y);", pred)) {
+                let mut result = kb.query_result(&f);
+                result.sort();
+                assert_eq!(
+                    rendered_rows(&result),
+                    vec![vec!["ann", "bob"], vec!["ann", "cal"], vec!["bob", "cal"]]
+                );
+            }
+        }
+
+        // Both conclusions share the support of the retracted fact
+        if let Ok(fact) = kb.create_fact("fact: (parent bob cal);") {
+            assert!(kb.retract(fact).is_ok());
+        }
+        for pred in &["ancestor", "related"] {
+            if let Ok(f) = kb.create_fact(&format!("fact: ({} ?x ?y);", pred)) {
+                assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["ann", "bob"]]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod query_tests {
+    use super::*;
+
+    // Renders every row of a query result so it can be compared against string literals
+    pub fn rendered_rows(result: &QueryResult) -> Vec<Vec<String>> {
+        result
+            .rows()
+            .iter()
+            .map(|row| row.values().iter().map(|value| value.to_string()).collect())
+            .collect()
+    }
+
+    // Renders the rows answering a pattern written without its "fact:" prefix and semicolon
+    pub fn rows(kb: &mut KnowledgeBase, pattern: &str) -> Vec<Vec<String>> {
+        match kb.create_fact(&format!("fact: {};", pattern)) {
+            Ok(f) => rendered_rows(&kb.query_result(&f)),
+            Err(_) => vec![],
+        }
+    }
+
+    #[test]
+    fn empty_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(f) = kb.create_fact("fact: (isa ?a ?b);") {
+            let a = kb.query(&f);
+            let b: Vec<QueryBinding> = vec![];
+
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn single_binding_test() {
+        let mut kb = KnowledgeBase::new();
+
+        if let Ok(f1) = kb.create_fact("fact: (isa a b);") {
+            if let Ok(f2) = kb.create_fact("fact: (isa c d);") {
+                if let Ok(f3) = kb.create_fact("fact: (isa a c);") {
+                    if let Ok(f4) = kb.create_fact("fact: (isa a d);") {
+                        if let Ok(f5) = kb.create_fact("fact: (isa f g);") {
+                            let facts = [f1, f2, f3, f4, f5];
+
+                            for fact in facts.iter() {
+                                match kb.assert(fact.clone()) {
+                                    Ok(_) => {}
+                                    Err(e) => println!("{}", e),
+                                }
+                            }
+
+                            if let Ok(f) = kb.create_fact("fact: (isa f ?b);") {
+                                let a = kb.query(&f);
+                                let b: Vec<QueryBinding> =
+                                    vec![vec![(kb.intern_string("?b"), Term::Symbol(kb.intern_string("g")))]];
+
+                                assert_eq!(a, b);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn multi_binding_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(f1) = kb.create_fact("fact: (isa a b c);") {
+            if let Ok(f2) = kb.create_fact("fact: (isa c d c);") {
+                if let Ok(f3) = kb.create_fact("fact: (isa a c c);") {
+                    if let Ok(f4) = kb.create_fact("fact: (isa a d c);") {
+                        if let Ok(f5) = kb.create_fact("fact: (isa f g c);") {
+                            let facts = [f1, f2, f3, f4, f5];
+
+                            for fact in facts.iter() {
+                                match kb.assert(fact.clone()) {
+                                    Ok(_) => {}
+                                    Err(e) => println!("{}", e),
+                                }
+                            }
+
+                            if let Ok(f) = kb.create_fact("fact: (isa ?a ?b c);") {
+                                assert_eq!(kb.query(&f).len(), 5);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lazy_limit_offset_test() {
+        let mut kb = KnowledgeBase::new();
+        for statement in &["a b", "a c", "a d", "f g"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: (isa {});", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (isa a ?b);") {
+            let all = kb.query(&f);
+            assert_eq!(all.len(), 3);
+
+            let page: Vec<QueryBinding> = kb.query_iter(&f).offset(1).limit(1).collect();
+            assert_eq!(page, vec![all[1].clone()]);
+
+            assert_eq!(kb.query_iter(&f).offset(3).count(), 0);
+            assert_eq!(kb.query_iter(&f).limit(0).count(), 0);
+            assert_eq!(kb.query_iter(&f).next(), Some(all[0].clone()));
+        }
+    }
+
+    #[test]
+    fn lazy_unknown_argument_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(fact) = kb.create_fact("fact: (isa a b);") {
+            assert!(kb.assert(fact).is_ok());
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (isa z ?b);") {
+            assert_eq!(kb.query_iter(&f).next(), None);
+        }
+    }
+
+    #[test]
+    fn ordered_binding_test() {
+        let mut kb = KnowledgeBase::new();
+        for statement in &["a b c", "d e f", "g h i"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: (isa {});", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (isa ?z ?a ?m);") {
+            let header = vec![
+                kb.intern_string("?z"),
+                kb.intern_string("?a"),
+                kb.intern_string("?m"),
+            ];
+            for binding in kb.query(&f) {
+                let variables: Vec<Symbol> = binding.into_iter().map(|(var, _)| var).collect();
+                assert_eq!(variables, header);
+            }
+        }
+    }
+
+    #[test]
+    fn query_result_test() {
+        let mut kb = KnowledgeBase::new();
+        for statement in &["c d", "a b", "b c"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: (on {});", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (on ?top ?bottom);") {
+            let mut result = kb.query_result(&f);
+            assert_eq!(result.variables(), vec!["?top", "?bottom"]);
+            assert_eq!(result.len(), 3);
+
+            result.sort();
+            let rows = rendered_rows(&result);
+            assert_eq!(rows, vec![vec!["a", "b"], vec!["b", "c"], vec!["c", "d"]]);
+
+            result.sort_by("?bottom");
+            assert_eq!(*result.rows()[0].get("?bottom").unwrap(), "b");
+            assert_eq!(*result.rows()[0].get("?top").unwrap(), "a");
+            assert_eq!(result.rows()[0].get("?other"), None);
+        }
+    }
+
+    #[test]
+    fn selective_binding_test() {
+        let mut kb = KnowledgeBase::new();
+        for statement in &["a x 1", "a y 1", "a z 2", "b x 1", "c x 1"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: (isa {});", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (isa a ?b 1);") {
+            let mut result = kb.query_result(&f);
+            result.sort();
+            let rows = rendered_rows(&result);
+            assert_eq!(rows, vec![vec!["x"], vec!["y"]]);
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (isa a ?b 3);") {
+            assert!(kb.query(&f).is_empty());
+        }
+    }
+
+    #[test]
+    fn zero_arity_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(fact) = kb.create_fact("fact: (raining);") {
+            assert!(kb.assert(fact.clone()).is_ok());
+            assert_eq!(kb.ask(&fact), Ok(true));
+            assert_eq!(kb.query(&fact), vec![vec![]]);
+        }
+
+        if let Ok(rule) = kb.create_rule("rule: ((raining)) -> (wet grass);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (wet ?x);") {
+            let result = kb.query_result(&f);
+            assert_eq!(result.len(), 1);
+            assert_eq!(*result.rows()[0].get("?x").unwrap(), "grass");
+        }
+    }
+
+    #[test]
+    fn repeated_variable_test() {
+        let mut kb = KnowledgeBase::new();
+        for statement in &["bob bob", "bob alice", "alice alice"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: (likes {});", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (likes ?x ?x);") {
+            let mut result = kb.query_result(&f);
+            result.sort();
+            assert_eq!(result.variables(), vec!["?x"]);
+            let rows = rendered_rows(&result);
+            assert_eq!(rows, vec![vec!["alice"], vec!["bob"]]);
+        }
+    }
+
+    #[test]
+    fn compound_term_test() {
+        let mut kb = KnowledgeBase::new();
+        let statements = [
+            "(holds (location player) kitchen)",
+            "(holds (location guard) hall)",
+            "(holds (health player) full)",
+            "(holds location nowhere)",
+        ];
+        for statement in statements.iter() {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (holds (location ?who) ?where);") {
+            let mut result = kb.query_result(&f);
+            result.sort();
+            assert_eq!(
+                rendered_rows(&result),
+                vec![vec!["guard", "hall"], vec!["player", "kitchen"]]
+            );
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (holds ?fluent kitchen);") {
+            let result = kb.query_result(&f);
+            assert_eq!(result.len(), 1);
+            assert_eq!(*result.rows()[0].get("?fluent").unwrap(), "(location player)");
+        }
+
+        if let Ok(rule) = kb.create_rule("rule: ((holds (location ?x) ?y)) -> (at ?x ?y);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (at guard hall);") {
+            assert_eq!(kb.ask(&f), Ok(true));
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (holds (location player) kitchen);") {
+            assert!(kb.retract(f).is_ok());
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (at player kitchen);") {
+            assert_eq!(kb.ask(&f), Ok(false));
+        }
+    }
+
+    #[test]
+    fn list_term_test() {
+        let mut kb = KnowledgeBase::new();
+        for statement in &["(path guard [gate hall yard])", "(path cook [])"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (path ?who [?first | ?rest]);") {
+            let result = kb.query_result(&f);
+            assert_eq!(rendered_rows(&result), vec![vec!["guard", "gate", "[hall yard]"]]);
+        }
+    }
+
+    #[test]
+    fn list_builtin_query_test() {
+        let mut kb = KnowledgeBase::new();
+        let mut queries = vec![];
+        for statement in &[
+            "(member ?x [a b c])",
+            "(length [a b c] ?n)",
+            "(append ?x ?y [a b])",
+            "(append [a] [b c] ?z)",
+            "(member d [a b c])",
+            "(length ?l 3)",
+        ] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", statement)) {
+                queries.push(kb.query_result(&fact));
+            }
+        }
+
+        assert_eq!(queries.len(), 6);
+        assert_eq!(rendered_rows(&queries[0]), vec![vec!["a"], vec!["b"], vec!["c"]]);
+        assert_eq!(rendered_rows(&queries[1]), vec![vec!["3"]]);
+        assert_eq!(
+            rendered_rows(&queries[2]),
+            vec![
+                vec!["[]", "[a b]"],
+                vec!["[a]", "[b]"],
+                vec!["[a b]", "[]"],
+            ]
+        );
+        assert_eq!(rendered_rows(&queries[3]), vec![vec!["[a b c]"]]);
+        assert!(queries[4].is_empty());
+        assert!(queries[5].is_empty());
+    }
+
+    #[test]
+    fn list_builtin_rule_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(rule) =
+            kb.create_rule("rule: ((inventory ?p ?items) (member ?i ?items)) -> (carries ?p ?i);")
+        {
+            assert!(kb.assert(rule).is_ok());
+        }
+        if let Ok(rule) =
+            kb.create_rule("rule: ((inventory ?p ?items) (length ?items ?n)) -> (count ?p ?n);")
+        {
+            assert!(kb.assert(rule).is_ok());
+        }
+
+        if let Ok(fact) = kb.create_fact("fact: (inventory player [sword key]);") {
+            assert!(kb.assert(fact.clone()).is_ok());
+
+            if let Ok(f) = kb.create_fact("fact: (carries player ?i);") {
+                let mut result = kb.query_result(&f);
+                result.sort();
+                assert_eq!(rendered_rows(&result), vec![vec!["key"], vec!["sword"]]);
+            }
+            if let Ok(f) = kb.create_fact("fact: (count player 2);") {
+                assert_eq!(kb.ask(&f), Ok(true));
+            }
+
+            assert!(kb.retract(fact).is_ok());
+            if let Ok(f) = kb.create_fact("fact: (carries player ?i);") {
+                assert!(kb.query(&f).is_empty());
+            }
+        }
+
+        if let Ok(fact) = kb.create_fact("fact: (member a [a]);") {
+            assert!(kb.assert(fact).is_err());
+        }
+    }
+
+    #[test]
+    fn aggregate_query_test() {
+        let mut kb = KnowledgeBase::new();
+        let statements = [
+            "(adjacent p1 e1)",
+            "(adjacent p1 e2)",
+            "(adjacent p2 e1)",
+            "(threat u1 5)",
+            "(threat u2 9)",
+            "(threat u3 2)",
+        ];
+        for statement in statements.iter() {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        let mut results = vec![];
+        for statement in &[
+            "(count ?n ?e (adjacent ?p ?e))",
+            "(max ?m ?t (threat ? ?t))",
+            "(sum ?s ?t (threat ? ?t))",
+            "(min ?m ?t (threat ? ?t))",
+            "(collect ?l ?e (adjacent p1 ?e))",
+            "(count ?n ?e (adjacent p9 ?e))",
+        ] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", statement)) {
+                let mut result = kb.query_result(&fact);
+                result.sort();
+                results.push(result);
+            }
+        }
+
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[0].variables(), vec!["?n", "?p"]);
+        assert_eq!(rendered_rows(&results[0]), vec![vec!["1", "p2"], vec!["2", "p1"]]);
+        assert_eq!(rendered_rows(&results[1]), vec![vec!["9"]]);
+        assert_eq!(rendered_rows(&results[2]), vec![vec!["16"]]);
+        assert_eq!(rendered_rows(&results[3]), vec![vec!["2"]]);
+        assert_eq!(rendered_rows(&results[4]), vec![vec!["[e1 e2]"]]);
+        assert_eq!(rendered_rows(&results[5]), vec![vec!["0"]]);
+    }
+
+    #[test]
+    fn aggregate_rule_test() {
+        let mut kb = KnowledgeBase::new();
+        let rules = [
+            "rule: ((count ?n ?e (adjacent ?p ?e))) -> (enemies ?p ?n);",
+            "rule: ((unit ?p) (sum ?s ?t (threat ?p ?t))) -> (danger ?p ?s);",
+        ];
+        for statement in rules.iter() {
+            if let Ok(rule) = kb.create_rule(statement) {
+                assert!(kb.assert(rule).is_ok());
+            }
+        }
+
+        for statement in &["(adjacent p1 e1)", "(adjacent p1 e2)", "(unit p1)", "(threat p1 4)"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", statement)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (enemies ?p ?n);") {
+            assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["p1", "2"]]);
+        }
+        if let Ok(f) = kb.create_fact("fact: (danger p1 ?s);") {
+            assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["4"]]);
+        }
+
+        if let Ok(fact) = kb.create_fact("fact: (adjacent p1 e1);") {
+            assert!(kb.retract(fact).is_ok());
+        }
+        if let Ok(fact) = kb.create_fact("fact: (threat p1 3);") {
+            assert!(kb.assert(fact).is_ok());
+        }
+
+        if let Ok(f) = kb.create_fact("fact: (enemies ?p ?n);") {
+            assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["p1", "1"]]);
+        }
+        if let Ok(f) = kb.create_fact("fact: (danger p1 ?s);") {
+            assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["7"]]);
+        }
+
+        if let Ok(fact) = kb.create_fact("fact: (adjacent p1 e2);") {
+            assert!(kb.retract(fact).is_ok());
+        }
+        if let Ok(f) = kb.create_fact("fact: (enemies ?p ?n);") {
+            assert!(kb.query(&f).is_empty());
+        }
+    }
+
+    #[test]
+    fn aggregate_later_premise_test() {
+        let mut kb = KnowledgeBase::new();
+        let rule = "rule: ((unit ?p) (zone ?z) (count ?n ?e (near ?p ?z ?e))) -> (seen ?p ?z ?n);";
+        let rule = kb.create_rule(rule).unwrap();
+        assert!(kb.assert(rule).is_ok());
+        for statement in &["(unit p1)", "(zone north)", "(near p1 north e1)"] {
+            let fact = kb.create_fact(&format!("fact: {};", statement)).unwrap();
+            assert!(kb.assert(fact).is_ok());
+        }
+
+        let pattern = kb.create_fact("fact: (seen p1 north ?n);").unwrap();
+        assert_eq!(rendered_rows(&kb.query_result(&pattern)), vec![vec!["1"]]);
+
+        let fact = kb.create_fact("fact: (near p1 north e2);").unwrap();
+        assert!(kb.assert(fact).is_ok());
+        assert_eq!(rendered_rows(&kb.query_result(&pattern)), vec![vec!["2"]]);
+    }
+
+    #[test]
+    fn unstratified_aggregate_test() {
+        let mut kb = KnowledgeBase::new();
+        let rule = kb.create_rule("rule: ((count ?n ?x (item ?x))) -> (item ?n);").unwrap();
+        assert!(kb.assert(rule).is_err());
+
+        // The cycle may also go through other rules, whichever is added last
+        let rule = kb.create_rule("rule: ((total ?n)) -> (item ?n);").unwrap();
+        assert!(kb.assert(rule).is_ok());
+        let rule = kb.create_rule("rule: ((count ?n ?x (item ?x))) -> (total ?n);").unwrap();
+        assert!(kb.assert(rule).is_err());
+
+        let production = kb
+            .create_production("production: ((count ?n ?x (item ?x))) -> (total ?n);")
+            .unwrap();
+        assert!(kb.add_production(production).is_err());
+
+        let rule = kb.create_rule("rule: ((count ?n ?x (item ?x))) -> (size ?n);").unwrap();
+        assert!(kb.assert(rule).is_ok());
+        let fact = kb.create_fact("fact: (item sword);").unwrap();
+        assert!(kb.assert(fact).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod schema_tests {
+    use super::*;
+
+    #[test]
+    fn arity_mismatch_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_predicate("isa", &["symbol", "symbol"]).is_ok());
+        assert!(kb.register_predicate("isa", &["symbol", "symbol"]).is_ok());
+        assert!(kb.register_predicate("isa", &["symbol"]).is_err());
+        assert!(kb.register_predicate("member", &["any", "list"]).is_err());
+
+        if let Ok(fact) = kb.create_fact("fact: (isa cube);") {
+            assert_eq!(
+                kb.assert(fact).err(),
+                Some(String::from(
+                    "Predicate isa expects 2 arguments but (isa cube) has 1"
+                ))
+            );
+        }
+        if let Ok(fact) = kb.create_fact("fact: (isa cube block);") {
+            assert!(kb.assert(fact).is_ok());
+        }
+
+        assert!(kb.create_rule("rule: ((isa ?x)) -> (block ?x);").is_err());
+        assert!(kb.create_rule("rule: ((isa ?x ?y)) -> (block ?x);").is_ok());
+    }
+
+    #[test]
+    fn sort_mismatch_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_predicate("health", &["symbol", "number"]).is_ok());
+        assert!(kb.register_predicate("location", &["symbol", "room"]).is_ok());
+
+        if let Ok(fact) = kb.create_fact("fact: (health player full);") {
+            assert_eq!(
+                kb.assert(fact).err(),
+                Some(String::from(
+                    "Argument 2 of (health player full) should be a number but is full"
+                ))
+            );
+        }
+        if let Ok(fact) = kb.create_fact("fact: (health player 10);") {
+            assert!(kb.assert(fact).is_ok());
+        }
+        assert!(kb.create_rule("rule: ((health ?p [1 2])) -> (hurt ?p);").is_err());
+
+        if let Ok(fact) = kb.create_fact("fact: (location player kitchen);") {
+            assert!(kb.assert(fact.clone()).is_err());
+            if let Ok(room) = kb.create_fact("fact: (room kitchen);") {
+                assert!(kb.assert(room).is_ok());
+            }
+            assert!(kb.assert(fact).is_ok());
+        }
+    }
+
+    #[test]
+    fn declaration_conflicts_with_facts_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(fact) = kb.create_fact("fact: (on a b);") {
+            assert!(kb.assert(fact).is_ok());
+        }
+
+        assert!(kb.register_predicate("on", &["symbol"]).is_err());
+        assert!(kb.register_predicate("on", &["symbol", "symbol"]).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod constraint_tests {
+    use super::*;
+    use knowledge_base_basic_tests::assert_fact;
+    use query_tests::rendered_rows;
+
+    #[test]
+    fn reject_violating_fact_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(constraint) = kb.create_constraint("constraint: ((on ?x ?x));") {
+            assert!(kb.add_constraint(constraint.clone()).is_ok());
+            assert!(kb.add_constraint(constraint).is_err());
+        }
+
+        assert!(assert_fact(&mut kb, "(on a b)").is_ok());
+        assert_eq!(
+            assert_fact(&mut kb, "(on a a)"),
+            Err(String::from("Constraint ((on ?x ?x)) is violated by ?x = a"))
+        );
+
+        if let Ok(f) = kb.create_fact("fact: (on ?x ?y);") {
+            assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["a", "b"]]);
+        }
+    }
+
+    #[test]
+    fn rollback_inferences_test() {
+        let mut kb = KnowledgeBase::new();
+        if let Ok(rule) = kb.create_rule("rule: ((moved ?x ?r)) -> (location ?x ?r);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+        if let Ok(constraint) = kb.create_constraint(
+            "constraint: ((location ?x ?a) (location ?x ?b) (different ?a ?b));",
+        ) {
+            assert!(kb.add_constraint(constraint).is_ok());
+        }
+
+        assert!(assert_fact(&mut kb, "(location player kitchen)").is_ok());
+        assert_eq!(
+            assert_fact(&mut kb, "(moved player hall)"),
+            Err(String::from(
+                "Constraint ((location ?x ?a) (location ?x ?b) (different ?a ?b)) is violated \
+                 by ?x = player, ?a = kitchen, ?b = hall"
+            ))
+        );
+
+        if let Ok(f) = kb.create_fact("fact: (location player ?r);") {
+            assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["kitchen"]]);
+        }
+        if let Ok(f) = kb.create_fact("fact: (moved ?x ?r);") {
+            assert!(kb.query(&f).is_empty());
+        }
+    }
+
+    #[test]
+    fn manual_run_violation_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        if let Ok(rule) = kb.create_rule("rule: ((moved ?x ?r)) -> (location ?x ?r);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+        if let Ok(constraint) = kb.create_constraint(
+            "constraint: ((location ?x ?a) (location ?x ?b) (different ?a ?b));",
+        ) {
+            assert!(kb.add_constraint(constraint).is_ok());
+        }
+
+        // Nothing is inferred until the run, so both facts are accepted
+        assert!(assert_fact(&mut kb, "(location player kitchen)").is_ok());
+        assert!(assert_fact(&mut kb, "(moved player hall)").is_ok());
+        assert_eq!(
+            kb.run(),
+            Err(String::from(
+                "Constraint ((location ?x ?a) (location ?x ?b) (different ?a ?b)) is violated \
+                 by ?x = player, ?a = kitchen, ?b = hall"
+            ))
+        );
+
+        if let Ok(f) = kb.create_fact("fact: (location player ?r);") {
+            assert_eq!(rendered_rows(&kb.query_result(&f)), vec![vec!["kitchen"]]);
+        }
+        assert_eq!(kb.agenda().len(), 1);
+    }
+
+    #[test]
+    fn constraint_already_violated_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(assert_fact(&mut kb, "(on a a)").is_ok());
+
+        if let Ok(constraint) = kb.create_constraint("constraint: ((on ?x ?x));") {
+            assert!(kb.add_constraint(constraint.clone()).is_err());
+            assert!(kb.remove_constraint(&constraint).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod key_tests {
+    use super::*;
+    use knowledge_base_basic_tests::assert_fact;
+    use query_tests::rows;
+
+    #[test]
+    fn replace_keyed_fact_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_key("location", &[1]).is_ok());
+        if let Ok(rule) = kb.create_rule("rule: ((location ?x ?r) (dark ?r)) -> (blind ?x);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+
+        for fact in &["(dark kitchen)", "(location player kitchen)", "(location cat hall)"] {
+            assert!(assert_fact(&mut kb, fact).is_ok());
+        }
+        assert_eq!(rows(&mut kb, "(blind ?x)"), vec![vec!["player"]]);
+
+        assert!(assert_fact(&mut kb, "(location player hall)").is_ok());
+        assert_eq!(
+            rows(&mut kb, "(location ?x ?r)"),
+            vec![vec!["cat", "hall"], vec!["player", "hall"]]
+        );
+        assert!(rows(&mut kb, "(blind ?x)").is_empty());
+    }
+
+    #[test]
+    fn restore_replaced_fact_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_key("location", &[1]).is_ok());
+        if let Ok(constraint) = kb.create_constraint("constraint: ((location ?x void));") {
+            assert!(kb.add_constraint(constraint).is_ok());
+        }
+
+        assert!(assert_fact(&mut kb, "(location player kitchen)").is_ok());
+        assert!(assert_fact(&mut kb, "(location player void)").is_err());
+        assert_eq!(rows(&mut kb, "(location ?x ?r)"), vec![vec!["player", "kitchen"]]);
+    }
+
+    #[test]
+    fn derived_fact_keeps_asserted_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_key("location", &[1]).is_ok());
+        if let Ok(rule) = kb.create_rule("rule: ((fled ?x)) -> (location ?x hall);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+
+        assert!(assert_fact(&mut kb, "(location player kitchen)").is_ok());
+        assert!(assert_fact(&mut kb, "(fled player)").is_ok());
+        assert_eq!(
+            rows(&mut kb, "(location player ?r)"),
+            vec![vec!["kitchen"], vec!["hall"]]
+        );
+
+        if let Ok(fact) = kb.create_fact("fact: (fled player);") {
+            assert!(kb.retract(fact).is_ok());
+        }
+        assert_eq!(rows(&mut kb, "(location player ?r)"), vec![vec!["kitchen"]]);
+    }
+
+    #[test]
+    fn restored_fact_support_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_key("location", &[1]).is_ok());
+        if let Ok(constraint) = kb.create_constraint("constraint: ((location ?x void));") {
+            assert!(kb.add_constraint(constraint).is_ok());
+        }
+        if let Ok(rule) = kb.create_rule("rule: ((fled ?x)) -> (location ?x hall);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+
+        // A derived fact comes back still supported, and goes once its support does
+        assert!(assert_fact(&mut kb, "(fled cat)").is_ok());
+        assert!(assert_fact(&mut kb, "(location cat void)").is_err());
+        assert_eq!(rows(&mut kb, "(location cat ?r)"), vec![vec!["hall"]]);
+        if let Ok(fact) = kb.create_fact("fact: (fled cat);") {
+            assert!(kb.retract(fact).is_ok());
+        }
+        assert!(rows(&mut kb, "(location cat ?r)").is_empty());
+
+        // An asserted fact comes back as asserted, so it can be retracted again
+        assert!(assert_fact(&mut kb, "(location player kitchen)").is_ok());
+        assert!(assert_fact(&mut kb, "(location player void)").is_err());
+        if let Ok(fact) = kb.create_fact("fact: (location player kitchen);") {
+            assert!(kb.retract(fact).is_ok());
+        }
+        assert!(rows(&mut kb, "(location player ?r)").is_empty());
+    }
+
+    #[test]
+    fn invalid_key_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_predicate("location", &["symbol", "symbol"]).is_ok());
+        assert!(kb.register_key("location", &[0]).is_err());
+        assert!(kb.register_key("location", &[3]).is_err());
+        assert!(kb.register_key("member", &[1]).is_err());
+
+        assert!(assert_fact(&mut kb, "(owns alice sword)").is_ok());
+        assert!(assert_fact(&mut kb, "(owns alice shield)").is_ok());
+        assert!(kb.register_key("owns", &[1]).is_err());
+        assert!(kb.register_key("owns", &[1, 2]).is_ok());
+        assert!(kb.register_key("owns", &[2]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod plan_tests {
+    use super::*;
+
+    fn goal(kb: &mut KnowledgeBase, facts: &[&str]) -> Vec<Fact> {
+        facts
+            .iter()
+            .filter_map(|f| kb.create_fact(&format!("fact: {};", f)).ok())
+            .collect()
+    }
+
+    fn rendered(plan: &[Action]) -> Vec<String> {
+        plan.iter().map(|action| action.to_string()).collect()
+    }
+
+    #[test]
+    fn breadth_first_blocks_test() {
+        let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+        let goal = goal(&mut kb, &["(on d c)", "(on c b)", "(on b a)"]);
+
+        let plan = kb.plan(&goal).unwrap();
+        assert_eq!(
+            rendered(&plan),
+            vec!["(unstack a b)", "(move b c a)", "(move c d b)", "(stack d c)"]
+        );
+
+        // planning leaves the knowledge base untouched
+        if let Ok(fact) = kb.create_fact("fact: (on a b);") {
+            assert_eq!(kb.ask(&fact), Ok(true));
+        }
+    }
+
+    #[test]
+    fn astar_blocks_test() {
+        let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+        let goal = goal(&mut kb, &["(on d c)", "(on c b)", "(on b a)"]);
+
+        let plan = kb.plan_with(&goal, &Search::AStar(&unsatisfied_goals)).unwrap();
+        assert_eq!(plan.len(), 4);
+
+        let zero = |_: &PlanState, _: &[Fact]| 0;
+        let plan = kb.plan_with(&goal, &Search::AStar(&zero)).unwrap();
+        assert_eq!(plan.len(), 4);
+    }
+
+    #[test]
+    fn plan_limit_test() {
+        let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+        let goal = goal(&mut kb, &["(on d c)", "(on c b)", "(on b a)"]);
+
+        kb.set_plan_limit(Some(5));
+        assert_eq!(
+            kb.plan(&goal),
+            Err(String::from(
+                "No sequence of actions reaches the goal within the planning limit of 5 states"
+            ))
+        );
+        let one_left = |_: &PlanState, _: &[Fact]| 1;
+        assert!(kb.plan_with(&goal, &Search::AStar(&one_left)).is_err());
+
+        kb.set_plan_limit(None);
+        assert_eq!(kb.plan(&goal).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn astar_reopen_test() {
+        let mut kb = KnowledgeBase::new();
+        let go = kb
+            .create_action(
+                "action: (go ?a ?b) pre: ((at ?a) (edge ?a ?b)) add: ((at ?b)) del: ((at ?a));",
+            )
+            .unwrap();
+        assert!(kb.add_action(go).is_ok());
+        for fact in &["(at s)", "(edge s x)", "(edge x c)", "(edge s y)", "(edge y z)",
+                      "(edge z c)", "(edge c g)"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", fact)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+        let goal = goal(&mut kb, &["(at g)"]);
+        let at_x = kb.create_fact("fact: (at x);").unwrap();
+
+        // Never overestimates, but makes the long way round to c look better than the short one
+        let detour = |state: &PlanState, _: &[Fact]| if state.contains(&at_x) { 2 } else { 0 };
+        let plan = kb.plan_with(&goal, &Search::AStar(&detour)).unwrap();
+        assert_eq!(rendered(&plan), vec!["(go s x)", "(go x c)", "(go c g)"]);
+    }
+
+    #[test]
+    fn derived_goal_test() {
+        let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+
+        let satisfied = goal(&mut kb, &["(above a d)"]);
+        assert_eq!(kb.plan(&satisfied), Ok(vec![]));
+
+        let goal = goal(&mut kb, &["(above d ?x)", "(isa ?x block)"]);
+        let plan = kb.plan(&goal).unwrap();
+        assert_eq!(plan.len(), 4);
+        assert_eq!(plan.last().unwrap().name(), "stack");
+    }
+
+    #[test]
+    fn unreachable_goal_test() {
+        let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+        if let Ok(constraint) = kb.create_constraint("constraint: ((ontable a));") {
+            assert!(kb.add_constraint(constraint).is_ok());
+        }
+
+        let goal = goal(&mut kb, &["(on b a)"]);
+        assert!(kb.plan(&goal).is_err());
+    }
+
+    #[test]
+    fn pddl_blocks_test() {
+        let (kb, goal) =
+            KnowledgeBase::from_pddl("test/blocks-domain.pddl", "test/blocks-problem.pddl")
+                .unwrap();
+        assert_eq!(kb.actions().len(), 3);
+
+        let plan = kb.plan(&goal).unwrap();
+        assert_eq!(
+            rendered(&plan),
+            vec!["(unstack a b)", "(move b c a)", "(move c d b)", "(stack d c)"]
+        );
+
+        assert!(KnowledgeBase::from_pddl("test/blocks-domain.pddl", "test/missing.pddl").is_err());
+    }
+
+    #[test]
+    fn invalid_action_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb
+            .create_action("action: (go ?x ?x) pre: ((at ?x));")
+            .is_err());
+        assert!(kb.create_action("action: (go ?x) pre: ((at ?y));").is_err());
+        assert!(kb
+            .create_action("action: (go ?x) pre: ((at ?x)) add: ((at ?y));")
+            .is_err());
+
+        if let Ok(action) = kb.create_action("action: (go ?x) pre: ((path ?x)) add: ((at ?x));") {
+            assert!(kb.add_action(action.clone()).is_ok());
+            assert!(kb.add_action(action).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod action_tests {
+    use super::*;
+    use query_tests::rows;
+
+    fn world() -> (KnowledgeBase, ActionSchema) {
+        let mut kb = KnowledgeBase::new();
+        let go = kb
+            .create_action(
+                "action: (go ?p ?from ?to) pre: ((at ?p ?from) (path ?from ?to)) \
+                 add: ((at ?p ?to)) del: ((at ?p ?from));",
+            )
+            .unwrap();
+        if let Ok(rule) = kb.create_rule("rule: ((at ?p ?r) (dark ?r)) -> (blind ?p);") {
+            assert!(kb.assert(rule).is_ok());
+        }
+        for fact in &["(at player kitchen)", "(path kitchen cellar)", "(dark cellar)"] {
+            if let Ok(fact) = kb.create_fact(&format!("fact: {};", fact)) {
+                assert!(kb.assert(fact).is_ok());
+            }
+        }
+        (kb, go)
+    }
+
+    #[test]
+    fn apply_action_test() {
+        let (mut kb, go) = world();
+
+        assert!(kb.apply(&go, &["player", "kitchen", "cellar"]).is_ok());
+        assert_eq!(rows(&mut kb, "(at player ?r)"), vec![vec!["cellar"]]);
+        assert_eq!(rows(&mut kb, "(blind ?p)"), vec![vec!["player"]]);
+    }
+
+    #[test]
+    fn failed_precondition_test() {
+        let (mut kb, go) = world();
+
+        assert_eq!(
+            kb.apply(&go, &["player", "kitchen", "hall"]),
+            Err(String::from(
+                "Precondition (path kitchen hall) of (go player kitchen hall) does not hold"
+            ))
+        );
+        assert_eq!(
+            kb.apply(&go, &["player", "cellar", "kitchen"]),
+            Err(String::from(
+                "Precondition (at player cellar) of (go player cellar kitchen) does not hold"
+            ))
+        );
+        assert!(kb.apply(&go, &["player", "kitchen"]).is_err());
+        assert_eq!(rows(&mut kb, "(at player ?r)"), vec![vec!["kitchen"]]);
+    }
+
+    #[test]
+    fn atomic_rollback_test() {
+        let (mut kb, go) = world();
+        if let Ok(constraint) = kb.create_constraint("constraint: ((blind player));") {
+            assert!(kb.add_constraint(constraint).is_ok());
+        }
+
+        assert!(kb.apply(&go, &["player", "kitchen", "cellar"]).is_err());
+        assert_eq!(rows(&mut kb, "(at player ?r)"), vec![vec!["kitchen"]]);
+        assert!(rows(&mut kb, "(blind ?p)").is_empty());
+    }
+
+    #[test]
+    fn unbound_call_test() {
+        let (mut kb, _) = world();
+        let go = kb
+            .create_action(
+                "action: (go ?from ?to) pre: ((at player ?from) (path ?from ?to)) \
+                 add: ((at player ?to)) del: ((at player ?from));",
+            )
+            .unwrap();
+
+        assert_eq!(
+            kb.apply(&go, &["kitchen", "?y"]),
+            Err(String::from("Cannot take action (go kitchen ?y) with unbound variables"))
+        );
+        assert!(kb.apply(&go, &["kitchen", "[cellar | ?t]"]).is_err());
+        assert_eq!(rows(&mut kb, "(at player ?r)"), vec![vec!["kitchen"]]);
+    }
+
+    #[test]
+    fn failed_effect_test() {
+        let (mut kb, go) = world();
+        if let Ok(fact) = kb.create_fact("fact: (room kitchen);") {
+            assert!(kb.assert(fact).is_ok());
+        }
+        assert!(kb.register_predicate("at", &["symbol", "room"]).is_ok());
+
+        assert_eq!(
+            kb.apply(&go, &["player", "kitchen", "cellar"]),
+            Err(String::from(
+                "cellar in (at player cellar) is not a room; assert (room cellar) first"
+            ))
+        );
+        assert_eq!(rows(&mut kb, "(at player ?r)"), vec![vec!["kitchen"]]);
+        assert!(rows(&mut kb, "(blind ?p)").is_empty());
+    }
+
+    #[test]
+    fn execute_plan_test() {
+        let mut kb = KnowledgeBase::from_file("test/block.kb").unwrap();
+        let goal: Vec<Fact> = ["(on d c)", "(on c b)", "(on b a)"]
+            .iter()
+            .filter_map(|f| kb.create_fact(&format!("fact: {};", f)).ok())
+            .collect();
+
+        for action in kb.plan(&goal).unwrap() {
+            assert!(kb.execute(&action).is_ok());
+        }
+        for fact in &goal {
+            assert_eq!(kb.ask(fact), Ok(true));
+        }
+        assert_eq!(kb.plan(&goal), Ok(vec![]));
+    }
+}
+
+#[cfg(test)]
+mod agenda_tests {
+    use super::*;
+    use knowledge_base_basic_tests::assert_statement;
+    use std::time::Duration;
+
+    fn rendered(activations: &[Activation]) -> Vec<String> {
+        activations
+            .iter()
+            .map(|a| format!("{} {}", a.facts()[0], a.rule().unwrap().rhs[0]))
+            .collect()
+    }
+
+    fn fire_all(kb: &mut KnowledgeBase) -> Vec<String> {
+        let mut fired = vec![];
+        while let Some(activation) = kb.step() {
+            fired.extend(rendered(&[activation]));
+        }
+        fired
+    }
+
+    #[test]
+    fn salience_order_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        assert_statement(&mut kb, "rule: ((alarm ?x)) -> (low ?x) salience: -1;");
+        assert_statement(&mut kb, "rule: ((alarm ?x)) -> (high ?x) salience: 10;");
+        assert_statement(&mut kb, "rule: ((alarm ?x)) -> (mid ?x);");
+        assert_statement(&mut kb, "fact: (alarm fire);");
+
+        let saliences = kb.agenda().iter().map(|a| a.salience()).collect::<Vec<i32>>();
+        assert_eq!(saliences, vec![10, 0, -1]);
+        assert_eq!(
+            fire_all(&mut kb),
+            vec![
+                "(alarm fire) (high ?x)",
+                "(alarm fire) (mid ?x)",
+                "(alarm fire) (low ?x)"
+            ]
+        );
+        assert!(kb.agenda().is_empty());
+    }
+
+    #[test]
+    fn depth_and_breadth_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        assert_statement(&mut kb, "rule: ((item ?x)) -> (seen ?x);");
+        for fact in &["fact: (item 1);", "fact: (item 2);", "fact: (item 3);"] {
+            assert_statement(&mut kb, fact);
+        }
+
+        assert_eq!(kb.strategy(), Strategy::Depth);
+        assert_eq!(
+            rendered(&kb.agenda()),
+            vec!["(item 3) (seen ?x)", "(item 2) (seen ?x)", "(item 1) (seen ?x)"]
+        );
+
+        kb.set_strategy(Strategy::Breadth);
+        assert_eq!(
+            rendered(&kb.agenda()),
+            vec!["(item 1) (seen ?x)", "(item 2) (seen ?x)", "(item 3) (seen ?x)"]
+        );
+    }
+
+    #[test]
+    fn lex_and_mea_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        assert_statement(&mut kb, "rule: ((goal ?g) (data ?d)) -> (handled ?g);");
+        assert_statement(&mut kb, "fact: (goal g1);");
+        assert!(kb.step().is_some());
+        assert_statement(&mut kb, "fact: (data d1);");
+        assert_statement(&mut kb, "fact: (goal g2);");
+        assert!(kb.step().is_some());
+        assert_statement(&mut kb, "fact: (data d2);");
+
+        kb.set_strategy(Strategy::Lex);
+        assert_eq!(
+            rendered(&kb.agenda()),
+            vec![
+                "(data d2) (handled g2)",
+                "(data d2) (handled g1)",
+                "(data d1) (handled g2)",
+                "(data d1) (handled g1)"
+            ]
+        );
+
+        kb.set_strategy(Strategy::Mea);
+        assert_eq!(
+            rendered(&kb.agenda()),
+            vec![
+                "(data d2) (handled g2)",
+                "(data d1) (handled g2)",
+                "(data d2) (handled g1)",
+                "(data d1) (handled g1)"
+            ]
+        );
+    }
+
+    #[test]
+    fn manual_run_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        assert_statement(&mut kb, "rule: ((parent ?x ?y) (parent ?y ?z)) -> (grandparent ?x ?z);");
+        assert_statement(&mut kb, "fact: (parent ann bob);");
+        assert_statement(&mut kb, "fact: (parent bob cid);");
+
+        let grandparent = kb.create_fact("fact: (grandparent ann cid);").unwrap();
+        assert_eq!(kb.ask(&grandparent), Ok(false));
+        assert!(kb.run().unwrap() > 0);
+        assert_eq!(kb.ask(&grandparent), Ok(true));
+        assert!(kb.agenda().is_empty());
+    }
+
+    #[test]
+    fn budgeted_run_constraint_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        assert_statement(&mut kb, "rule: ((moved ?x ?r)) -> (location ?x ?r);");
+        let constraint = "constraint: ((location ?x ?a) (location ?x ?b) (different ?a ?b));";
+        let constraint = kb.create_constraint(constraint).unwrap();
+        assert!(kb.add_constraint(constraint).is_ok());
+        assert_statement(&mut kb, "fact: (location player kitchen);");
+        assert_statement(&mut kb, "fact: (moved player hall);");
+
+        let pending = rendered(&kb.agenda());
+        let error = kb.run_inference(Budget::Steps(1)).unwrap_err();
+        assert!(error.starts_with("Constraint"));
+        assert_eq!(rendered(&kb.agenda()), pending);
+
+        let hall = kb.create_fact("fact: (location player hall);").unwrap();
+        assert_eq!(kb.ask(&hall), Ok(false));
+    }
+
+    #[test]
+    fn depth_limit_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_inference_limits(InferenceLimits {
+            max_depth: Some(5),
+            ..Default::default()
+        });
+        assert_statement(&mut kb, "rule: ((number ?n)) -> (number (next ?n));");
+
+        let zero = kb.create_fact("fact: (number zero);").unwrap();
+        match kb.assert(zero.clone()) {
+            Err(e) => assert!(e.starts_with("Inference limit exceeded: a fact was derived")),
+            Ok(_) => panic!("runaway inference was not stopped"),
+        }
+        assert_eq!(kb.ask(&zero), Ok(false));
+        assert_eq!(kb.facts.len(), 0);
+        assert!(kb.agenda().is_empty());
+    }
+
+    #[test]
+    fn limit_rollback_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_inference_limits(InferenceLimits {
+            max_depth: Some(5),
+            ..Default::default()
+        });
+        let item = kb.create_fact("fact: (item apple);").unwrap();
+        assert!(kb.assert_for(item.clone(), 10).is_ok());
+        assert_statement(&mut kb, "rule: ((count ?n ?x (item ?x))) -> (items ?n);");
+        assert_statement(&mut kb, "rule: ((items 0)) -> (number zero);");
+        assert_statement(&mut kb, "rule: ((number ?n)) -> (number (next ?n));");
+        let before: Vec<String> = kb.facts.iter().map(|f| f.to_string()).collect();
+
+        // Running out of items starts runaway inference, so neither retracting the last item
+        // nor letting it expire goes through
+        assert!(kb.retract(item.clone()).is_err());
+        assert!(kb.advance_time(10).is_err());
+        assert_eq!(kb.now(), 0);
+        assert_eq!(kb.expiry(&item), Some(10));
+
+        let production = "production: ((item ?x)) -> (number zero);";
+        let production = kb.create_production(production).unwrap();
+        assert!(kb.add_production(production).is_err());
+        assert!(kb.productions().is_empty());
+
+        let after: Vec<String> = kb.facts.iter().map(|f| f.to_string()).collect();
+        assert_eq!(after, before);
+        assert!(kb.agenda().is_empty());
+    }
+
+    #[test]
+    fn derived_and_time_limits_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_inference_limits(InferenceLimits {
+            max_derived: Some(2),
+            ..Default::default()
+        });
+        assert_statement(&mut kb, "rule: ((item ?x)) -> (seen ?x) (kept ?x);");
+        assert_statement(&mut kb, "rule: ((seen ?x)) -> (noted ?x);");
+
+        let item = kb.create_fact("fact: (item 1);").unwrap();
+        assert!(kb.assert(item).err().unwrap().contains("more than 2 facts"));
+        assert_eq!(kb.facts.len(), 0);
+
+        kb.set_inference_limits(InferenceLimits {
+            time_budget: Some(Duration::from_millis(1)),
+            ..Default::default()
+        });
+        assert_statement(&mut kb, "rule: ((number ?n)) -> (number (next ?n));");
+        let zero = kb.create_fact("fact: (number zero);").unwrap();
+        assert!(kb.assert(zero).err().unwrap().contains("took longer than"));
+    }
+
+    #[test]
+    fn deep_derivation_test() {
+        let mut kb = KnowledgeBase::new();
+        assert_statement(&mut kb, "rule: ((reach ?x) (edge ?x ?y)) -> (reach ?y);");
+        let t = std::time::Instant::now();
+        for i in 0..2000 {
+            if i == 4999 { eprintln!("edges {:?}", t.elapsed()); }
+            assert_statement(&mut kb, &format!("fact: (edge {} {});", i, i + 1));
+        }
+        let t = std::time::Instant::now();
+        assert_statement(&mut kb, "fact: (reach 0);");
+        eprintln!("derive {:?}", t.elapsed());
+
+        let last = kb.create_fact("fact: (reach 2000);").unwrap();
+        assert_eq!(kb.ask(&last), Ok(true));
+
+        // Retracting the start of the chain takes every fact and rule derived from it along
+        let first = kb.create_fact("fact: (reach 0);").unwrap();
+        let t = std::time::Instant::now();
+        assert!(kb.retract(first).is_ok());
+        eprintln!("retract {:?}", t.elapsed());
+        assert_eq!(kb.ask(&last), Ok(false));
+        assert_eq!((kb.facts.len(), kb.rules.len()), (2000, 1));
+    }
+
+    #[test]
+    fn budgeted_run_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        assert_statement(&mut kb, "rule: ((reach ?x) (edge ?x ?y)) -> (reach ?y);");
+        for i in 0..5 {
+            assert_statement(&mut kb, &format!("fact: (edge {} {});", i, i + 1));
+        }
+        assert_statement(&mut kb, "fact: (reach 0);");
+
+        let last = kb.create_fact("fact: (reach 5);").unwrap();
+        let first = kb.run_inference(Budget::Steps(2)).unwrap();
+        assert_eq!(first, Progress { fired: 2, fixpoint: false });
+        assert_eq!(kb.ask(&last), Ok(false));
+
+        loop {
+            let progress = kb.run_inference(Budget::Steps(2)).unwrap();
+            assert!(progress.fired <= 2);
+            if progress.fixpoint {
+                break;
+            }
+            assert_eq!(progress.fired, 2);
+        }
+        assert_eq!(kb.ask(&last), Ok(true));
+
+        let idle = kb.run_inference(Budget::Time(Duration::from_secs(1))).unwrap();
+        assert_eq!(idle, Progress { fired: 0, fixpoint: true });
+    }
+
+    #[test]
+    fn retract_removes_activations_test() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_auto_run(false);
+        assert_statement(&mut kb, "rule: ((item ?x)) -> (seen ?x);");
+        assert_statement(&mut kb, "fact: (item 1);");
+        assert_eq!(kb.agenda().len(), 1);
+
+        let item = kb.create_fact("fact: (item 1);").unwrap();
+        assert!(kb.retract(item).is_ok());
+        assert!(kb.agenda().is_empty());
+        assert_eq!(kb.step().map(|_| ()), None);
+    }
+}
+
+#[cfg(test)]
+mod production_tests {
+    use super::*;
+    use knowledge_base_basic_tests::assert_fact;
+    use query_tests::rows;
+    use std::cell::RefCell;
+
+    fn add_production(kb: &mut KnowledgeBase, production: &str) {
+        let production = kb.create_production(production).unwrap();
+        assert!(kb.add_production(production).is_ok());
+    }
+
+    #[test]
+    fn refraction_test() {
+        let mut kb = KnowledgeBase::new();
+        let greeted = Rc::new(RefCell::new(Vec::new()));
+        let log = greeted.clone();
+        kb.register_callback("greet", move |args| log.borrow_mut().push(args[0].to_string()));
+        add_production(&mut kb, "production: ((visitor ?x)) -> (call greet ?x);");
+
+        assert!(assert_fact(&mut kb, "(visitor ann)").is_ok());
+        assert!(assert_fact(&mut kb, "(visitor bob)").is_ok());
+        assert!(assert_fact(&mut kb, "(weather rain)").is_ok());
+        assert_eq!(*greeted.borrow(), vec!["ann", "bob"]);
+
+        let ann = kb.create_fact("fact: (visitor ann);").unwrap();
+        assert!(kb.retract(ann).is_ok());
+        assert!(assert_fact(&mut kb, "(visitor ann)").is_ok());
+        assert_eq!(*greeted.borrow(), vec!["ann", "bob", "ann"]);
+    }
+
+    #[test]
+    fn assert_and_retract_effects_test() {
+        let mut kb = KnowledgeBase::new();
+        add_production(
+            &mut kb,
+            "production: ((hungry ?x) (food ?f)) -> (eating ?x ?f) (retract (hungry ?x)) \
+             (retract (food ?f));",
+        );
+        assert!(assert_fact(&mut kb, "(hungry ann)").is_ok());
+        assert!(assert_fact(&mut kb, "(hungry bob)").is_ok());
+        assert!(assert_fact(&mut kb, "(food apple)").is_ok());
+
+        assert_eq!(rows(&mut kb, "(eating ?x apple)").len(), 1);
+        assert_eq!(rows(&mut kb, "(hungry ?x)").len(), 1);
+        assert!(rows(&mut kb, "(food ?f)").is_empty());
+        assert!(kb.agenda().is_empty());
+    }
+
+    #[test]
+    fn undone_effects_test() {
+        let mut kb = KnowledgeBase::new();
+        let greeted = Rc::new(RefCell::new(Vec::new()));
+        let log = greeted.clone();
+        kb.register_callback("greet", move |args| log.borrow_mut().push(args[0].to_string()));
+        add_production(
+            &mut kb,
+            "production: ((visitor ?x)) -> (greeted ?x) (retract (door open)) (call greet ?x);",
+        );
+        let constraint = kb.create_constraint("constraint: ((greeted ?x) (banned ?x));").unwrap();
+        assert!(kb.add_constraint(constraint).is_ok());
+        assert!(assert_fact(&mut kb, "(banned bob)").is_ok());
+
+        assert!(assert_fact(&mut kb, "(door open)").is_ok());
+        assert!(assert_fact(&mut kb, "(visitor ann)").is_ok());
+        assert!(rows(&mut kb, "(door open)").is_empty());
+
+        // Greeting bob violates the constraint, so neither the greeting nor the call happens
+        assert!(assert_fact(&mut kb, "(door open)").is_ok());
+        assert!(assert_fact(&mut kb, "(visitor bob)").is_err());
+        assert_eq!(*greeted.borrow(), vec!["ann"]);
+        assert_eq!(rows(&mut kb, "(greeted ?x)"), vec![vec!["ann"]]);
+        assert_eq!(rows(&mut kb, "(door open)").len(), 1);
+        assert!(rows(&mut kb, "(visitor bob)").is_empty());
+    }
+
+    #[test]
+    fn high_salience_first_test() {
+        let mut kb = KnowledgeBase::new();
+        add_production(&mut kb, "production: ((alarm ?x)) -> (logged ?x);");
+        add_production(&mut kb, "production: ((alarm ?x)) -> (retract (alarm ?x)) salience: 10;");
+        assert!(assert_fact(&mut kb, "(alarm fire)").is_ok());
+
+        assert!(rows(&mut kb, "(logged ?x)").is_empty());
+        assert!(rows(&mut kb, "(alarm ?x)").is_empty());
+    }
+
+    #[test]
+    fn low_salience_last_test() {
+        let mut kb = KnowledgeBase::new();
+        add_production(&mut kb, "production: ((alarm ?x)) -> (logged ?x);");
+        add_production(&mut kb, "production: ((alarm ?x)) -> (retract (alarm ?x)) salience: -10;");
+        assert!(assert_fact(&mut kb, "(alarm fire)").is_ok());
+
+        assert_eq!(rows(&mut kb, "(logged ?x)"), vec![vec!["fire"]]);
+        assert!(rows(&mut kb, "(alarm ?x)").is_empty());
+    }
+
+    #[test]
+    fn invalid_production_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.create_production("production: ((alarm ?x)) -> (flee ?y);").is_err());
+        assert!(kb.create_production("production: ((alarm ?x)) -> (retract ?x);").is_err());
+        assert!(kb.create_production("production: ((alarm ?x)) -> (call ?x);").is_err());
+
+        add_production(&mut kb, "production: ((alarm ?x)) -> (flee ?x);");
+        let duplicate = kb.create_production("production: ((alarm ?x)) -> (flee ?x);").unwrap();
+        assert!(kb.add_production(duplicate).is_err());
+    }
+}
+
+#[cfg(test)]
+mod subscription_tests {
+    use super::*;
+    use knowledge_base_basic_tests::assert_statement;
+    use std::cell::RefCell;
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    // Records each change as the change, the fact and the bound values
+    fn subscribe(kb: &mut KnowledgeBase, pattern: &str) -> (Subscription, Log) {
+        let log: Log = Rc::new(RefCell::new(Vec::new()));
+        let pattern = kb.create_fact(pattern).unwrap();
+        let entries = log.clone();
+        let subscription = kb.subscribe(&pattern, move |change, fact, bindings| {
+            let values: Vec<String> = bindings.iter().map(|(_, t)| t.to_string()).collect();
+            entries
+                .borrow_mut()
+                .push(format!("{:?} {} {}", change, fact, values.join(" ")));
+        });
+        (subscription, log)
+    }
+
+    #[test]
+    fn derived_and_truth_maintenance_test() {
+        let mut kb = KnowledgeBase::new();
+        let (_, log) = subscribe(&mut kb, "fact: (cool ?x);");
+        assert_statement(&mut kb, "rule: ((isa ?x boy)) -> (cool ?x);");
+        assert_statement(&mut kb, "fact: (isa bob boy);");
+        assert_statement(&mut kb, "fact: (isa rex dog);");
+
+        let bob = kb.create_fact("fact: (isa bob boy);").unwrap();
+        assert!(kb.retract(bob).is_ok());
+        assert_eq!(
+            *log.borrow(),
+            vec!["Derived (cool bob) bob", "Retracted (cool bob) bob"]
+        );
+    }
+
+    #[test]
+    fn replaced_by_key_test() {
+        let mut kb = KnowledgeBase::new();
+        assert!(kb.register_key("location", &[1]).is_ok());
+        let (_, log) = subscribe(&mut kb, "fact: (location player ?r);");
+        assert_statement(&mut kb, "fact: (location player kitchen);");
+        assert_statement(&mut kb, "fact: (location cat kitchen);");
+        assert_statement(&mut kb, "fact: (location player hall);");
+
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "Asserted (location player kitchen) kitchen",
+                "Retracted (location player kitchen) kitchen",
+                "Asserted (location player hall) hall",
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_aggregate_test() {
+        let mut kb = KnowledgeBase::new();
+        let (_, log) = subscribe(&mut kb, "fact: (enemies ?p ?n);");
+        assert_statement(&mut kb, "rule: ((count ?n ?e (adjacent ?p ?e))) -> (enemies ?p ?n);");
+        assert_statement(&mut kb, "fact: (adjacent p1 e1);");
+        assert_statement(&mut kb, "fact: (adjacent p2 e1);");
+        assert_statement(&mut kb, "fact: (adjacent p2 e2);");
+
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "Derived (enemies p1 1) p1 1",
+                "Derived (enemies p2 1) p2 1",
+                "Retracted (enemies p2 1) p2 1",
+                "Derived (enemies p2 2) p2 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn unsubscribe_test() {
+        let mut kb = KnowledgeBase::new();
+        let (first, first_log) = subscribe(&mut kb, "fact: (alarm ?x);");
+        let (_, second_log) = subscribe(&mut kb, "fact: (alarm ?x);");
+        assert_statement(&mut kb, "fact: (alarm fire);");
+
+        assert!(kb.unsubscribe(first).is_ok());
+        assert!(kb.unsubscribe(first).is_err());
+        assert_statement(&mut kb, "fact: (alarm flood);");
+
+        assert_eq!(first_log.borrow().len(), 1);
+        assert_eq!(second_log.borrow().len(), 2);
+    }
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+    use knowledge_base_basic_tests::assert_statement;
+
+    fn retract_fact(kb: &mut KnowledgeBase, fact: &str) {
+        let fact = kb.create_fact(fact).unwrap();
+        assert!(kb.retract(fact).is_ok());
+    }
+
+    fn watch(kb: &mut KnowledgeBase, pattern: &str) -> QueryWatch {
+        let pattern = kb.create_fact(pattern).unwrap();
+        kb.watch_query(&pattern).unwrap()
+    }
+
+    fn rendered_changes(watch: &QueryWatch) -> Vec<String> {
+        watch
+            .drain_changes()
+            .iter()
+            .map(|change| {
+                let (sign, row) = match change {
+                    RowChange::Added(row) => ("+", row),
+                    RowChange::Removed(row) => ("-", row),
+                };
+                let values: Vec<String> = row.values().iter().map(|v| v.to_string()).collect();
+                format!("{} {}", sign, values.join(" "))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn incremental_changes_test() {
+        let mut kb = KnowledgeBase::new();
+        assert_statement(&mut kb, "rule: ((parent ?x ?y)) -> (related ?x ?y);");
+        assert_statement(&mut kb, "fact: (parent ann bob);");
+
+        let watch = watch(&mut kb, "fact: (related ?x ?y);");
+        assert_eq!(rendered_changes(&watch), vec!["+ ann bob"]);
+
+        assert_statement(&mut kb, "fact: (parent bob cid);");
+        retract_fact(&mut kb, "fact: (parent ann bob);");
+        assert_eq!(rendered_changes(&watch), vec!["+ bob cid", "- ann bob"]);
+        assert!(rendered_changes(&watch).is_empty());
+        assert_eq!(watch.len(), 1);
     }
 
-    // returns all of the facts that match the query bindings of the given fact
-    fn get_query_facts(&self, f: &Fact) -> Vec<Rc<Fact>> {
-        match self.facts_map.get(&f.pred) {
-            Some(arg_list) => {
-                if arg_list.len() == f.args.len() {
-                    let mut facts = HashSet::new();
-                    let mut any_bind = false;
-
-                    for i in 0..arg_list.len() {
-                        if !f.args[i].is_var() {
-                            if let Some(fact_list) = arg_list[i].get(&f.args[i]) {
-                                let temp_facts = fact_list.iter().map(|f| f.clone()).collect();
-                                if any_bind {
-                                    facts = facts
-                                        .intersection(&temp_facts)
-                                        .map(|f| f.clone())
-                                        .collect();
-                                } else {
-                                    facts = temp_facts;
-                                }
-                            }
+    #[test]
+    fn repeated_rows_test() {
+        let mut kb = KnowledgeBase::new();
+        let watch = watch(&mut kb, "fact: (on ?x ?);");
+        assert!(watch.is_empty());
 
-                            any_bind = true;
-                        }
-                    }
+        assert_statement(&mut kb, "fact: (on a b);");
+        assert_statement(&mut kb, "fact: (on a c);");
+        assert_eq!(rendered_changes(&watch), vec!["+ a"]);
 
-                    if !any_bind {
-                        for fact_list in arg_list[0].values() {
-                            for fact in fact_list {
-                                facts.insert(fact.clone());
-                            }
-                        }
-                    }
-                    return facts.into_iter().collect();
-                }
-            }
-            None => {}
+        retract_fact(&mut kb, "fact: (on a b);");
+        assert!(rendered_changes(&watch).is_empty());
+        retract_fact(&mut kb, "fact: (on a c);");
+        assert_eq!(rendered_changes(&watch), vec!["- a"]);
+    }
+
+    #[test]
+    fn unwatch_test() {
+        let mut kb = KnowledgeBase::new();
+        let first = watch(&mut kb, "fact: (alarm ?x);");
+        let second = watch(&mut kb, "fact: (alarm ?x);");
+        assert!(kb.unwatch_query(first).is_ok());
+
+        assert_statement(&mut kb, "fact: (alarm fire);");
+        assert_eq!(rendered_changes(&second), vec!["+ fire"]);
+    }
+    #[test]
+    fn computed_pattern_test() {
+        let mut kb = KnowledgeBase::new();
+        for pattern in &["fact: (member ?x [a b]);", "fact: (count ?n ?x (alarm ?x));"] {
+            let pattern = kb.create_fact(pattern).unwrap();
+            assert!(kb.watch_query(&pattern).is_err());
         }
-        vec![]
     }
 }
 
 #[cfg(test)]
-mod knowledge_base_basic_tests {
+mod temporal_tests {
     use super::*;
+    use knowledge_base_basic_tests::assert_statement;
 
     #[test]
-    fn test_add_fact() {
+    fn derived_interval_test() {
         let mut kb = KnowledgeBase::new();
+        assert_statement(&mut kb, "rule: ((at ?x gate) (alarm)) -> (alert ?x);");
+        assert_statement(&mut kb, "fact: (at guard gate) from: 10 to: 40;");
+        assert_statement(&mut kb, "fact: (alarm) from: 30;");
 
-        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
-            match kb.add_fact(new_fact.clone()) {
-                Ok(_) => {}
-                Err(e) => println!("{}", e),
-            }
-
-            assert_eq!(kb.contains_fact(&new_fact), true);
-        }
+        let alert = kb.create_fact("fact: (alert guard);").unwrap();
+        assert_eq!(kb.intervals(&alert), vec![Interval::new(Some(30), Some(40))]);
+        assert_eq!(kb.query(&alert).len(), 1);
+        assert!(kb.query_at(&alert, 25).is_empty());
+        assert_eq!(kb.query_at(&alert, 35).len(), 1);
     }
 
     #[test]
-    fn test_remove_fact() {
+    fn query_at_test() {
         let mut kb = KnowledgeBase::new();
+        assert_statement(&mut kb, "fact: (at guard gate) from: 10 to: 40;");
+        assert_statement(&mut kb, "fact: (at guard tower) from: 40;");
+        assert_statement(&mut kb, "fact: (at cook kitchen);");
 
-        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
-            match kb.add_fact(new_fact.clone()) {
-                Ok(_) => {}
-                Err(e) => println!("{}", e),
-            }
+        let pattern = kb.create_fact("fact: (at guard ?place);").unwrap();
+        assert!(kb.query_at(&pattern, 5).is_empty());
+        assert_eq!(kb.query_at(&pattern, 10).len(), 1);
+        assert_eq!(kb.query_at(&pattern, 40).len(), 1);
+        assert_eq!(kb.query_during(&pattern, Interval::new(Some(30), None)).len(), 2);
 
-            match kb.remove_fact(&new_fact) {
-                Ok(_) => {}
-                Err(e) => println!("{}", e),
-            }
+        let pattern = kb.create_fact("fact: (at cook ?place);").unwrap();
+        assert_eq!(kb.query_at(&pattern, -100).len(), 1);
+    }
 
-            assert_eq!(kb.contains_fact(&new_fact), false);
-            assert_eq!(kb.facts.is_empty(), true);
-        }
+    #[test]
+    fn disjoint_intervals_test() {
+        let mut kb = KnowledgeBase::new();
+        assert_statement(&mut kb, "rule: ((at ?x gate) (alarm)) -> (alert ?x);");
+        assert_statement(&mut kb, "fact: (at guard gate) from: 10 to: 20;");
+        assert_statement(&mut kb, "fact: (alarm) from: 20 to: 30;");
+
+        let alert = kb.create_fact("fact: (alert guard);").unwrap();
+        assert!(kb.intervals(&alert).is_empty());
     }
 
     #[test]
-    fn test_ask_fact_already_in_kb() {
+    fn retract_interval_test() {
         let mut kb = KnowledgeBase::new();
-        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
-            match kb.add_fact(new_fact.clone()) {
-                Ok(_) => {}
-                Err(e) => println!("{}", e),
-            }
-            assert_eq!(kb.ask(&new_fact), Ok(true));
-        }
+        assert_statement(&mut kb, "fact: (alarm) from: 20 to: 30;");
+
+        let unbounded = kb.create_fact("fact: (alarm);").unwrap();
+        assert!(kb.retract(unbounded.clone()).is_err());
+
+        let bounded = kb.create_fact("fact: (alarm) from: 20 to: 30;").unwrap();
+        assert!(kb.retract(bounded).is_ok());
+        assert!(kb.intervals(&unbounded).is_empty());
     }
 
     #[test]
-    fn test_ask_fact_not_in_fb() {
+    fn empty_interval_test() {
         let mut kb = KnowledgeBase::new();
-        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
-            assert_eq!(kb.ask(&new_fact), Ok(false));
-        }
+        let fact = kb.create_fact("fact: (alarm) from: 30 to: 30;").unwrap();
+        assert!(kb.assert(fact).is_err());
     }
 }
 
 #[cfg(test)]
-mod inference_tests {
+mod expiry_tests {
     use super::*;
+    use knowledge_base_basic_tests::assert_statement;
+
+    fn assert_for(kb: &mut KnowledgeBase, fact: &str, ttl: i64) {
+        let fact = kb.create_fact(fact).unwrap();
+        assert!(kb.assert_for(fact, ttl).is_ok());
+    }
+
+    fn holds(kb: &mut KnowledgeBase, fact: &str) -> bool {
+        let fact = kb.create_fact(fact).unwrap();
+        kb.ask(&fact).unwrap()
+    }
 
     #[test]
-    fn test_assert_and_infer() {
+    fn expiry_cascades_test() {
         let mut kb = KnowledgeBase::new();
-        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
-            match kb.assert(new_fact.clone()) {
-                Ok(_) => {}
-                Err(e) => println!("{}", e),
-            }
+        assert_statement(&mut kb, "rule: ((sees ?x player) (armed ?x)) -> (attacks ?x player);");
+        assert_statement(&mut kb, "fact: (armed guard);");
+        assert_for(&mut kb, "fact: (sees guard player);", 5);
+        assert!(holds(&mut kb, "fact: (attacks guard player);"));
 
-            if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy)) -> (cool ?x);") {
-                match kb.assert(new_rule.clone()) {
-                    Ok(_) => {}
-                    Err(e) => println!("{}", e),
-                }
+        assert!(kb.advance_time(4).unwrap().is_empty());
+        assert!(holds(&mut kb, "fact: (attacks guard player);"));
 
-                if let Ok(result_fact) = kb.create_fact("fact: (cool Bob);") {
-                    assert_eq!(kb.contains_fact(&new_fact), true);
-                    assert_eq!(kb.contains_rule(&new_rule), true);
-                    assert_eq!(kb.contains_fact(&result_fact), true);
-                }
-            }
-        }
+        let expired = kb.advance_time(5).unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].to_string(), "(sees guard player)");
+        assert!(!holds(&mut kb, "fact: (sees guard player);"));
+        assert!(!holds(&mut kb, "fact: (attacks guard player);"));
+        assert!(holds(&mut kb, "fact: (armed guard);"));
     }
 
     #[test]
-    fn test_infer_rule() {
+    fn refresh_test() {
         let mut kb = KnowledgeBase::new();
-        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
-            match kb.assert(new_fact.clone()) {
-                Ok(_) => {}
-                Err(e) => println!("{}", e),
-            }
+        assert_for(&mut kb, "fact: (sees guard player);", 5);
+        kb.advance_time(4).unwrap();
+        assert_for(&mut kb, "fact: (sees guard player);", 5);
 
-            if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy) (was ?x ?y)) -> (cool ?y);") {
-                match kb.assert(new_rule.clone()) {
-                    Ok(_) => {}
-                    Err(e) => println!("{}", e),
-                }
+        let fact = kb.create_fact("fact: (sees guard player);").unwrap();
+        assert_eq!(kb.expiry(&fact), Some(9));
+        assert!(kb.advance_time(8).unwrap().is_empty());
+        assert_eq!(kb.advance_time(20).unwrap().len(), 1);
+        assert_eq!(kb.expiry(&fact), None);
 
-                if let Ok(result_rule) = kb.create_rule("rule: ((was Bob ?y)) -> (cool ?y);") {
-                    assert_eq!(kb.contains_fact(&new_fact), true);
-                    assert_eq!(kb.contains_rule(&new_rule), true);
-                    assert_eq!(kb.contains_rule(&result_rule), true);
-                }
-            }
-        }
+        // An expired fact can be asserted again
+        assert_for(&mut kb, "fact: (sees guard player);", 1);
+        assert_eq!(kb.expiry(&fact), Some(21));
     }
 
     #[test]
-    fn test_bind() {
+    fn permanent_fact_test() {
         let mut kb = KnowledgeBase::new();
-        if let Ok(fact1) = kb.create_fact("fact: (isa Bob boy);") {
-            if let Ok(fact2) = kb.create_fact("fact: (isa ?x boy);") {
-                let bindings = match kb.try_bind(&fact1, &fact2) {
-                    Ok(lst) => lst,
-                    Err(e) => {
-                        println!("{}", e);
-                        HashMap::new()
-                    }
-                };
+        assert_statement(&mut kb, "fact: (armed guard);");
 
-                assert!(bindings.contains_key(&kb.intern_string("?x")));
+        let fact = kb.create_fact("fact: (armed guard);").unwrap();
+        assert!(kb.assert_for(fact.clone(), 5).is_err());
+        assert!(kb.advance_time(100).unwrap().is_empty());
+        assert_eq!(kb.expiry(&fact), None);
+        assert!(holds(&mut kb, "fact: (armed guard);"));
+    }
 
-                if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy)) -> (cool ?x);") {
-                    let result_fact = kb.apply_bindings(&new_rule.rhs, None, &bindings);
+    #[test]
+    fn invalid_time_test() {
+        let mut kb = KnowledgeBase::new();
+        let fact = kb.create_fact("fact: (sees guard player);").unwrap();
+        assert!(kb.assert_for(fact, 0).is_err());
 
-                    assert_eq!(
-                        result_fact,
-                        Fact::new(
-                            kb.intern_string("cool"),
-                            vec![kb.intern_string("Bob")],
-                            vec![]
-                        )
-                    );
-                }
-            }
+        kb.advance_time(10).unwrap();
+        assert!(kb.advance_time(9).is_err());
+        assert_eq!(kb.now(), 10);
+    }
+}
+
+#[cfg(test)]
+mod certainty_tests {
+    use super::*;
+
+    fn assert_with_confidence(kb: &mut KnowledgeBase, statement: &str, confidence: f64) {
+        if statement.starts_with("rule:") {
+            let rule = kb.create_rule(statement).unwrap();
+            assert!(kb.assert_with_confidence(rule, confidence).is_ok());
+        } else {
+            let fact = kb.create_fact(statement).unwrap();
+            assert!(kb.assert_with_confidence(fact, confidence).is_ok());
         }
     }
 
+    fn confidence(kb: &mut KnowledgeBase, fact: &str) -> Option<f64> {
+        let fact = kb.create_fact(fact).unwrap();
+        kb.ask_with_confidence(&fact)
+    }
+
+    fn suspects(kb: &mut KnowledgeBase) {
+        assert_with_confidence(kb, "rule: ((heard ?x noise) (saw ?x shadow)) -> (alert ?x);", 0.5);
+        assert_with_confidence(kb, "rule: ((smelled ?x smoke)) -> (alert ?x);", 1.0);
+        assert_with_confidence(kb, "fact: (heard guard noise);", 0.8);
+        assert_with_confidence(kb, "fact: (saw guard shadow);", 0.4);
+    }
+
     #[test]
-    fn test_retract_inferred() {
+    fn conjunction_test() {
         let mut kb = KnowledgeBase::new();
+        suspects(&mut kb);
+        assert_eq!(confidence(&mut kb, "fact: (alert guard);"), Some(0.5 * 0.4));
 
-        if let Ok(new_fact) = kb.create_fact("fact: (isa Bob boy);") {
-            let rc_fact = match kb.assert(new_fact.clone()) {
-                Ok(_f) => Some(_f),
-                Err(e) => {
-                    println!("{}", e);
-                    None
-                }
-            };
+        let mut kb = KnowledgeBase::new();
+        kb.set_certainty_model(CertaintyModel {
+            conjunction: Conjunction::Product,
+            ..Default::default()
+        });
+        suspects(&mut kb);
+        let alert = confidence(&mut kb, "fact: (alert guard);").unwrap();
+        assert!((alert - 0.5 * 0.8 * 0.4).abs() < 1e-9);
+    }
 
-            if let Ok(new_rule) = kb.create_rule("rule: ((isa ?x boy)) -> (cool ?x);") {
-                let rc_rule = match kb.assert(new_rule.clone()) {
-                    Ok(_r) => Some(_r),
-                    Err(e) => {
-                        println!("{}", e);
-                        None
-                    }
-                };
+    #[test]
+    fn disjunction_test() {
+        let mut kb = KnowledgeBase::new();
+        suspects(&mut kb);
+        assert_with_confidence(&mut kb, "fact: (smelled guard smoke);", 0.3);
+        assert_eq!(confidence(&mut kb, "fact: (alert guard);"), Some(0.3));
 
-                let result_fact = Fact::new(
-                    kb.intern_string("cool"),
-                    vec![kb.intern_string("Bob")],
-                    vec![
-                        (
-                            Rc::new(rc_fact.unwrap().to_fact().unwrap()),
-                            Rc::new(rc_rule.unwrap().to_rule().unwrap()),
-                        ),
-                    ],
-                );
-                assert_eq!(kb.contains_fact(&new_fact), true);
-                assert_eq!(kb.contains_rule(&new_rule), true);
-                assert_eq!(kb.contains_fact(&result_fact), true);
+        kb.set_certainty_model(CertaintyModel {
+            disjunction: Disjunction::NoisyOr,
+            ..Default::default()
+        });
+        let alert = confidence(&mut kb, "fact: (alert guard);").unwrap();
+        assert!((alert - (1.0 - 0.8 * 0.7)).abs() < 1e-9);
 
-                for f in kb.facts.iter() {
-                    println!("{:?}\n\n", f)
-                }
+        // Retracting a support lowers the confidence again
+        let smoke = kb.create_fact("fact: (smelled guard smoke);").unwrap();
+        assert!(kb.retract(smoke).is_ok());
+        assert_eq!(confidence(&mut kb, "fact: (alert guard);"), Some(0.2));
+    }
 
-                assert!(kb.retract(new_fact.clone()).is_ok());
+    #[test]
+    fn chained_confidence_test() {
+        let mut kb = KnowledgeBase::new();
+        assert_with_confidence(&mut kb, "rule: ((alert ?x)) -> (armed ?x);", 0.5);
+        suspects(&mut kb);
+        assert_eq!(confidence(&mut kb, "fact: (armed guard);"), Some(0.1));
+        assert_eq!(confidence(&mut kb, "fact: (heard guard noise);"), Some(0.8));
+        assert_eq!(confidence(&mut kb, "fact: (armed cook);"), None);
+    }
 
-                assert_eq!(kb.contains_fact(&new_fact), false);
-                assert_eq!(kb.contains_rule(&new_rule), true);
-                assert_eq!(kb.contains_fact(&result_fact), false);
-            }
-        }
+    #[test]
+    fn threshold_query_test() {
+        let mut kb = KnowledgeBase::new();
+        suspects(&mut kb);
+        let pattern = kb.create_fact("fact: (alert ?who);").unwrap();
+        assert_eq!(kb.query_with_confidence(&pattern, 0.2).len(), 1);
+        assert!(kb.query_with_confidence(&pattern, 0.5).is_empty());
+
+        let pattern = kb.create_fact("fact: (heard ?who ?what);").unwrap();
+        assert_eq!(kb.query_with_confidence(&pattern, 0.8).len(), 1);
+        assert!(kb.query_with_confidence(&pattern, 0.9).is_empty());
+
+        let pattern = kb.create_fact("fact: (different guard cook);").unwrap();
+        assert_eq!(kb.query_with_confidence(&pattern, 1.0).len(), 1);
+    }
+
+    #[test]
+    fn invalid_confidence_test() {
+        let mut kb = KnowledgeBase::new();
+        let fact = kb.create_fact("fact: (heard guard noise);").unwrap();
+        assert!(kb.assert_with_confidence(fact.clone(), 1.5).is_err());
+        assert!(kb.assert_with_confidence(fact.clone(), -0.1).is_err());
+        assert_eq!(kb.ask(&fact), Ok(false));
     }
 }
 
 #[cfg(test)]
-mod query_tests {
+mod context_tests {
     use super::*;
 
+    fn assert_in(kb: &mut KnowledgeBase, context: Option<&str>, statement: &str) {
+        let result = if statement.starts_with("rule:") {
+            let rule = kb.create_rule(statement).unwrap();
+            match context {
+                Some(context) => kb.assert_in(context, rule),
+                None => kb.assert(rule),
+            }
+        } else {
+            let fact = kb.create_fact(statement).unwrap();
+            match context {
+                Some(context) => kb.assert_in(context, fact),
+                None => kb.assert(fact),
+            }
+        };
+        assert!(result.is_ok());
+    }
+
+    fn ask_in(kb: &mut KnowledgeBase, context: &str, fact: &str) -> bool {
+        let fact = kb.create_fact(fact).unwrap();
+        kb.ask_in(context, &fact).unwrap()
+    }
+
+    fn village(kb: &mut KnowledgeBase) {
+        kb.create_context("guards", None).unwrap();
+        kb.create_context("alice", Some("guards")).unwrap();
+        kb.create_context("bob", Some("guards")).unwrap();
+    }
+
     #[test]
-    fn empty_test() {
+    fn layered_beliefs_test() {
         let mut kb = KnowledgeBase::new();
-        if let Ok(f) = kb.create_fact("fact: (isa ?a ?b);") {
-            let a = kb.query(&f);
-            let b: Vec<QueryBinding> = vec![];
+        village(&mut kb);
+        assert_in(&mut kb, None, "fact: (door open);");
+        assert_in(&mut kb, Some("alice"), "fact: (hides thief barn);");
 
-            assert_eq!(a, b);
-        }
+        assert!(ask_in(&mut kb, "alice", "fact: (door open);"));
+        assert!(ask_in(&mut kb, "alice", "fact: (hides thief barn);"));
+        assert!(!ask_in(&mut kb, "bob", "fact: (hides thief barn);"));
+        assert!(!ask_in(&mut kb, "guards", "fact: (hides thief barn);"));
+
+        let fact = kb.create_fact("fact: (hides thief barn);").unwrap();
+        assert_eq!(kb.ask(&fact), Ok(false));
+        assert!(kb.query(&fact).is_empty());
     }
 
     #[test]
-    fn single_binding_test() {
+    fn scoped_inference_test() {
         let mut kb = KnowledgeBase::new();
+        village(&mut kb);
+        assert_in(&mut kb, None, "rule: ((hides thief ?x) (door open)) -> (search ?x);");
+        assert_in(&mut kb, Some("guards"), "rule: ((search ?x)) -> (report ?x);");
+        assert_in(&mut kb, Some("alice"), "fact: (hides thief barn);");
+        assert_in(&mut kb, Some("bob"), "fact: (door open);");
 
-        if let Ok(f1) = kb.create_fact("fact: (isa a b);") {
-            if let Ok(f2) = kb.create_fact("fact: (isa c d);") {
-                if let Ok(f3) = kb.create_fact("fact: (isa a c);") {
-                    if let Ok(f4) = kb.create_fact("fact: (isa a d);") {
-                        if let Ok(f5) = kb.create_fact("fact: (isa f g);") {
-                            let facts = vec![f1, f2, f3, f4, f5];
+        // Alice and Bob only know half each
+        assert!(!ask_in(&mut kb, "alice", "fact: (search barn);"));
+        assert!(!ask_in(&mut kb, "bob", "fact: (search barn);"));
 
-                            for fact in facts.iter() {
-                                match kb.assert(fact.clone()) {
-                                    Ok(_) => {}
-                                    Err(e) => println!("{}", e),
-                                }
-                            }
+        assert_in(&mut kb, None, "fact: (door open);");
+        assert!(ask_in(&mut kb, "alice", "fact: (search barn);"));
+        assert!(ask_in(&mut kb, "alice", "fact: (report barn);"));
+        assert!(!ask_in(&mut kb, "bob", "fact: (report barn);"));
 
-                            if let Ok(f) = kb.create_fact("fact: (isa f ?b);") {
-                                let a = kb.query(&f);
-                                let b: Vec<QueryBinding> =
-                                    vec![vec![(kb.intern_string("?b"), kb.intern_string("g"))]];
+        let pattern = kb.create_fact("fact: (report ?x);").unwrap();
+        assert_eq!(kb.query_in("alice", &pattern).unwrap().len(), 1);
+        assert!(kb.query_in("guards", &pattern).unwrap().is_empty());
+        assert!(kb.query(&pattern).is_empty());
+    }
 
-                                assert_eq!(a, b);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    #[test]
+    fn retract_in_test() {
+        let mut kb = KnowledgeBase::new();
+        village(&mut kb);
+        assert_in(&mut kb, None, "rule: ((hides thief ?x)) -> (search ?x);");
+        assert_in(&mut kb, None, "fact: (hides thief barn);");
+        assert_in(&mut kb, Some("alice"), "fact: (hides thief barn);");
+
+        let fact = kb.create_fact("fact: (hides thief barn);").unwrap();
+        assert!(kb.retract(fact.clone()).is_ok());
+        assert!(ask_in(&mut kb, "alice", "fact: (search barn);"));
+        assert!(!ask_in(&mut kb, "bob", "fact: (search barn);"));
+
+        assert!(kb.retract_in("bob", fact.clone()).is_err());
+        assert!(kb.retract_in("alice", fact).is_ok());
+        assert!(!ask_in(&mut kb, "alice", "fact: (search barn);"));
     }
 
     #[test]
-    fn multi_binding_test() {
+    fn keyed_belief_test() {
         let mut kb = KnowledgeBase::new();
-        if let Ok(f1) = kb.create_fact("fact: (isa a b c);") {
-            if let Ok(f2) = kb.create_fact("fact: (isa c d c);") {
-                if let Ok(f3) = kb.create_fact("fact: (isa a c c);") {
-                    if let Ok(f4) = kb.create_fact("fact: (isa a d c);") {
-                        if let Ok(f5) = kb.create_fact("fact: (isa f g c);") {
-                            let facts = vec![f1, f2, f3, f4, f5];
+        village(&mut kb);
+        assert!(kb.register_key("location", &[1]).is_ok());
+        assert_in(&mut kb, None, "fact: (location player kitchen);");
+        assert_in(&mut kb, Some("alice"), "fact: (location player hall);");
 
-                            for fact in facts.iter() {
-                                match kb.assert(fact.clone()) {
-                                    Ok(_) => {}
-                                    Err(e) => println!("{}", e),
-                                }
-                            }
+        let kitchen = kb.create_fact("fact: (location player kitchen);").unwrap();
+        assert_eq!(kb.ask(&kitchen), Ok(true));
+        assert!(ask_in(&mut kb, "alice", "fact: (location player hall);"));
+        assert!(!ask_in(&mut kb, "bob", "fact: (location player hall);"));
 
-                            if let Ok(f) = kb.create_fact("fact: (isa ?a ?b c);") {
-                                assert_eq!(kb.query(&f).len(), 5);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        assert_in(&mut kb, Some("alice"), "fact: (location player cellar);");
+        assert!(!ask_in(&mut kb, "alice", "fact: (location player hall);"));
+        assert!(ask_in(&mut kb, "alice", "fact: (location player cellar);"));
+        assert_eq!(kb.ask(&kitchen), Ok(true));
+    }
+
+    #[test]
+    fn unknown_context_test() {
+        let mut kb = KnowledgeBase::new();
+        village(&mut kb);
+        assert!(kb.create_context("alice", None).is_err());
+        assert!(kb.create_context("carol", Some("thieves")).is_err());
+        let parent = kb.context_parent("alice").unwrap();
+        assert_eq!(parent.as_deref(), Some("guards"));
+        assert_eq!(kb.context_parent("guards"), Ok(None));
+
+        let fact = kb.create_fact("fact: (door open);").unwrap();
+        assert!(kb.assert_in("thieves", fact.clone()).is_err());
+        assert!(kb.ask_in("thieves", &fact).is_err());
+        assert!(kb.query_in("thieves", &fact).is_err());
+    }
+}
+
+#[cfg(test)]
+mod include_tests {
+    use super::*;
+
+    #[test]
+    fn namespaced_modules_test() {
+        let mut kb = KnowledgeBase::from_file("test/include/game.kb").unwrap();
+
+        let fact = kb.create_fact("fact: (contested gate);").unwrap();
+        assert_eq!(kb.ask(&fact), Ok(true));
+        let fact = kb.create_fact("fact: (contested barn);").unwrap();
+        assert_eq!(kb.ask(&fact), Ok(false));
+
+        let pattern = kb.create_fact("fact: (south::holds ?place);").unwrap();
+        assert_eq!(kb.query(&pattern).len(), 2);
+        let pattern = kb.create_fact("fact: (ally ?x);").unwrap();
+        assert!(kb.query(&pattern).is_empty());
+    }
+
+    #[test]
+    fn include_errors_test() {
+        assert!(KnowledgeBase::from_file("test/include/cycle_b.kb").is_err());
+        assert!(KnowledgeBase::from_file("test/include/missing.kb").is_err());
     }
 }
@@ -37,19 +37,19 @@ fn main() {
             } else {
                 ""
             };
-            let fact_attempt = "fact: ".to_string() + &statement + ";";
-            let rule_attempt = "rule: ".to_string() + &statement + ";";
+            let fact_attempt = "fact: ".to_string() + statement + ";";
+            let rule_attempt = "rule: ".to_string() + statement + ";";
 
-            match command.as_ref() {
+            match command {
                 "assert" => {
                     if let Ok(fact) = kb.create_fact(&fact_attempt) {
-                        if let Ok(_) = kb.assert(fact) {
+                        if kb.assert(fact).is_ok() {
                             println!("Asserted Fact '{}'.", &statement);
                         } else {
                             println!("Assert failed, probably because this fact has already been asserted.");
                         }
                     } else if let Ok(rule) = kb.create_rule(&rule_attempt) {
-                        if let Ok(_) = kb.assert(rule) {
+                        if kb.assert(rule).is_ok() {
                             println!("Asserted Rule '{}'.", &statement);
                         } else {
                             println!("Assert failed, probably because this rule has already been asserted.");
@@ -60,13 +60,13 @@ fn main() {
                 }
                 "retract" => {
                     if let Ok(fact) = kb.create_fact(&fact_attempt) {
-                        if let Ok(_) = kb.retract(fact) {
+                        if kb.retract(fact).is_ok() {
                             println!("Retracted Fact '{}'.", &statement);
                         } else {
                             println!("Retract failed, either because Fact does not exist or is supported by a Rule.");
                         }
                     } else if let Ok(rule) = kb.create_rule(&rule_attempt) {
-                        if let Ok(_) = kb.retract(rule) {
+                        if kb.retract(rule).is_ok() {
                             println!("Retracted Rule '{}'.", &statement);
                         } else {
                             println!("Retract failed, probably because Rule does not exist.");
@@ -80,7 +80,7 @@ fn main() {
                         if let Ok(res) = kb.ask(&fact) {
                             println!("{}", res.to_string().to_uppercase());
                         }
-                    } else if let Ok(_) = kb.create_rule(&rule_attempt) {
+                    } else if kb.create_rule(&rule_attempt).is_ok() {
                         println!("Ask can only accept a Fact.");
                     } else {
                         println!("Failed to parse statement.");
@@ -88,32 +88,32 @@ fn main() {
                 }
                 "query" => {
                     if let Ok(fact) = kb.create_fact(&fact_attempt) {
-                        let query_result = kb.query(&fact);
+                        let query_result = kb.query_result(&fact);
                         if query_result.is_empty() {
                             println!("No results found.");
                         } else {
                             println!("Query results:");
                             let mut crossbar = "-".to_string();
-                            for _ in 0..query_result[0].len() {
+                            for _ in 0..query_result.variables().len() {
                                 crossbar.push_str("-----------");
                             }
                             println!("{}", crossbar);
-                            for variable in &query_result[0] {
-                                print!("|{:^10}", (*variable.0).to_string());
+                            for variable in query_result.variables() {
+                                print!("|{:^10}", variable);
                             }
                             println!("|");
                             println!("{}", crossbar);
 
-                            for binding in query_result {
-                                for variable_tuple in binding {
-                                    print!("|{:^10}", (*variable_tuple.1).to_string());
+                            for row in &query_result {
+                                for value in row.values() {
+                                    print!("|{:^10}", value);
                                 }
                                 println!("|");
                             }
 
                             println!("{}", crossbar);
                         }
-                    } else if let Ok(_) = kb.create_rule(&rule_attempt) {
+                    } else if kb.create_rule(&rule_attempt).is_ok() {
                         println!("Query can only accept a Fact.");
                     } else {
                         println!("Failed to parse statement.");
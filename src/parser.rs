@@ -1,51 +1,328 @@
 #![allow(dead_code)]
 
+use aggregate::AGGREGATES;
 use nom::*;
+use production::{CALL, RETRACT};
+use std::collections::HashSet;
 use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, PartialEq)]
 pub struct ParsedKnowledgeBase {
+    pub preds: Vec<ParsedPredicate>,
     pub facts: Vec<ParsedFact>,
     pub rules: Vec<ParsedRule>,
+    pub constraints: Vec<ParsedConstraint>,
+    pub keys: Vec<ParsedKey>,
+    pub actions: Vec<ParsedAction>,
+    pub productions: Vec<ParsedProduction>,
+    pub includes: Vec<ParsedInclude>,
 }
 
 impl ParsedKnowledgeBase {
     pub fn new(facts: Vec<ParsedFact>, rules: Vec<ParsedRule>) -> ParsedKnowledgeBase {
-        ParsedKnowledgeBase { facts, rules }
+        ParsedKnowledgeBase {
+            preds: vec![],
+            facts,
+            rules,
+            constraints: vec![],
+            keys: vec![],
+            actions: vec![],
+            productions: vec![],
+            includes: vec![],
+        }
     }
+
+    // Collects statements that may appear in any order into their own lists
+    fn from_statements(statements: Vec<ParsedStatement>) -> ParsedKnowledgeBase {
+        let mut pkb = ParsedKnowledgeBase::new(vec![], vec![]);
+        for statement in statements {
+            match statement {
+                ParsedStatement::Pred(p) => pkb.preds.push(p),
+                ParsedStatement::Fact(f) => pkb.facts.push(f),
+                ParsedStatement::Rule(r) => pkb.rules.push(r),
+                ParsedStatement::Constraint(c) => pkb.constraints.push(c),
+                ParsedStatement::Key(k) => pkb.keys.push(k),
+                ParsedStatement::Action(a) => pkb.actions.push(a),
+                ParsedStatement::Production(p) => pkb.productions.push(p),
+                ParsedStatement::Include(i) => pkb.includes.push(i),
+            }
+        }
+        pkb
+    }
+
+    // Appends the statements of another parsed knowledge base
+    fn extend(&mut self, other: ParsedKnowledgeBase) {
+        self.preds.extend(other.preds);
+        self.facts.extend(other.facts);
+        self.rules.extend(other.rules);
+        self.constraints.extend(other.constraints);
+        self.keys.extend(other.keys);
+        self.actions.extend(other.actions);
+        self.productions.extend(other.productions);
+        self.includes.extend(other.includes);
+    }
+
+    // Prefixes every predicate the knowledge base defines with a namespace, wherever it is used.
+    // Predicates are defined by declarations, keys, facts, the conclusions of rules and
+    // productions, and the effects of actions. Other predicates are left alone, so they still
+    // refer to those of the including file.
+    fn qualify(&mut self, namespace: &str) {
+        let mut defined = HashSet::new();
+        defined.extend(self.preds.iter().map(|p| p.name.clone()));
+        defined.extend(self.keys.iter().map(|k| k.pred.clone()));
+        defined.extend(self.facts.iter().map(|f| f.pred.clone()));
+        for rule in &self.rules {
+            defined.extend(rule.rhs.iter().map(|f| f.pred.clone()));
+        }
+        for production in &self.productions {
+            let asserted = production.rhs.iter().filter(|f| f.pred != RETRACT && f.pred != CALL);
+            defined.extend(asserted.map(|f| f.pred.clone()));
+        }
+        for action in &self.actions {
+            defined.extend(action.add.iter().chain(&action.del).map(|f| f.pred.clone()));
+        }
+
+        let qualify = |pred: &mut String| {
+            if defined.contains(pred) {
+                *pred = format!("{}::{}", namespace, pred);
+            }
+        };
+        for pred in &mut self.preds {
+            qualify(&mut pred.name);
+        }
+        for key in &mut self.keys {
+            qualify(&mut key.pred);
+        }
+        let facts = self
+            .facts
+            .iter_mut()
+            .chain(self.rules.iter_mut().flat_map(|r| r.lhs.iter_mut().chain(&mut r.rhs)))
+            .chain(self.constraints.iter_mut().flat_map(|c| &mut c.body))
+            .chain(
+                self.productions
+                    .iter_mut()
+                    .flat_map(|p| p.lhs.iter_mut().chain(&mut p.rhs)),
+            )
+            .chain(self.actions.iter_mut().flat_map(|a| {
+                a.pre.iter_mut().chain(&mut a.add).chain(&mut a.del)
+            }));
+        for fact in facts {
+            fact.rename_nested(&qualify);
+            qualify(&mut fact.pred);
+        }
+    }
+}
+
+/// A file whose statements are loaded along with those of the including file
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedInclude {
+    pub path: String,
+    pub namespace: Option<String>,
+}
+
+impl ParsedInclude {
+    pub fn new(path: String, namespace: Option<String>) -> ParsedInclude {
+        ParsedInclude { path, namespace }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedPredicate {
+    pub name: String,
+    pub sorts: Vec<String>,
+}
+
+impl ParsedPredicate {
+    pub fn new(name: String, sorts: Vec<String>) -> ParsedPredicate {
+        ParsedPredicate { name, sorts }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedAction {
+    pub head: ParsedFact,
+    pub pre: Vec<ParsedFact>,
+    pub add: Vec<ParsedFact>,
+    pub del: Vec<ParsedFact>,
+}
+
+impl ParsedAction {
+    pub fn new(
+        head: ParsedFact,
+        pre: Vec<ParsedFact>,
+        add: Vec<ParsedFact>,
+        del: Vec<ParsedFact>,
+    ) -> ParsedAction {
+        ParsedAction { head, pre, add, del }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedKey {
+    pub pred: String,
+    pub args: Vec<usize>,
+}
+
+impl ParsedKey {
+    pub fn new(pred: String, args: Vec<usize>) -> ParsedKey {
+        ParsedKey { pred, args }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum ParsedStatement {
+    Pred(ParsedPredicate),
+    Fact(ParsedFact),
+    Rule(ParsedRule),
+    Constraint(ParsedConstraint),
+    Key(ParsedKey),
+    Action(ParsedAction),
+    Production(ParsedProduction),
+    Include(ParsedInclude),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParsedTerm {
+    Symbol(String),
+    Compound(String, Vec<ParsedTerm>),
+    List(Vec<ParsedTerm>, Option<Box<ParsedTerm>>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ParsedFact {
     pub pred: String,
-    pub args: Vec<String>,
+    pub args: Vec<ParsedTerm>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
 }
 
 impl ParsedFact {
-    pub fn new(pred: String, args: Vec<String>) -> ParsedFact {
-        ParsedFact { pred, args }
+    pub fn new(pred: String, args: Vec<ParsedTerm>) -> ParsedFact {
+        ParsedFact {
+            pred,
+            args,
+            start: None,
+            end: None,
+        }
+    }
+
+    // Renames the predicate of the fact written inside this one, which is the pattern of an
+    // aggregate or the target of a retract effect. Other compound arguments are data, so their
+    // functors are left alone.
+    fn rename_nested(&mut self, rename: &dyn Fn(&mut String)) {
+        let nested = if AGGREGATES.contains(&self.pred.as_str()) && self.args.len() == 3 {
+            self.args.get_mut(2)
+        } else if self.pred == RETRACT && self.args.len() == 1 {
+            self.args.get_mut(0)
+        } else {
+            None
+        };
+        if let Some(ParsedTerm::Compound(functor, _)) = nested {
+            rename(functor);
+        }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ParsedRule {
-    pub lhs: Vec<Vec<String>>,
-    pub rhs: Vec<String>,
+    pub lhs: Vec<ParsedFact>,
+    pub rhs: Vec<ParsedFact>,
+    pub salience: i32,
 }
 
 impl ParsedRule {
-    pub fn new(lhs: Vec<Vec<String>>, rhs: Vec<String>) -> ParsedRule {
-        ParsedRule { lhs, rhs }
+    pub fn new(lhs: Vec<ParsedFact>, rhs: Vec<ParsedFact>) -> ParsedRule {
+        ParsedRule {
+            lhs,
+            rhs,
+            salience: 0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedProduction {
+    pub lhs: Vec<ParsedFact>,
+    pub rhs: Vec<ParsedFact>,
+    pub salience: i32,
+}
+
+impl ParsedProduction {
+    pub fn new(lhs: Vec<ParsedFact>, rhs: Vec<ParsedFact>, salience: i32) -> ParsedProduction {
+        ParsedProduction { lhs, rhs, salience }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedConstraint {
+    pub body: Vec<ParsedFact>,
+}
+
+impl ParsedConstraint {
+    pub fn new(body: Vec<ParsedFact>) -> ParsedConstraint {
+        ParsedConstraint { body }
     }
 }
 
 pub fn parse_kb_from_file(filename: &str) -> Result<ParsedKnowledgeBase, String> {
-    let file = fs::read(filename).expect("file not found");
+    load_kb_file(Path::new(filename), None, &mut Vec::new(), &mut Vec::new())
+}
 
-    match kb(&file[..]) {
-        Ok(tuple) => Ok(tuple.1),
-        Err(_) => Err(String::from("Failed to parse kb from file")),
+// Parses a knowledge base file, with the statements of the files it includes ahead of its own.
+// The namespace only qualifies the file's own statements, as included files have their own.
+// `including` holds the files whose includes are being loaded, to detect cycles, and `loaded` the
+// files already included along with their namespaces, so each is only merged once.
+fn load_kb_file(
+    path: &Path,
+    namespace: Option<&str>,
+    including: &mut Vec<PathBuf>,
+    loaded: &mut Vec<(PathBuf, Option<String>)>,
+) -> Result<ParsedKnowledgeBase, String> {
+    let canonical = fs::canonicalize(path)
+        .map_err(|e| format!("Failed to read kb file {}: {}", path.display(), e))?;
+    let file = fs::read(&canonical)
+        .map_err(|e| format!("Failed to read kb file {}: {}", path.display(), e))?;
+
+    let mut pkb = match kb(&file[..]) {
+        // Everything up to the closing brace must have been parsed
+        Ok((rest, pkb)) if String::from_utf8_lossy(rest).trim() == "}" => pkb,
+        _ => return Err(format!("Failed to parse kb from file {}", path.display())),
+    };
+    if let Some(namespace) = namespace {
+        pkb.qualify(namespace);
+    }
+
+    including.push(canonical);
+    let mut merged = ParsedKnowledgeBase::new(vec![], vec![]);
+    for include in std::mem::take(&mut pkb.includes) {
+        // Paths are relative to the including file
+        let target = path.parent().unwrap_or(Path::new("")).join(&include.path);
+        let canonical = fs::canonicalize(&target)
+            .map_err(|e| format!("Failed to read kb file {}: {}", target.display(), e))?;
+
+        if let Some(start) = including.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = including[start..]
+                .iter()
+                .chain(Some(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format!("Include cycle: {}", cycle.join(" -> ")));
+        }
+
+        let key = (canonical, include.namespace.clone());
+        if loaded.contains(&key) {
+            continue;
+        }
+        loaded.push(key);
+
+        let included = load_kb_file(&target, include.namespace.as_deref(), including, loaded)?;
+        merged.extend(included);
     }
+    including.pop();
+
+    merged.extend(pkb);
+    Ok(merged)
 }
 
 pub fn parse_fact(f: &[u8]) -> Result<ParsedFact, String> {
@@ -62,14 +339,60 @@ pub fn parse_rule(r: &[u8]) -> Result<ParsedRule, String> {
     }
 }
 
+pub fn parse_production(p: &[u8]) -> Result<ParsedProduction, String> {
+    match production(p) {
+        Ok(tuple) => Ok(tuple.1),
+        Err(_) => Err(String::from("Failed to parse production from string")),
+    }
+}
+
+pub fn parse_constraint(c: &[u8]) -> Result<ParsedConstraint, String> {
+    match constraint(c) {
+        Ok(tuple) => Ok(tuple.1),
+        Err(_) => Err(String::from("Failed to parse constraint from string")),
+    }
+}
+
+pub fn parse_action(a: &[u8]) -> Result<ParsedAction, String> {
+    match action(a) {
+        Ok(tuple) => Ok(tuple.1),
+        Err(_) => Err(String::from("Failed to parse action from string")),
+    }
+}
+
+pub fn parse_term(t: &[u8]) -> Result<ParsedTerm, String> {
+    // A term on its own has no terminator of its own, so one is added to end the input
+    let input = [t, b";"].concat();
+    match lone_term(&input) {
+        Ok((b"", term)) => Ok(term),
+        _ => Err(String::from("Failed to parse term from string")),
+    }
+}
+
+pub fn parse_predicate(p: &[u8]) -> Result<ParsedPredicate, String> {
+    match predicate(p) {
+        Ok(tuple) => Ok(tuple.1),
+        Err(_) => Err(String::from("Failed to parse predicate declaration from string")),
+    }
+}
+
 named!(
-    name<&[u8]>,
+    name_segment<&[u8]>,
     recognize!(pair!(
         take_while1!(is_alphabetic),
         take_while!(is_alphanumeric)
     ))
 );
 
+// Names may be qualified by namespaces, as in faction1::ally
+named!(
+    name<&[u8]>,
+    recognize!(pair!(
+        name_segment,
+        many0!(pair!(tag!("::"), name_segment))
+    ))
+);
+
 named!(
     var<&[u8]>,
     recognize!(pair!(
@@ -78,26 +401,88 @@ named!(
     ))
 );
 
-named!(fact<&[u8], ParsedFact>,
+fn to_string(bytes: &[u8]) -> String {
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+named!(
+    number<&[u8]>,
+    recognize!(pair!(
+        opt!(tag!("-")),
+        take_while1!(is_digit)
+    ))
+);
+
+named!(term<&[u8], ParsedTerm>,
+    alt!(
+        compound |
+        list |
+        map!(alt!(name | var | number), |c| ParsedTerm::Symbol(to_string(c)))
+    )
+);
+
+named!(lone_term<&[u8], ParsedTerm>,
+    do_parse!(
+        opt!(multispace) >>
+        t: term >>
+        opt!(multispace) >>
+        tag!(";") >>
+        (t)
+    )
+);
+
+named!(list_tail<&[u8], ParsedTerm>,
+    ws!(do_parse!(
+        tag!("|") >>
+        tail: term >>
+        (tail)
+    ))
+);
+
+named!(list<&[u8], ParsedTerm>,
+    ws!(do_parse!(
+        tag!("[") >>
+        items: many0!(term) >>
+        tail: opt!(list_tail) >>
+        tag!("]") >>
+        (ParsedTerm::List(items, tail.map(Box::new)))
+    ))
+);
+
+named!(compound<&[u8], ParsedTerm>,
     ws!(do_parse!(
-        tag!("fact:") >>
         tag!("(") >>
-        pred: alpha >>
-        args: many1!(map!(alt!(name | var), |c| String::from_utf8(c.to_vec()).unwrap())) >>
+        functor: name >>
+        args: many0!(term) >>
         tag!(")") >>
-        (ParsedFact::new(String::from_utf8(pred.to_vec()).unwrap(), args))
+        (ParsedTerm::Compound(to_string(functor), args))
     ))
 );
 
-named!(rule_part<&[u8], Vec<String> >,
+named!(rule_part<&[u8], ParsedFact>,
     ws!(do_parse!(
         tag!("(") >>
-        args: many1!(map!(alt!(name | var), |c| String::from_utf8(c.to_vec()).unwrap())) >>
+        pred: name >>
+        args: many0!(term) >>
         tag!(")") >>
-        (args)
+        (ParsedFact::new(to_string(pred), args))
     ))
 );
 
+named!(fact<&[u8], ParsedFact>,
+    ws!(do_parse!(
+        tag!("fact:") >>
+        f: rule_part >>
+        start: opt!(preceded!(tag!("from:"), time)) >>
+        end: opt!(preceded!(tag!("to:"), time)) >>
+        (ParsedFact { start, end, ..f })
+    ))
+);
+
+named!(time<&[u8], i64>,
+    ws!(map_res!(map!(number, to_string), |n: String| n.parse::<i64>()))
+);
+
 named!(rule<&[u8], ParsedRule>,
     ws!(do_parse!(
         tag!("rule:") >>
@@ -105,18 +490,137 @@ named!(rule<&[u8], ParsedRule>,
         lhs: many1!(rule_part) >>
         tag!(")") >>
         tag!("->") >>
-        rhs: rule_part >>
-        (ParsedRule::new(lhs, rhs))
+        rhs: many1!(rule_part) >>
+        salience: opt!(salience) >>
+        (ParsedRule {
+            lhs,
+            rhs,
+            salience: salience.unwrap_or(0),
+        })
+    ))
+);
+
+named!(production<&[u8], ParsedProduction>,
+    ws!(do_parse!(
+        tag!("production:") >>
+        tag!("(") >>
+        lhs: many1!(rule_part) >>
+        tag!(")") >>
+        tag!("->") >>
+        rhs: many1!(rule_part) >>
+        salience: opt!(salience) >>
+        (ParsedProduction::new(lhs, rhs, salience.unwrap_or(0)))
+    ))
+);
+
+named!(salience<&[u8], i32>,
+    ws!(preceded!(
+        tag!("salience:"),
+        map_res!(map!(number, to_string), |n: String| n.parse::<i32>())
+    ))
+);
+
+named!(constraint<&[u8], ParsedConstraint>,
+    ws!(do_parse!(
+        tag!("constraint:") >>
+        tag!("(") >>
+        body: many1!(rule_part) >>
+        tag!(")") >>
+        (ParsedConstraint::new(body))
     ))
 );
 
+named!(predicate<&[u8], ParsedPredicate>,
+    ws!(do_parse!(
+        tag!("pred:") >>
+        tag!("(") >>
+        pred: name >>
+        sorts: many0!(map!(name, to_string)) >>
+        tag!(")") >>
+        (ParsedPredicate::new(to_string(pred), sorts))
+    ))
+);
+
+named!(fact_list<&[u8], Vec<ParsedFact> >,
+    ws!(do_parse!(
+        tag!("(") >>
+        facts: many0!(rule_part) >>
+        tag!(")") >>
+        (facts)
+    ))
+);
+
+named!(preconditions<&[u8], Vec<ParsedFact> >,
+    ws!(preceded!(tag!("pre:"), fact_list))
+);
+
+named!(add_list<&[u8], Vec<ParsedFact> >,
+    ws!(preceded!(tag!("add:"), fact_list))
+);
+
+named!(delete_list<&[u8], Vec<ParsedFact> >,
+    ws!(preceded!(tag!("del:"), fact_list))
+);
+
+named!(action<&[u8], ParsedAction>,
+    ws!(do_parse!(
+        tag!("action:") >>
+        head: rule_part >>
+        pre: opt!(preconditions) >>
+        add: opt!(add_list) >>
+        del: opt!(delete_list) >>
+        (ParsedAction::new(
+            head,
+            pre.unwrap_or_default(),
+            add.unwrap_or_default(),
+            del.unwrap_or_default()
+        ))
+    ))
+);
+
+named!(key<&[u8], ParsedKey>,
+    ws!(do_parse!(
+        tag!("key:") >>
+        tag!("(") >>
+        pred: name >>
+        args: many1!(map_res!(map!(digit, to_string), |n: String| n.parse::<usize>())) >>
+        tag!(")") >>
+        (ParsedKey::new(to_string(pred), args))
+    ))
+);
+
+named!(namespace<&[u8]>,
+    ws!(preceded!(tag!("as"), name))
+);
+
+named!(include<&[u8], ParsedInclude>,
+    ws!(do_parse!(
+        tag!("include") >>
+        path: delimited!(tag!("\""), take_until!("\""), tag!("\"")) >>
+        namespace: opt!(namespace) >>
+        (ParsedInclude::new(to_string(path), namespace.map(to_string)))
+    ))
+);
+
+named!(statement<&[u8], ParsedStatement>,
+    alt!(
+        map!(include, ParsedStatement::Include) |
+        map!(predicate, ParsedStatement::Pred) |
+        map!(fact, ParsedStatement::Fact) |
+        map!(rule, ParsedStatement::Rule) |
+        map!(production, ParsedStatement::Production) |
+        map!(constraint, ParsedStatement::Constraint) |
+        map!(key, ParsedStatement::Key) |
+        map!(action, ParsedStatement::Action)
+    )
+);
+
 named!(kb<&[u8], ParsedKnowledgeBase>,
     ws!(do_parse!(
         tag!("kb") >>
         tag!("{") >>
-        facts: many1!(fact) >>
-        rules: many1!(rule) >>
-        (ParsedKnowledgeBase { facts, rules })
+        statements: many0!(statement) >>
+        (ParsedKnowledgeBase::from_statements(statements))
     ))
 );
 
@@ -124,23 +628,122 @@ named!(kb<&[u8], ParsedKnowledgeBase>,
 mod parse_tests {
     use super::*;
 
+    fn parsed_fact(tokens: &[&str]) -> ParsedFact {
+        ParsedFact::new(
+            String::from(tokens[0]),
+            tokens[1..]
+                .iter()
+                .map(|w| ParsedTerm::Symbol(String::from(*w)))
+                .collect(),
+        )
+    }
+
     #[test]
     fn parse_fact() {
         assert_eq!(
             fact(&b"fact: (isa cube box)eol"[..]),
+            Ok((&b"eol"[..], parsed_fact(&["isa", "cube", "box"])))
+        );
+    }
+
+    #[test]
+    fn parse_fact_interval() {
+        let mut guard = parsed_fact(&["at", "guard", "gate"]);
+        guard.start = Some(10);
+        guard.end = Some(40);
+        assert_eq!(
+            fact(&b"fact: (at guard gate) from: 10 to: 40;"[..]),
+            Ok((&b";"[..], guard.clone()))
+        );
+
+        guard.start = None;
+        assert_eq!(
+            fact(&b"fact: (at guard gate) to: 40;"[..]),
+            Ok((&b";"[..], guard))
+        );
+    }
+
+    #[test]
+    fn parse_zero_arity_fact() {
+        assert_eq!(
+            fact(&b"fact: (raining)eol"[..]),
+            Ok((
+                &b"eol"[..],
+                ParsedFact::new(String::from("raining"), vec![])
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_compound_fact() {
+        assert_eq!(
+            fact(&b"fact: (holds (location (owner box)) kitchen)eol"[..]),
+            Ok((
+                &b"eol"[..],
+                ParsedFact::new(
+                    String::from("holds"),
+                    vec![
+                        ParsedTerm::Compound(
+                            String::from("location"),
+                            vec![ParsedTerm::Compound(
+                                String::from("owner"),
+                                vec![ParsedTerm::Symbol(String::from("box"))],
+                            )],
+                        ),
+                        ParsedTerm::Symbol(String::from("kitchen")),
+                    ],
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_list_fact() {
+        assert_eq!(
+            fact(&b"fact: (path [a 12 []] [?h | ?t])eol"[..]),
             Ok((
                 &b"eol"[..],
                 ParsedFact::new(
-                    String::from("isa"),
-                    vec!["cube", "box"]
-                        .into_iter()
-                        .map(|w| String::from(w))
-                        .collect(),
+                    String::from("path"),
+                    vec![
+                        ParsedTerm::List(
+                            vec![
+                                ParsedTerm::Symbol(String::from("a")),
+                                ParsedTerm::Symbol(String::from("12")),
+                                ParsedTerm::List(vec![], None),
+                            ],
+                            None,
+                        ),
+                        ParsedTerm::List(
+                            vec![ParsedTerm::Symbol(String::from("?h"))],
+                            Some(Box::new(ParsedTerm::Symbol(String::from("?t")))),
+                        ),
+                    ],
                 )
             ))
         );
     }
 
+    #[test]
+    fn parse_lone_term() {
+        assert_eq!(
+            parse_term(b"[a (f b)]"),
+            Ok(ParsedTerm::List(
+                vec![
+                    ParsedTerm::Symbol(String::from("a")),
+                    ParsedTerm::Compound(
+                        String::from("f"),
+                        vec![ParsedTerm::Symbol(String::from("b"))]
+                    ),
+                ],
+                None,
+            ))
+        );
+        assert_eq!(parse_term(b"kitchen"), Ok(ParsedTerm::Symbol(String::from("kitchen"))));
+        assert!(parse_term(b"kitchen hall").is_err());
+        assert!(parse_term(b"").is_err());
+    }
+
     #[test]
     fn parse_rule() {
         assert_eq!(
@@ -148,14 +751,11 @@ mod parse_tests {
             Ok((
                 &b"eol"[..],
                 ParsedRule::new(
-                    vec![vec!["inst", "?x", "?y"], vec!["isa", "?y", "?z"]]
-                        .into_iter()
-                        .map(|lst| lst.into_iter().map(|w| String::from(w)).collect())
-                        .collect(),
-                    vec!["inst", "?x", "?z"]
-                        .into_iter()
-                        .map(|w| String::from(w))
-                        .collect(),
+                    vec![
+                        parsed_fact(&["inst", "?x", "?y"]),
+                        parsed_fact(&["isa", "?y", "?z"]),
+                    ],
+                    vec![parsed_fact(&["inst", "?x", "?z"])],
                 )
             ))
         )
@@ -168,17 +768,229 @@ mod parse_tests {
             Ok((
                 &b"}"[..],
                 ParsedKnowledgeBase {
-                    facts: vec![ParsedFact::new(
-                        String::from("isa"),
-                        vec!["cube","box"].into_iter().map(|w| String::from(w)).collect()
-                    ), ParsedFact::new(
-                        String::from("isa"),
-                        vec!["box","container"].into_iter().map(|w| String::from(w)).collect()
-                    )],
+                    preds: vec![],
+                    facts: vec![
+                        parsed_fact(&["isa", "cube", "box"]),
+                        parsed_fact(&["isa", "box", "container"]),
+                    ],
                     rules: vec![ParsedRule::new(
-                        vec![vec!["inst","?x","?y"],vec!["isa","?y","?z"]].into_iter().map(|lst| lst.into_iter().map(|w| String::from(w)).collect()).collect(),
-                        vec!["inst", "?x", "?z"].into_iter().map(|w| String::from(w)).collect()
-                    )]
+                        vec![
+                            parsed_fact(&["inst", "?x", "?y"]),
+                            parsed_fact(&["isa", "?y", "?z"]),
+                        ],
+                        vec![parsed_fact(&["inst", "?x", "?z"])],
+                    )],
+                    constraints: vec![],
+                    keys: vec![],
+                    actions: vec![],
+                    productions: vec![],
+                    includes: vec![],
+                }
+            ))
+        )
+    }
+
+    #[test]
+    fn parse_rule_salience() {
+        assert_eq!(
+            rule(&b"rule: ((alarm ?x)) -> (flee ?x) salience: -5;"[..]),
+            Ok((
+                &b";"[..],
+                ParsedRule {
+                    lhs: vec![parsed_fact(&["alarm", "?x"])],
+                    rhs: vec![parsed_fact(&["flee", "?x"])],
+                    salience: -5,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_rule_multiple_conclusions() {
+        assert_eq!(
+            rule(&b"rule: ((parent ?x ?y)) -> (ancestor ?x ?y) (related ?x ?y);"[..]),
+            Ok((
+                &b";"[..],
+                ParsedRule::new(
+                    vec![parsed_fact(&["parent", "?x", "?y"])],
+                    vec![
+                        parsed_fact(&["ancestor", "?x", "?y"]),
+                        parsed_fact(&["related", "?x", "?y"]),
+                    ],
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_production() {
+        assert_eq!(
+            production(
+                &b"production: ((hungry ?x)) -> (eating ?x) (retract (hungry ?x)) salience: 2;"[..]
+            ),
+            Ok((
+                &b";"[..],
+                ParsedProduction::new(
+                    vec![parsed_fact(&["hungry", "?x"])],
+                    vec![
+                        parsed_fact(&["eating", "?x"]),
+                        ParsedFact::new(
+                            String::from("retract"),
+                            vec![ParsedTerm::Compound(
+                                String::from("hungry"),
+                                vec![ParsedTerm::Symbol(String::from("?x"))],
+                            )],
+                        ),
+                    ],
+                    2,
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_constraint() {
+        assert_eq!(
+            constraint(&b"constraint: ((on ?x ?x))eol"[..]),
+            Ok((
+                &b"eol"[..],
+                ParsedConstraint::new(vec![parsed_fact(&["on", "?x", "?x"])])
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_key() {
+        assert_eq!(
+            key(&b"key: (location 1)eol"[..]),
+            Ok((&b"eol"[..], ParsedKey::new(String::from("location"), vec![1])))
+        );
+    }
+
+    #[test]
+    fn parse_include() {
+        assert_eq!(
+            include(&b"include \"factions/north.kb\" as north eol"[..]),
+            Ok((
+                &b"eol"[..],
+                ParsedInclude::new(String::from("factions/north.kb"), Some(String::from("north")))
+            ))
+        );
+        assert_eq!(
+            include(&b"include \"world.kb\" eol"[..]),
+            Ok((&b"eol"[..], ParsedInclude::new(String::from("world.kb"), None)))
+        );
+    }
+
+    #[test]
+    fn parse_qualified_fact() {
+        assert_eq!(
+            fact(&b"fact: (north::ally guard)eol"[..]),
+            Ok((&b"eol"[..], parsed_fact(&["north::ally", "guard"])))
+        );
+    }
+
+    #[test]
+    fn parse_from_file_with_includes() {
+        let pkb = parse_kb_from_file("test/include/game.kb").unwrap();
+        let preds: Vec<&str> = pkb.facts.iter().map(|f| f.pred.as_str()).collect();
+        assert_eq!(preds, vec!["at", "at", "north::ally", "south::ally", "south::ally"]);
+        assert_eq!(pkb.preds[0].name, "at");
+
+        let rule = &pkb.rules[0];
+        assert_eq!(rule.lhs[0].pred, "north::ally");
+        assert_eq!(rule.lhs[1].pred, "at");
+        assert_eq!(rule.rhs[0].pred, "north::holds");
+        assert!(pkb.includes.is_empty());
+    }
+
+    #[test]
+    fn qualify_nested_facts() {
+        let mut pkb = ParsedKnowledgeBase::new(
+            vec![
+                fact(&b"fact: (at guard gate)eol"[..]).unwrap().1,
+                fact(&b"fact: (route (at gate))eol"[..]).unwrap().1,
+            ],
+            vec![rule(&b"rule: ((count ?n ?x (at ?x gate))) -> (guards ?n)eol"[..]).unwrap().1],
+        );
+        pkb.productions.push(
+            production(&b"production: ((route ?r)) -> (retract (at guard gate))eol"[..])
+                .unwrap()
+                .1,
+        );
+        pkb.qualify("north");
+
+        assert_eq!(
+            pkb.facts[1].args[0],
+            ParsedTerm::Compound(String::from("at"), vec![ParsedTerm::Symbol(String::from("gate"))])
+        );
+        match pkb.rules[0].lhs[0].args[2] {
+            ParsedTerm::Compound(ref functor, _) => assert_eq!(functor, "north::at"),
+            _ => panic!("aggregate pattern should stay a compound term"),
+        }
+        match pkb.productions[0].rhs[0].args[0] {
+            ParsedTerm::Compound(ref functor, _) => assert_eq!(functor, "north::at"),
+            _ => panic!("retract target should stay a compound term"),
+        }
+    }
+
+    #[test]
+    fn parse_include_cycle() {
+        let error = parse_kb_from_file("test/include/cycle_a.kb").unwrap_err();
+        assert!(error.starts_with("Include cycle: "));
+        assert!(error.ends_with("cycle_a.kb"));
+        assert!(parse_kb_from_file("test/include/missing.kb").is_err());
+    }
+
+    #[test]
+    fn parse_action() {
+        assert_eq!(
+            action(&b"action: (move ?b ?x ?y)\n pre: ((on ?b ?x) (clear ?y))\n add: ((on ?b ?y))\n del: ((on ?b ?x))eol"[..]),
+            Ok((
+                &b"eol"[..],
+                ParsedAction::new(
+                    parsed_fact(&["move", "?b", "?x", "?y"]),
+                    vec![
+                        parsed_fact(&["on", "?b", "?x"]),
+                        parsed_fact(&["clear", "?y"]),
+                    ],
+                    vec![parsed_fact(&["on", "?b", "?y"])],
+                    vec![parsed_fact(&["on", "?b", "?x"])],
+                )
+            ))
+        );
+        assert_eq!(
+            action(&b"action: (wait) del: ((awake))eol"[..]),
+            Ok((
+                &b"eol"[..],
+                ParsedAction::new(
+                    parsed_fact(&["wait"]),
+                    vec![],
+                    vec![],
+                    vec![parsed_fact(&["awake"])],
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_predicate_declaration() {
+        assert_eq!(
+            kb(&b"kb {\nfact: (on a b)\npred: (on block block)\n}"[..]),
+            Ok((
+                &b"}"[..],
+                ParsedKnowledgeBase {
+                    preds: vec![ParsedPredicate::new(
+                        String::from("on"),
+                        vec![String::from("block"), String::from("block")],
+                    )],
+                    facts: vec![parsed_fact(&["on", "a", "b"])],
+                    rules: vec![],
+                    constraints: vec![],
+                    keys: vec![],
+                    actions: vec![],
+                    productions: vec![],
+                    includes: vec![],
                 }
             ))
         )
@@ -190,33 +1002,16 @@ mod parse_tests {
             parse_kb_from_file("test/test.kb"),
             Ok(ParsedKnowledgeBase::new(
                 vec![
-                    ParsedFact::new(
-                        String::from("isa"),
-                        vec!["cube", "box"]
-                            .into_iter()
-                            .map(|w| String::from(w))
-                            .collect(),
-                    ),
-                    ParsedFact::new(
-                        String::from("isa"),
-                        vec!["box", "container"]
-                            .into_iter()
-                            .map(|w| String::from(w))
-                            .collect(),
-                    ),
-                ],
-                vec![
-                    ParsedRule::new(
-                        vec![vec!["inst", "?x", "?y"], vec!["isa", "?y", "?z"]]
-                            .into_iter()
-                            .map(|lst| lst.into_iter().map(|w| String::from(w)).collect())
-                            .collect(),
-                        vec!["inst", "?x", "?z"]
-                            .into_iter()
-                            .map(|w| String::from(w))
-                            .collect(),
-                    ),
+                    parsed_fact(&["isa", "cube", "box"]),
+                    parsed_fact(&["isa", "box", "container"]),
                 ],
+                vec![ParsedRule::new(
+                    vec![
+                        parsed_fact(&["inst", "?x", "?y"]),
+                        parsed_fact(&["isa", "?y", "?z"]),
+                    ],
+                    vec![parsed_fact(&["inst", "?x", "?z"])],
+                )],
             ))
         )
     }
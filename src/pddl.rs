@@ -0,0 +1,548 @@
+use nom::*;
+use parser::{ParsedAction, ParsedFact, ParsedKnowledgeBase, ParsedPredicate, ParsedTerm};
+
+use std::collections::HashMap;
+use std::fs;
+
+// Requirements whose features can be expressed in a knowledge base
+const SUPPORTED_REQUIREMENTS: &[&str] = &[":strips", ":typing", ":equality"];
+
+// The root of every type hierarchy
+const OBJECT: &str = "object";
+
+// A parenthesized PDDL expression, or a single name within one
+#[derive(Debug, PartialEq, Clone)]
+enum Expr {
+    Atom(String),
+    List(Vec<Expr>),
+}
+
+impl Expr {
+    fn as_atom(&self) -> Option<&str> {
+        match *self {
+            Expr::Atom(ref a) => Some(a),
+            Expr::List(_) => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&[Expr]> {
+        match *self {
+            Expr::List(ref items) => Some(items),
+            Expr::Atom(_) => None,
+        }
+    }
+
+    // Returns the items of a list starting with the given keyword, without the keyword
+    fn section(&self, keyword: &str) -> Option<&[Expr]> {
+        match self.as_list() {
+            Some(items) if !items.is_empty() && items[0].as_atom() == Some(keyword) => {
+                Some(&items[1..])
+            }
+            _ => None,
+        }
+    }
+}
+
+fn render(expr: &Expr) -> String {
+    match *expr {
+        Expr::Atom(ref a) => a.clone(),
+        Expr::List(ref items) => {
+            let items: Vec<String> = items.iter().map(render).collect();
+            format!("({})", items.join(" "))
+        }
+    }
+}
+
+fn is_atom_char(c: u8) -> bool {
+    !(c as char).is_whitespace() && c != b'(' && c != b')'
+}
+
+named!(atom<&[u8], Expr>,
+    map!(take_while1!(is_atom_char), |a| {
+        Expr::Atom(String::from_utf8_lossy(a).to_lowercase())
+    })
+);
+
+named!(list<&[u8], Expr>,
+    ws!(do_parse!(
+        tag!("(") >>
+        items: many0!(expr) >>
+        tag!(")") >>
+        (Expr::List(items))
+    ))
+);
+
+named!(expr<&[u8], Expr>,
+    ws!(alt!(list | atom))
+);
+
+// Parses one top level expression, ignoring comments, which run from a semicolon to the end of
+// the line
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut stripped: String = text
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or(""))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    // The parser works on streams, so it needs to see something after the expression to know
+    // that the expression is complete. A closing parenthesis can never start an expression.
+    stripped.push_str("\n)");
+
+    match list(stripped.as_bytes()) {
+        Ok((rest, e)) if rest == b")" => Ok(e),
+        _ => Err(String::from("Failed to parse PDDL expression")),
+    }
+}
+
+// The parts of a domain needed to read a problem for it
+struct Domain {
+    name: String,
+    parents: HashMap<String, String>,
+}
+
+impl Domain {
+    // Returns the type and every type it descends from, ending in object
+    fn ancestors(&self, kind: &str) -> Vec<String> {
+        let mut ancestors = vec![kind.to_string()];
+        let mut current = kind;
+        while let Some(parent) = self.parents.get(current) {
+            if ancestors.contains(parent) {
+                break;
+            }
+            ancestors.push(parent.clone());
+            current = parent;
+        }
+        if !ancestors.iter().any(|a| a == OBJECT) {
+            ancestors.push(OBJECT.to_string());
+        }
+        ancestors
+    }
+
+    // Returns facts stating that an object is of the given type and of every type above it
+    fn membership(&self, object: &str, kind: &str) -> Vec<ParsedFact> {
+        self.ancestors(kind)
+            .into_iter()
+            .map(|kind| ParsedFact::new(kind, vec![symbol(object)]))
+            .collect()
+    }
+}
+
+fn symbol(name: &str) -> ParsedTerm {
+    ParsedTerm::Symbol(name.to_string())
+}
+
+/// Reads a PDDL domain file and a PDDL problem file
+///
+/// The result is a parsed knowledge base holding the domain's predicates and actions and the
+/// problem's objects and initial facts, along with the facts of the problem's goal. Types become
+/// unary facts, so an object a of type block gives (block a) and (object a), and typed parameters
+/// become extra preconditions. Requirements and constructs a knowledge base cannot express, such
+/// as negative preconditions or conditional effects, are reported as errors.
+pub fn parse_pddl_from_files(
+    domain_file: &str,
+    problem_file: &str,
+) -> Result<(ParsedKnowledgeBase, Vec<ParsedFact>), String> {
+    let read = |filename: &str| {
+        fs::read_to_string(filename).map_err(|_| format!("Failed to read PDDL file {}", filename))
+    };
+    parse_pddl(&read(domain_file)?, &read(problem_file)?)
+}
+
+// Reads PDDL domain and problem definitions from strings
+fn parse_pddl(
+    domain_text: &str,
+    problem_text: &str,
+) -> Result<(ParsedKnowledgeBase, Vec<ParsedFact>), String> {
+    let mut pkb = ParsedKnowledgeBase::new(vec![], vec![]);
+    let domain = read_domain(&parse_expr(domain_text)?, &mut pkb)?;
+    let goal = read_problem(&parse_expr(problem_text)?, &domain, &mut pkb)?;
+
+    // Objects of types sharing an ancestor, or listed as constants and again as objects, would
+    // otherwise repeat membership facts
+    let mut facts: Vec<ParsedFact> = Vec::new();
+    for fact in pkb.facts.drain(..) {
+        if !facts.contains(&fact) {
+            facts.push(fact);
+        }
+    }
+    pkb.facts = facts;
+
+    Ok((pkb, goal))
+}
+
+fn read_domain(definition: &Expr, pkb: &mut ParsedKnowledgeBase) -> Result<Domain, String> {
+    let items = definition
+        .section("define")
+        .ok_or("PDDL domain must start with define")?;
+
+    let name = match items.first().and_then(|e| e.section("domain")) {
+        Some([Expr::Atom(name)]) => name.clone(),
+        _ => return Err(String::from("PDDL domain must be named with (domain <name>)")),
+    };
+
+    let mut domain = Domain {
+        name,
+        parents: HashMap::new(),
+    };
+
+    for section in &items[1..] {
+        if let Some(requirements) = section.section(":requirements") {
+            check_requirements(requirements)?;
+        } else if let Some(types) = section.section(":types") {
+            for (kind, parent) in typed_list(types)? {
+                if kind != OBJECT {
+                    domain.parents.insert(kind, parent);
+                }
+            }
+        } else if let Some(constants) = section.section(":constants") {
+            for (object, kind) in typed_list(constants)? {
+                pkb.facts.extend(domain.membership(&object, &kind));
+            }
+        } else if let Some(predicates) = section.section(":predicates") {
+            for predicate in predicates {
+                pkb.preds.push(read_predicate(predicate)?);
+            }
+        } else if let Some(action) = section.section(":action") {
+            pkb.actions.push(read_action(action)?);
+        } else {
+            return Err(format!("Unsupported PDDL domain section {}", render(section)));
+        }
+    }
+
+    Ok(domain)
+}
+
+fn read_problem(
+    definition: &Expr,
+    domain: &Domain,
+    pkb: &mut ParsedKnowledgeBase,
+) -> Result<Vec<ParsedFact>, String> {
+    let items = definition
+        .section("define")
+        .ok_or("PDDL problem must start with define")?;
+
+    if items.first().and_then(|e| e.section("problem")).is_none() {
+        return Err(String::from("PDDL problem must be named with (problem <name>)"));
+    }
+
+    let mut goal = Vec::new();
+    for section in &items[1..] {
+        if let Some(name) = section.section(":domain") {
+            if name.len() != 1 || name[0].as_atom() != Some(&domain.name[..]) {
+                return Err(format!(
+                    "PDDL problem is for domain {} instead of {}",
+                    render(section),
+                    domain.name
+                ));
+            }
+        } else if let Some(requirements) = section.section(":requirements") {
+            check_requirements(requirements)?;
+        } else if let Some(objects) = section.section(":objects") {
+            for (object, kind) in typed_list(objects)? {
+                pkb.facts.extend(domain.membership(&object, &kind));
+            }
+        } else if let Some(init) = section.section(":init") {
+            for fact in init {
+                pkb.facts.push(read_atom(fact)?);
+            }
+        } else if let Some(formula) = section.section(":goal") {
+            match formula {
+                [formula] => goal = read_conjunction(formula, "goal")?,
+                _ => return Err(String::from("PDDL goal must be a single formula")),
+            }
+        } else {
+            return Err(format!("Unsupported PDDL problem section {}", render(section)));
+        }
+    }
+
+    Ok(goal)
+}
+
+fn check_requirements(requirements: &[Expr]) -> Result<(), String> {
+    for requirement in requirements {
+        let name = requirement.as_atom().unwrap_or("");
+        if !SUPPORTED_REQUIREMENTS.contains(&name) {
+            return Err(format!("Unsupported PDDL requirement {}", render(requirement)));
+        }
+    }
+    Ok(())
+}
+
+// Reads a list such as `a b - block c` into names paired with their types. Names without a
+// type are objects.
+fn typed_list(items: &[Expr]) -> Result<Vec<(String, String)>, String> {
+    let mut typed = Vec::new();
+    let mut pending = Vec::new();
+    let mut iter = items.iter();
+
+    while let Some(item) = iter.next() {
+        match item.as_atom() {
+            Some("-") => {
+                let kind = match iter.next().and_then(|e| e.as_atom()) {
+                    Some(kind) => kind.to_string(),
+                    None => {
+                        return Err(format!(
+                            "Unsupported PDDL type in {}",
+                            render(&Expr::List(items.to_vec()))
+                        ))
+                    }
+                };
+                for name in pending.drain(..) {
+                    typed.push((name, kind.clone()));
+                }
+            }
+            Some(name) => pending.push(name.to_string()),
+            None => return Err(format!("Unexpected {} in a typed list", render(item))),
+        }
+    }
+
+    for name in pending {
+        typed.push((name, OBJECT.to_string()));
+    }
+    Ok(typed)
+}
+
+fn read_predicate(predicate: &Expr) -> Result<ParsedPredicate, String> {
+    match predicate.as_list() {
+        Some(items) if !items.is_empty() => {
+            let name = items[0]
+                .as_atom()
+                .ok_or_else(|| format!("Invalid PDDL predicate {}", render(predicate)))?;
+            let sorts = typed_list(&items[1..])?
+                .into_iter()
+                .map(|(_, kind)| kind)
+                .collect();
+            Ok(ParsedPredicate::new(name.to_string(), sorts))
+        }
+        _ => Err(format!("Invalid PDDL predicate {}", render(predicate))),
+    }
+}
+
+fn read_action(action: &[Expr]) -> Result<ParsedAction, String> {
+    let name = match action.first().and_then(|e| e.as_atom()) {
+        Some(name) => name,
+        None => return Err(String::from("PDDL action must have a name")),
+    };
+
+    let mut params = Vec::new();
+    let mut pre = Vec::new();
+    let mut add = Vec::new();
+    let mut del = Vec::new();
+
+    let mut iter = action[1..].iter();
+    while let Some(keyword) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("Missing value for {} in action {}", render(keyword), name))?;
+
+        match keyword.as_atom() {
+            Some(":parameters") => {
+                params = typed_list(value.as_list().unwrap_or(&[]))?;
+            }
+            Some(":precondition") => pre = read_conjunction(value, "precondition")?,
+            Some(":effect") => read_effect(value, &mut add, &mut del)?,
+            _ => {
+                return Err(format!(
+                    "Unsupported PDDL action field {} in action {}",
+                    render(keyword),
+                    name
+                ))
+            }
+        }
+    }
+
+    // Types of parameters are checked after the preconditions, so the preconditions bind the
+    // parameters before their types are looked up
+    for (param, kind) in &params {
+        pre.push(ParsedFact::new(kind.clone(), vec![symbol(param)]));
+    }
+
+    let head = ParsedFact::new(
+        name.to_string(),
+        params.iter().map(|(param, _)| symbol(param)).collect(),
+    );
+    Ok(ParsedAction::new(head, pre, add, del))
+}
+
+// Reads a formula that is a single atom or a conjunction of atoms. Inequalities written as
+// (not (= ?x ?y)) become the builtin (different ?x ?y).
+fn read_conjunction(formula: &Expr, context: &str) -> Result<Vec<ParsedFact>, String> {
+    if let Some(conjuncts) = formula.section("and") {
+        let mut facts = Vec::new();
+        for conjunct in conjuncts {
+            facts.extend(read_conjunction(conjunct, context)?);
+        }
+        return Ok(facts);
+    }
+
+    if let Some([negated]) = formula.section("not") {
+        if let Some([a, b]) = negated.section("=") {
+            return Ok(vec![ParsedFact::new(
+                String::from("different"),
+                vec![read_term(a)?, read_term(b)?],
+            )]);
+        }
+        return Err(format!(
+            "Unsupported negative {} {}",
+            context,
+            render(formula)
+        ));
+    }
+
+    Ok(vec![read_formula_atom(formula, context)?])
+}
+
+// Reads an effect that is a conjunction of atoms to add and negated atoms to delete
+fn read_effect(
+    formula: &Expr,
+    add: &mut Vec<ParsedFact>,
+    del: &mut Vec<ParsedFact>,
+) -> Result<(), String> {
+    if let Some(conjuncts) = formula.section("and") {
+        for conjunct in conjuncts {
+            read_effect(conjunct, add, del)?;
+        }
+        return Ok(());
+    }
+
+    if let Some([negated]) = formula.section("not") {
+        del.push(read_formula_atom(negated, "effect")?);
+        return Ok(());
+    }
+
+    add.push(read_formula_atom(formula, "effect")?);
+    Ok(())
+}
+
+// Reads an atom of a formula, rejecting the logical connectives a knowledge base cannot express
+fn read_formula_atom(formula: &Expr, context: &str) -> Result<ParsedFact, String> {
+    const CONNECTIVES: &[&str] = &[
+        "or", "not", "imply", "exists", "forall", "when", "=", "increase", "decrease", "assign",
+    ];
+
+    let head = formula
+        .as_list()
+        .and_then(|items| items.first())
+        .and_then(|e| e.as_atom());
+    match head {
+        Some(connective) if CONNECTIVES.contains(&connective) => Err(format!(
+            "Unsupported {} {}",
+            context,
+            render(formula)
+        )),
+        _ => read_atom(formula),
+    }
+}
+
+// Reads an atom such as (on ?x table)
+fn read_atom(atom: &Expr) -> Result<ParsedFact, String> {
+    match atom.as_list() {
+        Some(items) if !items.is_empty() && items[0].as_atom().is_some() => {
+            let args = items[1..]
+                .iter()
+                .map(read_term)
+                .collect::<Result<Vec<ParsedTerm>, String>>()?;
+            Ok(ParsedFact::new(items[0].as_atom().unwrap().to_string(), args))
+        }
+        _ => Err(format!("Invalid PDDL atom {}", render(atom))),
+    }
+}
+
+fn read_term(term: &Expr) -> Result<ParsedTerm, String> {
+    match term.as_atom() {
+        Some(name) => Ok(symbol(name)),
+        None => Err(format!("Unsupported PDDL term {}", render(term))),
+    }
+}
+
+#[cfg(test)]
+mod pddl_tests {
+    use super::*;
+
+    fn parsed_fact(tokens: &[&str]) -> ParsedFact {
+        ParsedFact::new(
+            String::from(tokens[0]),
+            tokens[1..].iter().map(|w| symbol(w)).collect(),
+        )
+    }
+
+    const DOMAIN: &str = "
+        ; a tiny domain
+        (define (domain Travel)
+          (:requirements :strips :typing)
+          (:types room - place)
+          (:predicates (at ?p - place) (path ?from ?to - room))
+          (:action go
+            :parameters (?from ?to - room)
+            :precondition (and (at ?from) (path ?from ?to))
+            :effect (and (at ?to) (not (at ?from)))))";
+
+    #[test]
+    fn parse_expression() {
+        assert_eq!(
+            parse_expr("(at ?x) ; comment"),
+            Ok(Expr::List(vec![
+                Expr::Atom(String::from("at")),
+                Expr::Atom(String::from("?x")),
+            ]))
+        );
+        assert!(parse_expr("(at ?x").is_err());
+    }
+
+    #[test]
+    fn parse_domain_and_problem() {
+        let problem = "
+            (define (problem walk) (:domain travel)
+              (:objects hall kitchen - room)
+              (:init (at hall) (path hall kitchen))
+              (:goal (at kitchen)))";
+
+        let (pkb, goal) = parse_pddl(DOMAIN, problem).unwrap();
+        assert_eq!(goal, vec![parsed_fact(&["at", "kitchen"])]);
+        assert_eq!(
+            pkb.preds[1],
+            ParsedPredicate::new(
+                String::from("path"),
+                vec![String::from("room"), String::from("room")],
+            )
+        );
+        assert!(pkb.facts.contains(&parsed_fact(&["place", "hall"])));
+        assert!(pkb.facts.contains(&parsed_fact(&["object", "kitchen"])));
+        assert!(pkb.facts.contains(&parsed_fact(&["path", "hall", "kitchen"])));
+
+        assert_eq!(
+            pkb.actions,
+            vec![ParsedAction::new(
+                parsed_fact(&["go", "?from", "?to"]),
+                vec![
+                    parsed_fact(&["at", "?from"]),
+                    parsed_fact(&["path", "?from", "?to"]),
+                    parsed_fact(&["room", "?from"]),
+                    parsed_fact(&["room", "?to"]),
+                ],
+                vec![parsed_fact(&["at", "?to"])],
+                vec![parsed_fact(&["at", "?from"])],
+            )]
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_pddl() {
+        let problem = "(define (problem p) (:domain travel) (:goal (at hall)))";
+
+        let requirement = DOMAIN.replace(":typing", ":typing :conditional-effects");
+        assert_eq!(
+            parse_pddl(&requirement, problem).err(),
+            Some(String::from("Unsupported PDDL requirement :conditional-effects"))
+        );
+
+        let negative = DOMAIN.replace("(path ?from ?to))", "(not (path ?from ?to)))");
+        assert_eq!(
+            parse_pddl(&negative, problem).err(),
+            Some(String::from("Unsupported negative precondition (not (path ?from ?to))"))
+        );
+
+        let other_domain = problem.replace("(:domain travel)", "(:domain sokoban)");
+        assert!(parse_pddl(DOMAIN, &other_domain).is_err());
+    }
+}
@@ -0,0 +1,432 @@
+use super::{Fact, KnowledgeBase};
+use parser::ParsedAction;
+use query;
+use symbols::{Symbol, SymbolTable};
+use term::Term;
+use unify::Substitution;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+/// An operator that changes the facts of a knowledge base
+///
+/// An action schema has a name and parameters, such as `(move ?b ?from ?to)`, preconditions that
+/// must hold before it can be taken, and effects listing the facts it adds and the facts it
+/// deletes. Binding every parameter gives an `Action`.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::KnowledgeBase;
+///
+/// let mut kb = KnowledgeBase::new();
+/// let schema = kb.create_action(
+///     "action: (move ?b ?from ?to) pre: ((on ?b ?from) (clear ?b) (clear ?to)) \
+///      add: ((on ?b ?to) (clear ?from)) del: ((on ?b ?from) (clear ?to));",
+/// );
+/// assert!(schema.is_ok());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct ActionSchema {
+    head: Fact,
+    pre: Vec<Fact>,
+    add: Vec<Fact>,
+    del: Vec<Fact>,
+}
+
+impl ActionSchema {
+    // Creates an action schema from the parser output, checking that its parameters are distinct
+    // variables that the preconditions bind, and that its effects only use its parameters
+    pub(crate) fn from(pa: &ParsedAction, symbols: &SymbolTable) -> Result<ActionSchema, String> {
+        let schema = ActionSchema {
+            head: Fact::from(&pa.head, symbols),
+            pre: pa.pre.iter().map(|pf| Fact::from(pf, symbols)).collect(),
+            add: pa.add.iter().map(|pf| Fact::from(pf, symbols)).collect(),
+            del: pa.del.iter().map(|pf| Fact::from(pf, symbols)).collect(),
+        };
+
+        let mut params = Vec::new();
+        for arg in &schema.head.args {
+            match arg.as_var() {
+                Some(var) if !var.is_anonymous() && !params.contains(var) => {
+                    params.push(var.clone())
+                }
+                _ => {
+                    return Err(format!(
+                        "Parameters of action {} must be distinct variables",
+                        schema.head
+                    ))
+                }
+            }
+        }
+
+        let mut bound = Vec::new();
+        for fact in &schema.pre {
+            for arg in &fact.args {
+                arg.collect_variables(&mut bound);
+            }
+        }
+        if let Some(param) = params.iter().find(|param| !bound.contains(param)) {
+            return Err(format!(
+                "Parameter {} of action {} is not bound by any precondition",
+                &**param, schema.head
+            ));
+        }
+
+        for effect in schema.add.iter().chain(schema.del.iter()) {
+            let mut used = Vec::new();
+            for arg in &effect.args {
+                arg.collect_variables(&mut used);
+            }
+            if let Some(var) = used.iter().find(|var| !params.contains(var)) {
+                return Err(format!(
+                    "Effect {} of action {} uses {}, which is not a parameter",
+                    effect, schema.head, &**var
+                ));
+            }
+        }
+
+        Ok(schema)
+    }
+
+    /// Returns the name of the action
+    pub fn name(&self) -> &str {
+        &self.head.pred
+    }
+
+    // Binds the parameters of the schema to the arguments of a call such as (move a b c)
+    pub(crate) fn bind(&self, call: &Fact) -> Result<Substitution, String> {
+        if call.args.len() != self.head.args.len() {
+            return Err(format!(
+                "Action {} expects {} parameters but {} has {}",
+                self.name(),
+                self.head.args.len(),
+                call,
+                call.args.len()
+            ));
+        }
+
+        let mut substitution = Substitution::new();
+        if !substitution.unify_facts(&self.head, call) {
+            return Err(format!("{} does not match action {}", call, self.head));
+        }
+        Ok(substitution)
+    }
+
+    // Checks the preconditions of the schema with its parameters bound, returning an error naming
+    // the first precondition that cannot hold together with the ones before it
+    pub(crate) fn check_preconditions(
+        &self,
+        kb: &KnowledgeBase,
+        substitution: &Substitution,
+    ) -> Result<(), String> {
+        let pre: Vec<Fact> = self.pre.iter().map(|f| substitution.apply(f)).collect();
+        for i in 0..pre.len() {
+            if query::first_solution(kb, &pre[..i + 1]).is_none() {
+                return Err(format!(
+                    "Precondition {} of {} does not hold",
+                    pre[i],
+                    substitution.apply(&self.head)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the facts the schema deletes and the facts it adds, with its parameters bound
+    pub(crate) fn effects(&self, substitution: &Substitution) -> (Vec<Fact>, Vec<Fact>) {
+        (
+            self.del.iter().map(|f| substitution.apply(f)).collect(),
+            self.add.iter().map(|f| substitution.apply(f)).collect(),
+        )
+    }
+
+    // Finds every action this schema allows in the current state of the knowledge base, along
+    // with the substitution binding its parameters
+    fn applicable(&self, kb: &KnowledgeBase) -> Vec<(Action, Substitution)> {
+        let mut found: Vec<(Action, Substitution)> = Vec::new();
+        for substitution in query::solve_goals(kb, &self.pre) {
+            let action = Action {
+                name: self.head.pred.clone(),
+                args: substitution.apply_args(&self.head),
+            };
+            if !found.iter().any(|(a, _)| *a == action) {
+                found.push((action, substitution));
+            }
+        }
+        found
+    }
+}
+
+/// An action schema with every parameter bound, such as `(move a b table)`
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Action {
+    name: Symbol,
+    args: Vec<Term>,
+}
+
+impl Action {
+    /// Returns the name of the action schema this action instantiates
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the values of the action's parameters, in order
+    pub fn args(&self) -> &[Term] {
+        &self.args
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rendered = format!("({}", &*self.name);
+        for arg in &self.args {
+            rendered.push_str(&format!(" {}", arg));
+        }
+        rendered.push(')');
+        f.pad(&rendered)
+    }
+}
+
+/// The facts that hold in one state visited by the planner
+///
+/// Only asserted facts are part of a state. Facts inferred from them by rules are recomputed for
+/// every state the planner looks at, so preconditions and goals can use them.
+#[derive(Debug)]
+pub struct PlanState<'a> {
+    facts: &'a [Fact],
+}
+
+impl<'a> PlanState<'a> {
+    /// Returns true if some asserted fact of the state matches the given fact, whose variables
+    /// match anything
+    pub fn contains(&self, fact: &Fact) -> bool {
+        self.facts
+            .iter()
+            .any(|f| Substitution::new().unify_facts(f, fact))
+    }
+
+    /// Returns the number of asserted facts in the state
+    pub fn len(&self) -> usize {
+        self.facts.len()
+    }
+
+    /// Returns true if nothing is asserted in the state
+    pub fn is_empty(&self) -> bool {
+        self.facts.is_empty()
+    }
+}
+
+// How many states the planner looks at before giving up, unless told otherwise
+pub(crate) const DEFAULT_PLAN_LIMIT: usize = 100_000;
+
+/// A heuristic estimating how many actions are needed to reach the goal from a state
+pub type Heuristic<'h> = &'h dyn Fn(&PlanState, &[Fact]) -> usize;
+
+/// The search strategy used by the planner
+pub enum Search<'h> {
+    /// Explores states in order of the number of actions taken, finding a shortest plan
+    BreadthFirst,
+    /// Explores states in order of the actions taken plus the heuristic's estimate of the actions
+    /// left. Plans are shortest as long as the heuristic never overestimates.
+    AStar(Heuristic<'h>),
+}
+
+/// Counts the goal facts that no asserted fact of the state matches
+///
+/// Facts only derived by rules are not counted as matches, and one action may achieve several
+/// goals, so this heuristic is cheap but can overestimate. Plans found with it are not always
+/// shortest.
+pub fn unsatisfied_goals(state: &PlanState, goal: &[Fact]) -> usize {
+    goal.iter().filter(|fact| !state.contains(fact)).count()
+}
+
+// A state reached by the search, and how it was reached
+struct Node {
+    state: Vec<Fact>,
+    parent: Option<usize>,
+    action: Option<Action>,
+    depth: usize,
+}
+
+// Searches for a sequence of actions that leads from the current state of the knowledge base to
+// a state where every goal fact holds
+//
+// A scratch copy of the knowledge base is moved from state to state, so that rules and
+// constraints apply to every state exactly as they do to the original. States that violate a
+// constraint are never expanded. A state reached again by a shorter sequence of actions is
+// searched again from there, and its longer entries are skipped when they come up. The search
+// gives up once it has looked at more states than the planning limit of the knowledge base.
+pub(crate) fn search(
+    kb: &KnowledgeBase,
+    goal: &[Fact],
+    strategy: &Search,
+) -> Result<Vec<Action>, String> {
+    let mut scratch = kb.scratch_copy();
+    let initial = asserted_state(&scratch);
+
+    let mut nodes = vec![Node {
+        state: initial.clone(),
+        parent: None,
+        action: None,
+        depth: 0,
+    }];
+    // The fewest actions known to reach each state
+    let mut best: HashMap<Vec<Fact>, usize> = HashMap::new();
+    best.insert(initial, 0);
+
+    let mut frontier = Frontier::new(strategy);
+    frontier.push(0, 0, &nodes[0].state, goal);
+
+    let mut looked_at = 0;
+    while let Some(current) = frontier.pop() {
+        if best[&nodes[current].state] < nodes[current].depth {
+            continue;
+        }
+        looked_at += 1;
+        if let Some(limit) = kb.plan_limit.filter(|&limit| looked_at > limit) {
+            return Err(format!(
+                "No sequence of actions reaches the goal within the planning limit of {} states",
+                limit
+            ));
+        }
+        if set_state(&mut scratch, &nodes[current].state).is_err() {
+            continue;
+        }
+
+        if query::first_solution(&scratch, goal).is_some() {
+            return Ok(path_to(&nodes, current));
+        }
+
+        for schema in &kb.actions {
+            for (action, substitution) in schema.applicable(&scratch) {
+                let next = match successor(&nodes[current].state, schema, &substitution) {
+                    Some(next) => next,
+                    None => continue,
+                };
+
+                let depth = nodes[current].depth + 1;
+                if let Some(&known) = best.get(&next) {
+                    if known <= depth {
+                        continue;
+                    }
+                }
+                best.insert(next.clone(), depth);
+                nodes.push(Node {
+                    state: next,
+                    parent: Some(current),
+                    action: Some(action),
+                    depth,
+                });
+                let index = nodes.len() - 1;
+                frontier.push(index, depth, &nodes[index].state, goal);
+            }
+        }
+    }
+
+    Err(String::from("No sequence of actions reaches the goal"))
+}
+
+// The nodes waiting to be expanded, in the order the strategy expands them
+enum Frontier<'h> {
+    Queue(VecDeque<usize>),
+    // Ordered by estimated plan length, then by insertion order to break ties
+    Heap(BinaryHeap<Reverse<(usize, usize, usize)>>, Heuristic<'h>, usize),
+}
+
+impl<'h> Frontier<'h> {
+    fn new(strategy: &Search<'h>) -> Frontier<'h> {
+        match *strategy {
+            Search::BreadthFirst => Frontier::Queue(VecDeque::new()),
+            Search::AStar(heuristic) => Frontier::Heap(BinaryHeap::new(), heuristic, 0),
+        }
+    }
+
+    fn push(&mut self, node: usize, depth: usize, state: &[Fact], goal: &[Fact]) {
+        match *self {
+            Frontier::Queue(ref mut queue) => queue.push_back(node),
+            Frontier::Heap(ref mut heap, heuristic, ref mut counter) => {
+                let estimate = depth + heuristic(&PlanState { facts: state }, goal);
+                *counter += 1;
+                heap.push(Reverse((estimate, *counter, node)));
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        match *self {
+            Frontier::Queue(ref mut queue) => queue.pop_front(),
+            Frontier::Heap(ref mut heap, ..) => heap.pop().map(|Reverse((_, _, node))| node),
+        }
+    }
+}
+
+// Returns the actions leading from the initial state to the given node
+fn path_to(nodes: &[Node], mut current: usize) -> Vec<Action> {
+    let mut actions = Vec::new();
+    while let Some(parent) = nodes[current].parent {
+        actions.push(nodes[current].action.clone().unwrap());
+        current = parent;
+    }
+    actions.reverse();
+    actions
+}
+
+// Applies the effects of an action to a state: deleted facts are removed before added facts are
+// put in, so an action may delete and add the same fact. Returns None if an effect is not ground.
+fn successor(
+    state: &[Fact],
+    schema: &ActionSchema,
+    substitution: &Substitution,
+) -> Option<Vec<Fact>> {
+    let (del, add) = schema.effects(substitution);
+    if add.iter().chain(del.iter()).any(|f| f.contains_variable()) {
+        return None;
+    }
+
+    let mut next: Vec<Fact> = state.iter().filter(|f| !del.contains(f)).cloned().collect();
+    for fact in add {
+        if !next.contains(&fact) {
+            next.push(fact);
+        }
+    }
+    next.sort_by(compare_facts);
+    Some(next)
+}
+
+// Returns the asserted facts of a knowledge base, stripped of support and sorted, so that equal
+// states compare equal
+fn asserted_state(kb: &KnowledgeBase) -> Vec<Fact> {
+    let mut state: Vec<Fact> = kb.facts
+        .iter()
+        .filter(|f| f.asserted)
+        .map(|f| Fact::new(f.pred.clone(), f.args.clone(), vec![]))
+        .collect();
+    state.sort_by(compare_facts);
+    state.dedup();
+    state
+}
+
+// Retracts and asserts facts until the asserted facts of the knowledge base are the given state,
+// and infers what follows from them. An error is returned if the state violates a constraint or
+// inference goes past a limit, in which case what was inferred is kept rather than undone.
+fn set_state(kb: &mut KnowledgeBase, state: &[Fact]) -> Result<(), String> {
+    let current = asserted_state(kb);
+    let (wanted, present): (HashSet<&Fact>, HashSet<&Fact>) =
+        (state.iter().collect(), current.iter().collect());
+
+    for fact in current.iter().filter(|f| !wanted.contains(f)) {
+        let _ = kb.remove_fact(fact);
+    }
+    for fact in state.iter().filter(|f| !present.contains(f)) {
+        let _ = kb.add_statement(fact.clone());
+    }
+    kb.run_within(None).map(|_| ())
+}
+
+fn compare_facts(a: &Fact, b: &Fact) -> Ordering {
+    (*a.pred)
+        .cmp(&*b.pred)
+        .then_with(|| a.args.cmp(&b.args))
+}
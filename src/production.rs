@@ -0,0 +1,203 @@
+use super::Fact;
+use parser::ParsedProduction;
+use symbols::{Symbol, SymbolTable};
+use term::Term;
+use unify::Substitution;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// Predicates of the effects that are not asserted as facts
+pub(crate) const RETRACT: &str = "retract";
+pub(crate) const CALL: &str = "call";
+
+/// A rule whose conclusion is a list of effects rather than a single fact
+///
+/// A production fires once for every complete match of its premises. Its effects assert facts,
+/// retract facts with `(retract (fact))`, or call a callback registered with
+/// `KnowledgeBase::register_callback` with `(call name args...)`. Facts asserted by a production
+/// are not supported by the facts it matched, and stay when those facts are retracted.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::KnowledgeBase;
+///
+/// let mut kb = KnowledgeBase::new();
+/// let production = kb.create_production(
+///     "production: ((hungry ?x) (food ?f)) -> (eating ?x ?f) (retract (food ?f)) (call eat ?x);",
+/// );
+/// assert!(production.is_ok());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Production {
+    pub(crate) lhs: Vec<Fact>,
+    pub(crate) rhs: Vec<Effect>,
+    pub(crate) salience: i32,
+}
+
+// One thing a production does when it fires
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub(crate) enum Effect {
+    Assert(Fact),
+    Retract(Fact),
+    Call(Symbol, Vec<Term>),
+}
+
+impl Production {
+    // Creates a production from the parser output, checking that every variable its effects use
+    // is bound by a premise
+    pub(crate) fn from(pp: &ParsedProduction, symbols: &SymbolTable) -> Result<Production, String> {
+        let lhs: Vec<Fact> = pp.lhs.iter().map(|pf| Fact::from(pf, symbols)).collect();
+        let rhs = pp
+            .rhs
+            .iter()
+            .map(|pf| Effect::from(Fact::from(pf, symbols)))
+            .collect::<Result<Vec<Effect>, String>>()?;
+        let production = Production {
+            lhs,
+            rhs,
+            salience: pp.salience,
+        };
+
+        let mut bound = Vec::new();
+        for fact in &production.lhs {
+            for arg in &fact.args {
+                arg.collect_variables(&mut bound);
+            }
+        }
+        for effect in &production.rhs {
+            let mut used = Vec::new();
+            for arg in effect.args() {
+                arg.collect_variables(&mut used);
+            }
+            if let Some(var) = used.iter().find(|var| !bound.contains(var)) {
+                return Err(format!(
+                    "Effect {} of production {} uses {}, which no premise binds",
+                    effect, production, &**var
+                ));
+            }
+        }
+
+        Ok(production)
+    }
+
+    /// Returns the salience of the production
+    pub fn salience(&self) -> i32 {
+        self.salience
+    }
+
+    // Returns the facts the production asserts, so they can be checked against declarations
+    pub(crate) fn asserted_facts(&self) -> impl Iterator<Item = &Fact> {
+        self.rhs.iter().filter_map(|effect| match effect {
+            Effect::Assert(fact) => Some(fact),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for Production {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rendered = String::from("(");
+        for (i, premise) in self.lhs.iter().enumerate() {
+            if i > 0 {
+                rendered.push(' ');
+            }
+            rendered.push_str(&premise.to_string());
+        }
+        rendered.push_str(") ->");
+        for effect in &self.rhs {
+            rendered.push_str(&format!(" {}", effect));
+        }
+        f.pad(&rendered)
+    }
+}
+
+impl Effect {
+    // Reads an effect from a fact of a production's conclusion
+    fn from(fact: Fact) -> Result<Effect, String> {
+        match &*fact.pred {
+            RETRACT => match fact.args.as_slice() {
+                [Term::Compound(pred, args)] => {
+                    Ok(Effect::Retract(Fact::new(pred.clone(), args.clone(), vec![])))
+                }
+                _ => Err(format!("Retract effect {} should name a single fact", fact)),
+            },
+            CALL => match fact.args.split_first() {
+                Some((Term::Symbol(name), args)) if !name.is_var() => {
+                    Ok(Effect::Call(name.clone(), args.to_vec()))
+                }
+                _ => Err(format!(
+                    "Call effect {} should start with the name of a callback",
+                    fact
+                )),
+            },
+            _ => Ok(Effect::Assert(fact)),
+        }
+    }
+
+    fn args(&self) -> &[Term] {
+        match self {
+            Effect::Assert(fact) | Effect::Retract(fact) => &fact.args,
+            Effect::Call(_, args) => args,
+        }
+    }
+
+    // Returns the effect with the bindings of a match applied
+    pub(crate) fn bind(&self, substitution: &Substitution) -> Effect {
+        match self {
+            Effect::Assert(fact) => Effect::Assert(substitution.apply(fact)),
+            Effect::Retract(fact) => Effect::Retract(substitution.apply(fact)),
+            Effect::Call(name, args) => Effect::Call(
+                name.clone(),
+                args.iter().map(|arg| substitution.apply_term(arg)).collect(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::Assert(fact) => write!(f, "{}", fact),
+            Effect::Retract(fact) => write!(f, "({} {})", RETRACT, fact),
+            Effect::Call(name, args) => {
+                write!(f, "({} {}", CALL, &**name)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// A function called by productions, with the arguments of their `(call name args...)` effects
+pub type Callback = Box<dyn FnMut(&[Term])>;
+
+// The callbacks registered with a knowledge base, by name
+#[derive(Default)]
+pub(crate) struct Callbacks(HashMap<Symbol, Callback>);
+
+impl Callbacks {
+    pub(crate) fn register(&mut self, name: Symbol, callback: Callback) {
+        self.0.insert(name, callback);
+    }
+
+    // Calls the named callback, and returns false if no callback has that name
+    pub(crate) fn call(&mut self, name: &Symbol, args: &[Term]) -> bool {
+        match self.0.get_mut(name) {
+            Some(callback) => {
+                callback(args);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
@@ -0,0 +1,471 @@
+use super::{aggregate, Fact, KnowledgeBase, PredicateIndex, QueryBinding};
+use subscription::Subscription;
+use temporal::Interval;
+use symbols::Symbol;
+use term::Term;
+use unify::Substitution;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::slice;
+use std::vec;
+
+// Returns the distinct variables of a pattern in the order they first appear
+pub(crate) fn pattern_variables(pattern: &Fact) -> Vec<Symbol> {
+    let mut variables: Vec<Symbol> = Vec::new();
+    for arg in &pattern.args {
+        arg.collect_variables(&mut variables);
+    }
+    variables
+}
+
+// Finds every substitution that makes all of the goals hold at once
+pub(crate) fn solve_goals(kb: &KnowledgeBase, goals: &[Fact]) -> Vec<Substitution> {
+    let mut solutions = Vec::new();
+    search(kb, goals, &Substitution::new(), &mut |solution| {
+        solutions.push(solution.clone());
+        true
+    });
+    solutions
+}
+
+// Finds one substitution that makes all of the goals hold at once, without looking for more
+pub(crate) fn first_solution(kb: &KnowledgeBase, goals: &[Fact]) -> Option<Substitution> {
+    let mut first = None;
+    search(kb, goals, &Substitution::new(), &mut |solution| {
+        first = Some(solution.clone());
+        false
+    });
+    first
+}
+
+// Solves the goals from left to right, so each goal sees the bindings of the ones before it.
+// Every complete solution is handed to the callback, which returns false to stop the search.
+// Returns false if the search was stopped.
+fn search(
+    kb: &KnowledgeBase,
+    goals: &[Fact],
+    substitution: &Substitution,
+    found: &mut dyn FnMut(&Substitution) -> bool,
+) -> bool {
+    let (first, rest) = match goals.split_first() {
+        Some(split) => split,
+        None => return found(substitution),
+    };
+
+    let goal = substitution.apply(first);
+    for binding in kb.query_iter(&goal) {
+        let mut extended = substitution.clone();
+        for (var, value) in binding {
+            extended.unify(&Term::Symbol(var), &value);
+        }
+        if !search(kb, rest, &extended, found) {
+            return false;
+        }
+    }
+    true
+}
+
+// The facts a query has to look at
+enum Candidates<'a> {
+    // Stored facts, taken straight from the knowledge base's index
+    Index(slice::Iter<'a, Rc<Fact>>),
+    // Instances computed by a builtin or an aggregate
+    Computed(vec::IntoIter<Fact>),
+}
+
+// Picks the smallest list of facts that can contain every answer to the pattern
+//
+// Each bound argument narrows the search to the facts sharing that argument, so the shortest of
+// those index columns is scanned. A bound argument that is missing from the index means no fact
+// can match. Patterns without bound arguments, including zero-arity ones, scan every fact of the
+// predicate.
+fn select_candidates<'a>(index: &'a PredicateIndex, pattern: &Fact) -> slice::Iter<'a, Rc<Fact>> {
+    let mut best: Option<&'a Vec<Rc<Fact>>> = None;
+
+    for (column, arg) in index.args.iter().zip(pattern.args.iter()) {
+        let key = match arg.index_key() {
+            Some(key) => key,
+            None => continue,
+        };
+
+        match column.get(key) {
+            Some(fact_list) => {
+                if best.is_none_or(|b| fact_list.len() < b.len()) {
+                    best = Some(fact_list);
+                }
+            }
+            None => return [].iter(),
+        }
+    }
+
+    best.unwrap_or(&index.facts).iter()
+}
+
+/// A lazy stream of the bindings that answer a query
+///
+/// Bindings are produced one at a time as the iterator is advanced, so a caller that only needs
+/// the first few answers never pays for the rest. Use `offset` and `limit` to page through the
+/// results of a broad query. Every binding lists the query's variables in the order they first
+/// appear in the pattern.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::KnowledgeBase;
+///
+/// let mut kb = KnowledgeBase::from_file("test/test.kb").unwrap();
+///
+/// if let Ok(fact) = kb.create_fact("fact: (isa ?x ?y);") {
+///     for binding in kb.query_iter(&fact).offset(1).limit(2) {
+///         // binding is a vector of (variable, value) pairs
+///     }
+/// }
+/// ```
+pub struct QueryIter<'a> {
+    kb: &'a KnowledgeBase,
+    pattern: &'a Fact,
+    variables: Vec<Symbol>,
+    candidates: Candidates<'a>,
+    window: Option<Interval>,
+    threshold: Option<f64>,
+    // The context whose facts are matched, along with those of its ancestors
+    scope: Option<Symbol>,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl<'a> QueryIter<'a> {
+    pub(crate) fn new(kb: &'a KnowledgeBase, pattern: &'a Fact) -> QueryIter<'a> {
+        let candidates = match kb.solve_computed(pattern) {
+            Some(solutions) => Candidates::Computed(solutions.into_iter()),
+            None => match kb.facts_map.get(&pattern.pred) {
+                Some(index) => Candidates::Index(select_candidates(index, pattern)),
+                None => Candidates::Index([].iter()),
+            },
+        };
+
+        let variables = if aggregate::is_aggregate(pattern) {
+            aggregate::visible_variables(pattern)
+        } else {
+            pattern_variables(pattern)
+        };
+
+        QueryIter {
+            kb,
+            pattern,
+            variables,
+            candidates,
+            window: None,
+            threshold: None,
+            scope: None,
+            offset: 0,
+            limit: None,
+        }
+    }
+
+    /// Only matches facts that are valid at the given point in time
+    pub fn at(self, time: i64) -> QueryIter<'a> {
+        self.during(Interval::at(time))
+    }
+
+    /// Only matches facts that are valid at some point during the given interval
+    pub fn during(mut self, interval: Interval) -> QueryIter<'a> {
+        self.window = Some(interval);
+        self
+    }
+
+    /// Only matches facts that the knowledge base has at least the given confidence in
+    pub fn min_confidence(mut self, threshold: f64) -> QueryIter<'a> {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    // Matches the facts of a named context instead of the shared context
+    pub(crate) fn in_context(mut self, context: Symbol) -> QueryIter<'a> {
+        self.scope = Some(context);
+        self
+    }
+
+    /// Skips the first `n` bindings of the query
+    pub fn offset(mut self, n: usize) -> QueryIter<'a> {
+        self.offset = n;
+        self
+    }
+
+    /// Stops the query after at most `n` bindings have been produced
+    pub fn limit(mut self, n: usize) -> QueryIter<'a> {
+        self.limit = Some(n);
+        self
+    }
+}
+
+impl<'a> Iterator for QueryIter<'a> {
+    type Item = QueryBinding;
+
+    fn next(&mut self) -> Option<QueryBinding> {
+        if self.limit == Some(0) {
+            return None;
+        }
+
+        loop {
+            let (kb, pattern, window, threshold) =
+                (self.kb, self.pattern, self.window, self.threshold);
+            let scope = &self.scope;
+            let bound = match self.candidates {
+                Candidates::Index(ref mut facts) => facts
+                    .find(|f| {
+                        kb.sees(scope, &f.context)
+                            && window.is_none_or(|w| f.valid.overlaps(&w))
+                            && threshold.is_none_or(|t| kb.fact_confidence(f).unwrap_or(0.0) >= t)
+                    })
+                    .map(|f| kb.try_bind(f, pattern)),
+                Candidates::Computed(ref mut facts) => facts.next().map(|f| kb.try_bind(&f, pattern)),
+            };
+
+            if let Ok(bindings) = bound? {
+                if self.offset > 0 {
+                    self.offset -= 1;
+                    continue;
+                }
+
+                if let Some(ref mut limit) = self.limit {
+                    *limit -= 1;
+                }
+
+                return Some(
+                    self.variables
+                        .iter()
+                        .map(|var| (var.clone(), bindings[var].clone()))
+                        .collect(),
+                );
+            }
+        }
+    }
+}
+
+/// The answers to a query laid out as a table
+///
+/// The header holds each variable of the query pattern once, in the order it first appears in the
+/// pattern, and every row holds one value per header column. Rows keep the order in which the
+/// knowledge base found them unless `sort` or `sort_by` is called.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::KnowledgeBase;
+///
+/// let mut kb = KnowledgeBase::from_file("test/test.kb").unwrap();
+///
+/// if let Ok(fact) = kb.create_fact("fact: (isa ?x ?y);") {
+///     let mut result = kb.query_result(&fact);
+///     result.sort();
+///     assert_eq!(result.variables(), vec!["?x", "?y"]);
+///     assert_eq!(result.rows()[0].get("?y").unwrap(), &"container");
+/// }
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryResult {
+    variables: Rc<Vec<Symbol>>,
+    rows: Vec<QueryRow>,
+}
+
+impl QueryResult {
+    pub(crate) fn from(iter: QueryIter) -> QueryResult {
+        let variables = Rc::new(iter.variables.clone());
+        let rows = iter
+            .map(|binding| QueryRow {
+                variables: variables.clone(),
+                values: binding.into_iter().map(|(_, value)| value).collect(),
+            })
+            .collect();
+
+        QueryResult { variables, rows }
+    }
+
+    /// Returns the variable names that head each column
+    pub fn variables(&self) -> Vec<&str> {
+        self.variables.iter().map(|var| &**var).collect()
+    }
+
+    /// Returns every row of the result
+    pub fn rows(&self) -> &[QueryRow] {
+        &self.rows
+    }
+
+    /// Returns the number of rows
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns true if the query found no answers
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Sorts rows by their values, comparing columns from left to right
+    ///
+    /// Symbols sort by name and before compound terms.
+    pub fn sort(&mut self) {
+        self.rows.sort_by(|a, b| a.values.cmp(&b.values));
+    }
+
+    /// Sorts rows by the value of the named variable, keeping the order of rows with equal values
+    ///
+    /// Unknown variable names leave the rows untouched.
+    pub fn sort_by(&mut self, name: &str) {
+        if let Some(column) = self.column(name) {
+            self.rows.sort_by(|a, b| a.values[column].cmp(&b.values[column]));
+        }
+    }
+
+    fn column(&self, name: &str) -> Option<usize> {
+        self.variables.iter().position(|var| &**var == name)
+    }
+}
+
+impl<'a> IntoIterator for &'a QueryResult {
+    type Item = &'a QueryRow;
+    type IntoIter = slice::Iter<'a, QueryRow>;
+
+    fn into_iter(self) -> slice::Iter<'a, QueryRow> {
+        self.rows.iter()
+    }
+}
+
+/// One answer to a query, with a value for every variable of the query pattern
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryRow {
+    variables: Rc<Vec<Symbol>>,
+    values: Vec<Term>,
+}
+
+impl QueryRow {
+    pub(crate) fn new(variables: Rc<Vec<Symbol>>, values: Vec<Term>) -> QueryRow {
+        QueryRow { variables, values }
+    }
+
+    /// Returns the value bound to the named variable, or None if the query has no such variable
+    pub fn get(&self, name: &str) -> Option<&Term> {
+        self.variables
+            .iter()
+            .position(|var| &**var == name)
+            .map(|column| &self.values[column])
+    }
+
+    /// Returns the values of the row, aligned with `QueryResult::variables`
+    pub fn values(&self) -> &[Term] {
+        &self.values
+    }
+}
+
+/// A row that was added to or removed from the answers of a watched query
+#[derive(Debug, PartialEq, Clone)]
+pub enum RowChange {
+    /// The row became an answer
+    Added(QueryRow),
+    /// The row stopped being an answer
+    Removed(QueryRow),
+}
+
+/// A query whose answers are kept up to date as facts are asserted, derived and retracted
+///
+/// A watch is created with `KnowledgeBase::watch_query`. The current answers are reported as
+/// added rows, and from then on every row that appears or disappears is reported once. A row
+/// found through several facts, such as `[a]` for `(on ?x ?)` when both `(on a b)` and
+/// `(on a c)` hold, is only removed once none of them hold.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::{KnowledgeBase, RowChange};
+///
+/// let mut kb = KnowledgeBase::new();
+///
+/// if let Ok(pattern) = kb.create_fact("fact: (sees ?who player);") {
+///     let watch = kb.watch_query(&pattern).unwrap();
+///     if let Ok(fact) = kb.create_fact("fact: (sees guard player);") {
+///         kb.assert(fact).unwrap();
+///     }
+///
+///     let changes = watch.drain_changes();
+///     assert_eq!(changes.len(), 1);
+///     if let RowChange::Added(ref row) = changes[0] {
+///         assert_eq!(row.get("?who").unwrap(), &"guard");
+///     }
+///     assert!(watch.drain_changes().is_empty());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct QueryWatch {
+    subscription: Subscription,
+    state: Rc<RefCell<WatchState>>,
+}
+
+impl QueryWatch {
+    pub(crate) fn new(subscription: Subscription, state: Rc<RefCell<WatchState>>) -> QueryWatch {
+        QueryWatch {
+            subscription,
+            state,
+        }
+    }
+
+    /// Returns the changes to the answers since the last call, in the order they happened
+    pub fn drain_changes(&self) -> Vec<RowChange> {
+        self.state.borrow_mut().changes.drain(..).collect()
+    }
+
+    /// Returns the number of answers the query currently has
+    pub fn len(&self) -> usize {
+        self.state.borrow().counts.len()
+    }
+
+    /// Returns true if the query currently has no answers
+    pub fn is_empty(&self) -> bool {
+        self.state.borrow().counts.is_empty()
+    }
+
+    pub(crate) fn subscription(&self) -> Subscription {
+        self.subscription
+    }
+}
+
+// The answers of a watched query, with how many facts produce each of them, and the changes
+// that were not drained yet
+#[derive(Debug)]
+pub(crate) struct WatchState {
+    variables: Rc<Vec<Symbol>>,
+    counts: HashMap<Vec<Term>, usize>,
+    changes: Vec<RowChange>,
+}
+
+impl WatchState {
+    pub(crate) fn new(variables: Vec<Symbol>) -> WatchState {
+        WatchState {
+            variables: Rc::new(variables),
+            counts: HashMap::new(),
+            changes: Vec::new(),
+        }
+    }
+
+    // Counts a fact producing the row as added or removed, reporting the row when it appears or
+    // disappears
+    pub(crate) fn record(&mut self, values: Vec<Term>, added: bool) {
+        if added {
+            let count = self.counts.entry(values.clone()).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                let row = QueryRow::new(self.variables.clone(), values);
+                self.changes.push(RowChange::Added(row));
+            }
+        } else if let Some(count) = self.counts.get_mut(&values) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&values);
+                let row = QueryRow::new(self.variables.clone(), values);
+                self.changes.push(RowChange::Removed(row));
+            }
+        }
+    }
+}
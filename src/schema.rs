@@ -0,0 +1,117 @@
+use super::{aggregate, Fact};
+use symbols::{Symbol, SymbolTable};
+use term::{Term, CONS, NIL};
+
+use std::fmt;
+
+/// The kind of value a predicate accepts in one argument position
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum Sort {
+    // Accepts any term
+    Any,
+    // A symbol that is not a number
+    Symbol,
+    // An integer
+    Number,
+    // A list, including the empty list and lists with a variable tail
+    List,
+    // A compound term that is not a list
+    Compound,
+    // A user defined sort, whose members are the x for which the fact (sort x) is known
+    Named(Symbol),
+}
+
+impl Sort {
+    pub(crate) fn from(name: &str, symbols: &SymbolTable) -> Sort {
+        match name {
+            "any" => Sort::Any,
+            "symbol" => Sort::Symbol,
+            "number" => Sort::Number,
+            "list" => Sort::List,
+            "compound" => Sort::Compound,
+            _ => Sort::Named(symbols.intern(name)),
+        }
+    }
+
+    // Checks the shape of a term against the sort. Variables could be bound to anything, and
+    // membership in named sorts depends on the facts of a knowledge base, so both are accepted
+    // here.
+    pub(crate) fn admits_shape(&self, term: &Term) -> bool {
+        if term.is_var() {
+            return true;
+        }
+
+        match (self, term) {
+            (Sort::Any, _) | (Sort::Named(_), _) => true,
+            (Sort::Symbol, Term::Symbol(s)) => &**s != NIL && s.parse::<i64>().is_err(),
+            (Sort::Number, Term::Symbol(s)) => s.parse::<i64>().is_ok(),
+            (Sort::List, Term::Symbol(s)) => &**s == NIL,
+            (Sort::List, Term::Compound(f, args)) => &**f == CONS && args.len() == 2,
+            (Sort::Compound, Term::Compound(f, _)) => &**f != CONS,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Sort::Any => f.pad("any"),
+            Sort::Symbol => f.pad("symbol"),
+            Sort::Number => f.pad("number"),
+            Sort::List => f.pad("list"),
+            Sort::Compound => f.pad("compound"),
+            Sort::Named(ref s) => f.pad(s),
+        }
+    }
+}
+
+/// The declared signature of a predicate: one sort per argument
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct Schema {
+    pub(crate) pred: Symbol,
+    pub(crate) sorts: Vec<Sort>,
+}
+
+impl Schema {
+    pub(crate) fn new(pred: Symbol, sorts: Vec<Sort>) -> Schema {
+        Schema { pred, sorts }
+    }
+
+    // Checks the arity of a fact and the shape of each of its arguments
+    pub(crate) fn check_shape(&self, fact: &Fact) -> Result<(), String> {
+        if fact.args.len() != self.sorts.len() {
+            return Err(format!(
+                "Predicate {} expects {} arguments but {} has {}",
+                &*self.pred,
+                self.sorts.len(),
+                fact,
+                fact.args.len()
+            ));
+        }
+
+        for (position, (sort, arg)) in self.sorts.iter().zip(fact.args.iter()).enumerate() {
+            if !sort.admits_shape(arg) {
+                return Err(format!(
+                    "Argument {} of {} should be a {} but is {}",
+                    position + 1,
+                    fact,
+                    sort,
+                    arg
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Returns the fact a schema applies to for a rule premise or head. Aggregates have no
+// schema of their own, but the pattern they range over does.
+pub(crate) fn checked_fact(fact: &Fact) -> Fact {
+    if aggregate::is_aggregate(fact) {
+        aggregate::pattern(fact)
+    } else {
+        fact.clone()
+    }
+}
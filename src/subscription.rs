@@ -0,0 +1,75 @@
+use super::{Fact, QueryBinding};
+use query;
+use symbols::Symbol;
+use term::Term;
+use unify::Substitution;
+
+use std::fmt;
+
+/// How a fact reported to a subscription changed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Change {
+    /// The fact was asserted
+    Asserted,
+    /// The fact was inferred by a rule
+    Derived,
+    /// The fact was retracted, directly or because the facts supporting it were
+    Retracted,
+}
+
+/// A handle to a subscription, used to unsubscribe
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Subscription(usize);
+
+/// A function called with each change to a fact matching a subscription's pattern, and the
+/// bindings of the pattern's variables in the order they first appear
+pub type Listener = Box<dyn FnMut(Change, &Fact, &[(Symbol, Term)])>;
+
+// The subscriptions of a knowledge base, in the order they were made
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    listeners: Vec<(Subscription, Fact, Listener)>,
+    next: usize,
+}
+
+impl Subscriptions {
+    pub(crate) fn add(&mut self, pattern: Fact, listener: Listener) -> Subscription {
+        let subscription = Subscription(self.next);
+        self.next += 1;
+        self.listeners.push((subscription, pattern, listener));
+        subscription
+    }
+
+    // Removes a subscription, and returns false if it was already removed
+    pub(crate) fn remove(&mut self, subscription: Subscription) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|(s, _, _)| *s != subscription);
+        self.listeners.len() != before
+    }
+
+    // Calls the listener of every subscription whose pattern matches the fact
+    pub(crate) fn notify(&mut self, change: Change, fact: &Fact) {
+        for (_, pattern, listener) in &mut self.listeners {
+            let mut substitution = Substitution::new();
+            if !substitution.unify_facts(pattern, fact) {
+                continue;
+            }
+            let bindings: QueryBinding = query::pattern_variables(pattern)
+                .into_iter()
+                .map(|var| {
+                    let value = substitution.apply_term(&Term::Symbol(var.clone()));
+                    (var, value)
+                })
+                .collect();
+            listener(change, fact, &bindings);
+        }
+    }
+}
+
+impl fmt::Debug for Subscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.listeners.iter().map(|(s, pattern, _)| (s, pattern)))
+            .finish()
+    }
+}
@@ -1,8 +1,10 @@
+use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 use std::rc::{Rc, Weak};
 use weak_table::WeakHashSet;
 
-#[derive(Clone, Debug, Hash)]
+#[derive(Clone, Debug)]
 pub struct Symbol(Rc<str>);
 
 impl Symbol {
@@ -11,6 +13,12 @@ impl Symbol {
     pub fn is_var(&self) -> bool {
         &self.0[..1] == "?"
     }
+
+    // A lone question mark is an anonymous variable, which matches anything without binding
+    #[inline]
+    pub fn is_anonymous(&self) -> bool {
+        &*self.0 == "?"
+    }
 }
 
 impl PartialEq for Symbol {
@@ -21,6 +29,14 @@ impl PartialEq for Symbol {
 
 impl Eq for Symbol {}
 
+// Interned symbols with equal pointers always have equal contents, so hashing the contents is
+// consistent with pointer equality
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
 impl Deref for Symbol {
     type Target = str;
     fn deref(&self) -> &str {
@@ -28,8 +44,10 @@ impl Deref for Symbol {
     }
 }
 
+// The table sits behind a RefCell so that read-only users of a knowledge base, such as queries
+// over builtins, can still create the symbols they produce
 #[derive(Debug, Default)]
-pub struct SymbolTable(WeakHashSet<Weak<str>>);
+pub struct SymbolTable(RefCell<WeakHashSet<Weak<str>>>);
 
 impl SymbolTable {
     pub fn new() -> Self {
@@ -38,12 +56,13 @@ impl SymbolTable {
 
     // Returns a reference counted pointer for a given string slice
     // This function assumes the string slice is a properly formatted argument
-    pub fn intern(&mut self, name: &str) -> Symbol {
-        if let Some(rc) = self.0.get(name) {
+    pub fn intern(&self, name: &str) -> Symbol {
+        let mut table = self.0.borrow_mut();
+        if let Some(rc) = table.get(name) {
             Symbol(rc)
         } else {
             let rc = Rc::<str>::from(name);
-            self.0.insert(Rc::clone(&rc));
+            table.insert(Rc::clone(&rc));
             Symbol(rc)
         }
     }
@@ -51,7 +70,7 @@ impl SymbolTable {
 
 #[test]
 fn interning() {
-    let mut tab = SymbolTable::new();
+    let tab = SymbolTable::new();
 
     let a0 = tab.intern("a");
     let a1 = tab.intern("a");
@@ -63,11 +82,11 @@ fn interning() {
 
 #[test]
 fn variable() {
-    let mut tab = SymbolTable::new();
+    let tab = SymbolTable::new();
 
     let a = tab.intern("ab");
     let b = tab.intern("?a");
 
-    assert_eq!(a.is_var(), false);
-    assert_eq!(b.is_var(), true)
+    assert!(!a.is_var());
+    assert!(b.is_var())
 }
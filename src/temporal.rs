@@ -0,0 +1,111 @@
+use std::cmp;
+use std::fmt;
+
+/// The span of time during which a fact is valid
+///
+/// An interval runs from its start up to, but not including, its end. Either side may be left
+/// unbounded, and facts are valid at all times unless they are given an interval, as in
+/// `fact: (at guard gate) from: 10 to: 40;`. A fact derived by a rule is valid for the
+/// intersection of the intervals of the facts that matched the rule's premises, and is not derived
+/// at all when that intersection is empty. Aggregates and constraints do not look at intervals.
+///
+///  # Example
+///
+/// ```
+/// use rust_kb::Interval;
+///
+/// let shift = Interval::new(Some(10), Some(40));
+/// let alarm = Interval::new(Some(30), None);
+///
+/// assert!(shift.contains(10) && !shift.contains(40));
+/// assert_eq!(shift.intersect(&alarm), Some(Interval::new(Some(30), Some(40))));
+/// assert_eq!(shift.intersect(&Interval::new(Some(40), Some(50))), None);
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Interval {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl Interval {
+    /// Creates an interval from its start and end, where None leaves that side unbounded
+    pub fn new(start: Option<i64>, end: Option<i64>) -> Interval {
+        Interval { start, end }
+    }
+
+    /// Returns the interval containing every point in time
+    pub fn always() -> Interval {
+        Interval::new(None, None)
+    }
+
+    /// Returns the interval containing only the given point in time
+    pub fn at(time: i64) -> Interval {
+        Interval::new(Some(time), Some(time.saturating_add(1)))
+    }
+
+    /// Returns the first point in time of the interval, or None if it is unbounded
+    pub fn start(&self) -> Option<i64> {
+        self.start
+    }
+
+    /// Returns the first point in time after the interval, or None if it is unbounded
+    pub fn end(&self) -> Option<i64> {
+        self.end
+    }
+
+    /// Returns true if the interval has no bounds
+    pub fn is_always(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+
+    /// Returns true if the interval contains no point in time
+    pub fn is_empty(&self) -> bool {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => start >= end,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the given point in time is in the interval
+    pub fn contains(&self, time: i64) -> bool {
+        self.start.is_none_or(|start| start <= time) && self.end.is_none_or(|end| time < end)
+    }
+
+    /// Returns the points in time both intervals contain, or None if there are none
+    pub fn intersect(&self, other: &Interval) -> Option<Interval> {
+        let start = match (self.start, other.start) {
+            (Some(a), Some(b)) => Some(cmp::max(a, b)),
+            (a, b) => a.or(b),
+        };
+        let end = match (self.end, other.end) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        let intersection = Interval::new(start, end);
+        if intersection.is_empty() {
+            None
+        } else {
+            Some(intersection)
+        }
+    }
+
+    /// Returns true if the intervals share at least one point in time
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.intersect(other).is_some()
+    }
+}
+
+// Written the way it is parsed after a fact, so an unbounded interval is empty text
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(start) = self.start {
+            parts.push(format!("from: {}", start));
+        }
+        if let Some(end) = self.end {
+            parts.push(format!("to: {}", end));
+        }
+        f.pad(&parts.join(" "))
+    }
+}
@@ -0,0 +1,210 @@
+use parser::ParsedTerm;
+use symbols::{Symbol, SymbolTable};
+
+use std::cmp::Ordering;
+use std::fmt;
+
+// Functor of a list cell and the symbol ending a proper list. Neither can be written as a name in
+// a kb file, so they never collide with user symbols.
+pub(crate) const CONS: &str = ".";
+pub(crate) const NIL: &str = "[]";
+
+/// An argument of a fact
+///
+/// A term is either a single symbol, which may be a variable such as `?x`, or a compound term made
+/// of a functor applied to further terms, such as `(location player)`. Compound terms can be nested
+/// to any depth. Lists such as `[a b c]` are compound terms built from list cells, so `[?h | ?t]`
+/// unifies with any non-empty list.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Term {
+    Symbol(Symbol),
+    Compound(Symbol, Vec<Term>),
+}
+
+impl Term {
+    /// Creates a term from the parser output and a given symbol table
+    pub(crate) fn from(pt: &ParsedTerm, symbols: &SymbolTable) -> Term {
+        match *pt {
+            ParsedTerm::Symbol(ref name) => Term::Symbol(symbols.intern(name)),
+            ParsedTerm::Compound(ref functor, ref args) => Term::Compound(
+                symbols.intern(functor),
+                args.iter().map(|arg| Term::from(arg, symbols)).collect(),
+            ),
+            ParsedTerm::List(ref items, ref tail) => {
+                let items = items.iter().map(|item| Term::from(item, symbols)).collect();
+                let tail = tail.as_ref().map(|tail| Term::from(tail, symbols));
+                Term::list(items, tail, symbols)
+            }
+        }
+    }
+
+    // Builds a list from its items, ending in the given tail or in the empty list
+    pub(crate) fn list(items: Vec<Term>, tail: Option<Term>, symbols: &SymbolTable) -> Term {
+        let cons = symbols.intern(CONS);
+        let mut list = tail.unwrap_or_else(|| Term::Symbol(symbols.intern(NIL)));
+        for item in items.into_iter().rev() {
+            list = Term::Compound(cons.clone(), vec![item, list]);
+        }
+        list
+    }
+
+    // Returns the items of a proper list, or None if the term is not a list ending in []
+    pub(crate) fn as_list(&self) -> Option<Vec<&Term>> {
+        let mut items = Vec::new();
+        let mut current = self;
+        loop {
+            match *current {
+                Term::Symbol(ref s) if &**s == NIL => return Some(items),
+                Term::Compound(ref functor, ref args) if &**functor == CONS && args.len() == 2 => {
+                    items.push(&args[0]);
+                    current = &args[1];
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns true if the term is a single variable
+    #[inline]
+    pub fn is_var(&self) -> bool {
+        match *self {
+            Term::Symbol(ref s) => s.is_var(),
+            Term::Compound(..) => false,
+        }
+    }
+
+    // Returns the variable if the term is a single variable
+    pub(crate) fn as_var(&self) -> Option<&Symbol> {
+        match *self {
+            Term::Symbol(ref s) if s.is_var() => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns true if a variable appears anywhere in the term
+    pub fn contains_variable(&self) -> bool {
+        match *self {
+            Term::Symbol(ref s) => s.is_var(),
+            Term::Compound(_, ref args) => args.iter().any(|arg| arg.contains_variable()),
+        }
+    }
+
+    // Checks whether the given variable appears anywhere in the term
+    pub(crate) fn occurs(&self, var: &Symbol) -> bool {
+        match *self {
+            Term::Symbol(ref s) => s == var,
+            Term::Compound(_, ref args) => args.iter().any(|arg| arg.occurs(var)),
+        }
+    }
+
+    // Appends every variable of the term that is not already in the list, in order of appearance
+    pub(crate) fn collect_variables(&self, variables: &mut Vec<Symbol>) {
+        match *self {
+            Term::Symbol(ref s) => {
+                if s.is_var() && !s.is_anonymous() && !variables.contains(s) {
+                    variables.push(s.clone());
+                }
+            }
+            Term::Compound(_, ref args) => {
+                for arg in args {
+                    arg.collect_variables(variables);
+                }
+            }
+        }
+    }
+
+    // Returns the symbol a term is indexed under: the symbol itself, or the outer functor of a
+    // compound term. Variables are not indexed.
+    pub(crate) fn index_key(&self) -> Option<&Symbol> {
+        match *self {
+            Term::Symbol(ref s) if s.is_var() => None,
+            Term::Symbol(ref s) => Some(s),
+            Term::Compound(ref functor, _) => Some(functor),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Term::Symbol(ref s) => f.pad(s),
+            Term::Compound(..) => {
+                let mut rendered = String::new();
+                write_term(self, &mut rendered);
+                f.pad(&rendered)
+            }
+        }
+    }
+}
+
+fn write_term(term: &Term, out: &mut String) {
+    match *term {
+        Term::Symbol(ref s) => out.push_str(s),
+        Term::Compound(ref functor, ref args) if &**functor == CONS && args.len() == 2 => {
+            out.push('[');
+            write_term(&args[0], out);
+            let mut tail = &args[1];
+            loop {
+                match *tail {
+                    Term::Symbol(ref s) if &**s == NIL => break,
+                    Term::Compound(ref functor, ref args)
+                        if &**functor == CONS && args.len() == 2 =>
+                    {
+                        out.push(' ');
+                        write_term(&args[0], out);
+                        tail = &args[1];
+                    }
+                    _ => {
+                        out.push_str(" | ");
+                        write_term(tail, out);
+                        break;
+                    }
+                }
+            }
+            out.push(']');
+        }
+        Term::Compound(ref functor, ref args) => {
+            out.push('(');
+            out.push_str(functor);
+            for arg in args {
+                out.push(' ');
+                write_term(arg, out);
+            }
+            out.push(')');
+        }
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Term {
+    fn eq(&self, other: &&'a str) -> bool {
+        match *self {
+            Term::Symbol(ref s) => &**s == *other,
+            Term::Compound(..) => {
+                let mut rendered = String::new();
+                write_term(self, &mut rendered);
+                rendered == *other
+            }
+        }
+    }
+}
+
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Term) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Symbols sort before compound terms, symbols sort by name, and compound terms sort by functor and
+// then by their arguments
+impl Ord for Term {
+    fn cmp(&self, other: &Term) -> Ordering {
+        match (self, other) {
+            (Term::Symbol(a), Term::Symbol(b)) => (**a).cmp(&**b),
+            (Term::Symbol(_), Term::Compound(..)) => Ordering::Less,
+            (Term::Compound(..), Term::Symbol(_)) => Ordering::Greater,
+            (Term::Compound(f1, a1), Term::Compound(f2, a2)) => {
+                (**f1).cmp(&**f2).then_with(|| a1.cmp(a2))
+            }
+        }
+    }
+}
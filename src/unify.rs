@@ -0,0 +1,313 @@
+use super::{Fact, Rule};
+use symbols::{Symbol, SymbolTable};
+use term::Term;
+
+use std::collections::HashMap;
+
+/// A set of variable bindings built up by unification
+///
+/// Variables may be bound to other variables or to terms containing variables, so bindings are
+/// followed until they reach a value or an unbound variable whenever they are read.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Substitution {
+    bindings: HashMap<Symbol, Term>,
+}
+
+impl Substitution {
+    pub(crate) fn new() -> Substitution {
+        Substitution::default()
+    }
+
+    pub(crate) fn from_map(bindings: &HashMap<Symbol, Term>) -> Substitution {
+        Substitution {
+            bindings: bindings.clone(),
+        }
+    }
+
+    // Follows the chain of bindings for a term until it ends in a value or a free variable
+    pub(crate) fn resolve<'a>(&'a self, term: &'a Term) -> &'a Term {
+        let mut current = term;
+        while let Some(next) = current.as_var().and_then(|var| self.bindings.get(var)) {
+            current = next;
+        }
+        current
+    }
+
+    // Replaces every bound variable in a term, however deeply nested, with what it is bound to
+    pub(crate) fn apply_term(&self, term: &Term) -> Term {
+        match *self.resolve(term) {
+            Term::Compound(ref functor, ref args) => Term::Compound(
+                functor.clone(),
+                args.iter().map(|arg| self.apply_term(arg)).collect(),
+            ),
+            ref resolved => resolved.clone(),
+        }
+    }
+
+    // Binds a variable to a term unless the variable occurs inside the term, which would make the
+    // binding infinite. Anonymous variables match anything and are never bound.
+    fn bind(&mut self, var: &Symbol, term: &Term) -> bool {
+        if var.is_anonymous() {
+            return true;
+        }
+        if self.apply_term(term).occurs(var) {
+            return false;
+        }
+        self.bindings.insert(var.clone(), term.clone());
+        true
+    }
+
+    // Extends the substitution so that both terms resolve to the same thing
+    pub(crate) fn unify(&mut self, a: &Term, b: &Term) -> bool {
+        let a = self.resolve(a).clone();
+        let b = self.resolve(b).clone();
+
+        match (&a, &b) {
+            _ if a == b => true,
+            (Term::Symbol(var), _) if var.is_var() => self.bind(var, &b),
+            (_, Term::Symbol(var)) if var.is_var() => self.bind(var, &a),
+            (Term::Compound(f1, args1), Term::Compound(f2, args2)) => {
+                f1 == f2
+                    && args1.len() == args2.len()
+                    && args1.iter().zip(args2.iter()).all(|(t1, t2)| self.unify(t1, t2))
+            }
+            _ => false,
+        }
+    }
+
+    // Unifies two facts argument by argument, leaving the substitution in an unspecified state
+    // when they do not unify
+    pub(crate) fn unify_facts(&mut self, f1: &Fact, f2: &Fact) -> bool {
+        f1.pred == f2.pred
+            && f1.args.len() == f2.args.len()
+            && f1.args.iter().zip(f2.args.iter()).all(|(a1, a2)| self.unify(a1, a2))
+    }
+
+    // Returns a substitution that has the effect of applying self and then other
+    pub(crate) fn compose(&self, other: &Substitution) -> Substitution {
+        let mut bindings: HashMap<Symbol, Term> = self.bindings
+            .keys()
+            .map(|var| {
+                let value = self.apply_term(&Term::Symbol(var.clone()));
+                (var.clone(), other.apply_term(&value))
+            })
+            .collect();
+
+        for var in other.bindings.keys() {
+            if !bindings.contains_key(var) {
+                let value = other.apply_term(&Term::Symbol(var.clone()));
+                bindings.insert(var.clone(), value);
+            }
+        }
+
+        bindings.retain(|var, value| value.as_var() != Some(var));
+        Substitution { bindings }
+    }
+
+    // Applies the substitution to every argument of a fact
+    pub(crate) fn apply_args(&self, fact: &Fact) -> Vec<Term> {
+        fact.args.iter().map(|arg| self.apply_term(arg)).collect()
+    }
+
+    // Applies the substitution to a fact, producing an unsupported copy
+    pub(crate) fn apply(&self, fact: &Fact) -> Fact {
+        Fact::new(fact.pred.clone(), self.apply_args(fact), vec![])
+    }
+
+    // Returns every bound variable mapped to what it finally resolves to
+    pub(crate) fn into_map(self) -> HashMap<Symbol, Term> {
+        self.bindings
+            .keys()
+            .map(|var| (var.clone(), self.apply_term(&Term::Symbol(var.clone()))))
+            .collect()
+    }
+}
+
+/// Generates variables that cannot collide with any variable a user can write
+///
+/// The parser only accepts alphanumeric variable names, so fresh variables are made unique with an
+/// underscore and a counter, e.g. `?x_12`.
+#[derive(Debug, Default)]
+pub(crate) struct VariableRenamer {
+    counter: usize,
+}
+
+impl VariableRenamer {
+    // Returns a substitution mapping every variable of the rule to a fresh one, so that a rule
+    // application never shares variables with the fact or rule it is unified against
+    pub(crate) fn rename_apart(&mut self, rule: &Rule, symbols: &SymbolTable) -> Substitution {
+        self.counter += 1;
+        let mut renaming = Substitution::new();
+
+        let mut variables = Vec::new();
+        for fact in rule.lhs.iter().chain(rule.rhs.iter()) {
+            for arg in &fact.args {
+                arg.collect_variables(&mut variables);
+            }
+        }
+
+        for var in variables {
+            let fresh = {
+                let base = var.split('_').next().unwrap_or(&var);
+                symbols.intern(&format!("{}_{}", base, self.counter))
+            };
+            renaming.bindings.insert(var, Term::Symbol(fresh));
+        }
+
+        renaming
+    }
+}
+
+// Checks whether two lists of facts are equal up to a consistent renaming of their variables
+pub(crate) fn is_variant(a: &[&Fact], b: &[&Fact]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut renaming = Renaming::default();
+    a.iter().zip(b.iter()).all(|(f1, f2)| {
+        f1.pred == f2.pred
+            && f1.args.len() == f2.args.len()
+            && f1.args.iter().zip(f2.args.iter()).all(|(t1, t2)| renaming.matches(t1, t2))
+    })
+}
+
+// A one-to-one correspondence between the variables of two terms
+#[derive(Default)]
+struct Renaming<'a> {
+    forward: HashMap<&'a Symbol, &'a Symbol>,
+    backward: HashMap<&'a Symbol, &'a Symbol>,
+}
+
+impl<'a> Renaming<'a> {
+    fn matches(&mut self, t1: &'a Term, t2: &'a Term) -> bool {
+        match (t1, t2) {
+            (Term::Symbol(a), Term::Symbol(b)) if a.is_var() && b.is_var() => {
+                *self.forward.entry(a).or_insert(b) == b && *self.backward.entry(b).or_insert(a) == a
+            }
+            (Term::Compound(f1, args1), Term::Compound(f2, args2)) => {
+                f1 == f2
+                    && args1.len() == args2.len()
+                    && args1.iter().zip(args2.iter()).all(|(a1, a2)| self.matches(a1, a2))
+            }
+            _ => t1 == t2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unify_tests {
+    use super::*;
+
+    fn fact(symbols: &SymbolTable, tokens: &[&str]) -> Fact {
+        let pred = symbols.intern(tokens[0]);
+        let args = tokens[1..]
+            .iter()
+            .map(|t| Term::Symbol(symbols.intern(t)))
+            .collect();
+        Fact::new(pred, args, vec![])
+    }
+
+    #[test]
+    fn unify_variables_on_both_sides() {
+        let symbols = SymbolTable::new();
+        let f1 = fact(&symbols, &["likes", "?x", "bob"]);
+        let f2 = fact(&symbols, &["likes", "?y", "?y"]);
+
+        let mut substitution = Substitution::new();
+        assert!(substitution.unify_facts(&f1, &f2));
+        assert_eq!(
+            substitution.apply(&f1),
+            fact(&symbols, &["likes", "bob", "bob"])
+        );
+        assert_eq!(
+            substitution.apply(&f2),
+            fact(&symbols, &["likes", "bob", "bob"])
+        );
+    }
+
+    #[test]
+    fn unify_repeated_variable_conflict() {
+        let symbols = SymbolTable::new();
+        let f1 = fact(&symbols, &["likes", "?x", "?x"]);
+        let f2 = fact(&symbols, &["likes", "alice", "bob"]);
+        let f3 = fact(&symbols, &["likes", "?y", "bob"]);
+
+        assert!(!Substitution::new().unify_facts(&f1, &f2));
+        assert!(!Substitution::new().unify_facts(&f2, &f1));
+        assert!(Substitution::new().unify_facts(&f1, &f3));
+    }
+
+    #[test]
+    fn compose_substitutions() {
+        let symbols = SymbolTable::new();
+        let f1 = fact(&symbols, &["on", "?a", "?b"]);
+        let f2 = fact(&symbols, &["on", "?c", "table"]);
+        let f3 = fact(&symbols, &["on", "box", "?d"]);
+
+        let mut first = Substitution::new();
+        assert!(first.unify_facts(&f1, &f2));
+        let mut second = Substitution::new();
+        assert!(second.unify_facts(&first.apply(&f1), &f3));
+
+        let composed = first.compose(&second);
+        assert_eq!(
+            composed.apply(&f1),
+            fact(&symbols, &["on", "box", "table"])
+        );
+        assert_eq!(composed.apply(&f1), second.apply(&first.apply(&f1)));
+    }
+
+    #[test]
+    fn rename_apart_rule() {
+        let symbols = SymbolTable::new();
+        let lhs = vec![fact(&symbols, &["inst", "?x", "?y"])];
+        let rhs = fact(&symbols, &["thing", "?x"]);
+        let rule = Rule::new(lhs, vec![rhs], vec![]);
+
+        let mut renamer = VariableRenamer::default();
+        let renaming = renamer.rename_apart(&rule, &symbols);
+        let renamed = renaming.apply(&rule.lhs[0]);
+
+        assert!(renamed.args.iter().all(|arg| arg.is_var()));
+        assert!(renamed.args.iter().all(|arg| !rule.lhs[0].args.contains(arg)));
+        assert!(is_variant(&[&rule.lhs[0]], &[&renamed]));
+        assert!(!is_variant(&[&rule.lhs[0]], &[&rule.rhs[0]]));
+    }
+
+    #[test]
+    fn unify_compound_terms() {
+        let symbols = SymbolTable::new();
+        let player = Term::Symbol(symbols.intern("player"));
+        let who = Term::Symbol(symbols.intern("?who"));
+        let location = symbols.intern("location");
+        let owner = symbols.intern("owner");
+
+        let ground = Term::Compound(location.clone(), vec![player.clone()]);
+        let pattern = Term::Compound(location.clone(), vec![who.clone()]);
+        let other = Term::Compound(owner, vec![who.clone()]);
+
+        let mut substitution = Substitution::new();
+        assert!(substitution.unify(&pattern, &ground));
+        assert_eq!(substitution.apply_term(&who), player);
+
+        assert!(!Substitution::new().unify(&other, &ground));
+    }
+
+    #[test]
+    fn occurs_check() {
+        let symbols = SymbolTable::new();
+        let x = Term::Symbol(symbols.intern("?x"));
+        let y = Term::Symbol(symbols.intern("?y"));
+        let father = symbols.intern("father");
+        let fx = Term::Compound(father.clone(), vec![x.clone()]);
+        let fy = Term::Compound(father, vec![y.clone()]);
+
+        assert!(!Substitution::new().unify(&x, &fx));
+
+        let mut substitution = Substitution::new();
+        assert!(substitution.unify(&x, &y));
+        assert!(!substitution.unify(&y, &fx));
+        assert!(substitution.unify(&fx, &fy));
+    }
+}